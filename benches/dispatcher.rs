@@ -0,0 +1,201 @@
+//! Dispatch throughput and start latency of the semaphore-bounded dispatcher,
+//! compared with the polling loop it replaced.
+//!
+//! Each run submits a burst of tasks of mixed priority that each take a
+//! millisecond of work, and waits until all of them have finished. Start latency
+//! is the time from submitting a task to handing it to a worker.
+
+use chrono::Utc;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use task_queue_system::models::{Task, TaskPriority};
+use task_queue_system::queue::{Dispatcher, PriorityQueue};
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+
+const TASKS: usize = 256;
+const WORKERS: usize = 16;
+const WORK: Duration = Duration::from_millis(1);
+
+struct Run {
+    elapsed: Duration,
+    p99_start_latency: Duration,
+}
+
+fn task(i: usize) -> Task {
+    let priority = match i % 4 {
+        0 => TaskPriority::Low,
+        1 => TaskPriority::Medium,
+        2 => TaskPriority::High,
+        _ => TaskPriority::Critical,
+    };
+    Task::new(format!("bench-{}", i), serde_json::json!({})).with_priority(priority)
+}
+
+// Time since the task was created, which is when it was submitted
+fn start_latency(task: &Task) -> Duration {
+    (Utc::now() - task.created_at).to_std().unwrap_or_default()
+}
+
+async fn collect(started: Instant, latencies: &mut mpsc::UnboundedReceiver<Duration>) -> Run {
+    let mut samples = Vec::with_capacity(TASKS);
+    while samples.len() < TASKS {
+        samples.push(latencies.recv().await.expect("workers stopped early"));
+    }
+    let elapsed = started.elapsed();
+
+    samples.sort();
+    let rank = ((samples.len() as f64) * 0.99).ceil() as usize;
+    Run {
+        elapsed,
+        p99_start_latency: samples[rank.saturating_sub(1)],
+    }
+}
+
+async fn run_dispatcher() -> Run {
+    let dispatcher = Dispatcher::new(WORKERS, TASKS);
+    let (latency_sender, mut latencies) = mpsc::unbounded_channel();
+
+    let running = dispatcher.clone();
+    let handle = tokio::spawn(async move {
+        running
+            .run(move |task, permit| {
+                let latency = start_latency(&task);
+                let latency_sender = latency_sender.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(WORK).await;
+                    let _ = latency_sender.send(latency);
+                    drop(permit);
+                });
+                async { Ok(()) }
+            })
+            .await
+    });
+
+    let started = Instant::now();
+    for i in 0..TASKS {
+        dispatcher.enqueue([task(i)]).unwrap();
+    }
+    let run = collect(started, &mut latencies).await;
+
+    handle.abort();
+    run
+}
+
+// The dispatch loop before the dispatcher: a crossbeam channel drained with a
+// blocking `recv`, and a 100 ms sleep whenever every worker is busy. It stops once
+// the channel is closed so every run starts from a clean runtime.
+async fn legacy_loop(
+    receiver: crossbeam_channel::Receiver<Task>,
+    pending: Arc<Mutex<PriorityQueue>>,
+    latencies: mpsc::UnboundedSender<Duration>,
+) {
+    let processing = Arc::new(AtomicUsize::new(0));
+    let dispatch = |task: Task| {
+        let latency = start_latency(&task);
+        let processing = processing.clone();
+        let latencies = latencies.clone();
+        processing.fetch_add(1, Ordering::SeqCst);
+        tokio::spawn(async move {
+            tokio::time::sleep(WORK).await;
+            let _ = latencies.send(latency);
+            processing.fetch_sub(1, Ordering::SeqCst);
+        });
+    };
+
+    loop {
+        while let Ok(task) = receiver.try_recv() {
+            dispatch(task);
+        }
+
+        if processing.load(Ordering::SeqCst) >= WORKERS {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            continue;
+        }
+
+        let task = pending.lock().pop();
+        match task {
+            Some(task) => dispatch(task),
+            None => match receiver.recv() {
+                Ok(task) => dispatch(task),
+                Err(_) => return,
+            },
+        }
+    }
+}
+
+async fn run_legacy() -> Run {
+    let (sender, receiver) = crossbeam_channel::bounded(WORKERS * 2);
+    let pending = Arc::new(Mutex::new(PriorityQueue::new()));
+    let (latency_sender, mut latencies) = mpsc::unbounded_channel();
+
+    tokio::spawn(legacy_loop(receiver, pending.clone(), latency_sender));
+
+    let started = Instant::now();
+    for i in 0..TASKS {
+        if let Err(crossbeam_channel::TrySendError::Full(task)) = sender.try_send(task(i)) {
+            pending.lock().push(task);
+        }
+    }
+    let run = collect(started, &mut latencies).await;
+
+    drop(sender);
+    run
+}
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(4)
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+fn dispatch_throughput(c: &mut Criterion) {
+    let rt = runtime();
+    let mut group = c.benchmark_group("dispatch_throughput");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(TASKS as u64));
+
+    group.bench_function(BenchmarkId::new("legacy_loop", TASKS), |b| {
+        b.to_async(&rt).iter(|| async { run_legacy().await.elapsed })
+    });
+    group.bench_function(BenchmarkId::new("semaphore", TASKS), |b| {
+        b.to_async(&rt).iter(|| async { run_dispatcher().await.elapsed })
+    });
+    group.finish();
+}
+
+// Reports the p99 start latency of each run as its time, so the estimates are
+// tail latencies rather than run durations
+fn dispatch_tail_latency(c: &mut Criterion) {
+    let rt = runtime();
+    let mut group = c.benchmark_group("dispatch_p99_start_latency");
+    group.sample_size(10);
+
+    group.bench_function(BenchmarkId::new("legacy_loop", TASKS), |b| {
+        b.to_async(&rt).iter_custom(|iters| async move {
+            let mut total = Duration::ZERO;
+            for _ in 0..iters {
+                total += run_legacy().await.p99_start_latency;
+            }
+            total
+        })
+    });
+    group.bench_function(BenchmarkId::new("semaphore", TASKS), |b| {
+        b.to_async(&rt).iter_custom(|iters| async move {
+            let mut total = Duration::ZERO;
+            for _ in 0..iters {
+                total += run_dispatcher().await.p99_start_latency;
+            }
+            total
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, dispatch_throughput, dispatch_tail_latency);
+criterion_main!(benches);
@@ -1,17 +1,40 @@
-use actix_web::{web, HttpResponse, Responder};
-use chrono::Utc;
+use actix_web::http::header;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
+use std::collections::BTreeMap;
+use tokio::sync::broadcast;
 
-use crate::error::AppResult;
-use crate::models::{CreateTaskRequest, Task, TaskPriority, TaskResponse, TaskState};
+use crate::error::{AppError, AppResult};
+use crate::models::{
+    BulkAction, CountMode, CreateTaskRequest, DurationPercentiles, Task, TaskCursor, TaskFilter,
+    TaskGroupCount, TaskLogEntry, TaskPriority, TaskResponse, TaskState, UpdateTaskRequest,
+};
 use crate::queue::TaskQueue;
+use crate::retention::RetentionPurger;
+use crate::telemetry::trace_id_from_traceparent;
+use tracing_actix_web::RequestId;
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 1000;
+// Largest JSON array accepted by the batch endpoint; NDJSON bodies are streamed
+const MAX_BATCH_PAYLOAD_BYTES: usize = 64 * 1024 * 1024;
+const MAX_BATCH_LINE_BYTES: usize = 2 * 1024 * 1024;
+const DEFAULT_STATS_WINDOWS: &str = "5m,1h,24h";
+const MAX_STATS_WINDOWS: usize = 10;
+const MAX_STATS_WINDOW_DAYS: i64 = 366;
+const LOG_FOLLOW_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
 
 // Task list response
 #[derive(Serialize)]
 struct TaskListResponse {
     tasks: Vec<TaskResponse>,
-    total: usize,
+    /// Cursor for the next page, absent on the last page
+    next_cursor: Option<String>,
+    /// Number of tasks matching the filter, only present when requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<i64>,
 }
 
 // Task status counts
@@ -26,6 +49,159 @@ struct TaskStatusCount {
     count: i64,
 }
 
+// Tag summary response
+#[derive(Serialize)]
+struct TagListResponse {
+    tags: Vec<TagSummary>,
+}
+
+#[derive(Serialize)]
+struct TagSummary {
+    tag: String,
+    total: i64,
+    states: BTreeMap<String, i64>,
+}
+
+// Result of a bulk operation on tasks
+#[derive(Serialize)]
+struct BulkOperationResponse {
+    affected: usize,
+    task_ids: Vec<String>,
+}
+
+// Bulk operation request body
+#[derive(Deserialize)]
+struct BulkActionRequest {
+    #[serde(default)]
+    filter: TaskFilter,
+    /// Only count the tasks that would be affected
+    #[serde(default)]
+    dry_run: bool,
+    /// New priority, required for `reprioritize`
+    priority: Option<TaskPriority>,
+    /// Reset the attempt counter when retrying
+    #[serde(default)]
+    reset_attempts: bool,
+    /// Include the affected task IDs in the response
+    #[serde(default = "default_true")]
+    return_ids: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+// Bulk operation response
+#[derive(Serialize)]
+struct BulkActionResponse {
+    action: BulkAction,
+    dry_run: bool,
+    affected: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    task_ids: Option<Vec<String>>,
+}
+
+// Manual retry request body
+#[derive(Deserialize, Default)]
+struct RetryTaskRequest {
+    #[serde(default)]
+    reset_attempts: bool,
+}
+
+// Reprioritization request body
+#[derive(Deserialize)]
+struct ReprioritizeRequest {
+    priority: TaskPriority,
+}
+
+// Batch submission response
+#[derive(Serialize)]
+struct BatchResponse {
+    accepted: usize,
+    rejected: usize,
+    results: Vec<BatchItemResult>,
+}
+
+// Last line of a batch submission streamed as NDJSON
+#[derive(Serialize)]
+struct BatchSummary {
+    accepted: usize,
+    rejected: usize,
+    /// Why the rest of the request wasn't read, if it wasn't
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct BatchItemResult {
+    index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    task_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+// Batch submission query parameters
+#[derive(Deserialize)]
+struct BatchParams {
+    /// `atomic` (default) rejects the whole batch on any invalid item,
+    /// `partial` accepts the valid items and reports the rest
+    mode: Option<String>,
+}
+
+// Task log page response
+#[derive(Serialize)]
+struct TaskLogResponse {
+    logs: Vec<TaskLogEntry>,
+    /// Pass as `after` to fetch the lines written since this page
+    next_after: Option<i64>,
+}
+
+// Task log query parameters
+#[derive(Deserialize)]
+struct TaskLogParams {
+    /// Only lines with a sequence number greater than this
+    after: Option<i64>,
+    limit: Option<u32>,
+    /// Stream lines as NDJSON until the task finishes
+    #[serde(default)]
+    follow: bool,
+}
+
+// Event stream query parameters
+#[derive(Deserialize)]
+struct EventStreamParams {
+    /// Only events about this task
+    task_id: Option<String>,
+}
+
+// Queue statistics response
+#[derive(Serialize)]
+struct StatsResponse {
+    counts: Vec<TaskGroupCount>,
+    windows: Vec<WindowStats>,
+}
+
+#[derive(Serialize)]
+struct WindowStats {
+    window: String,
+    window_seconds: i64,
+    /// Tasks completed within the window
+    completed: i64,
+    throughput_per_minute: f64,
+    wait_seconds: DurationPercentiles,
+    run_seconds: DurationPercentiles,
+}
+
+// Queue statistics query parameters
+#[derive(Deserialize)]
+struct StatsParams {
+    /// Comma-separated windows such as `5m,1h,24h`
+    windows: Option<String>,
+}
+
 // Task creation response
 #[derive(Serialize)]
 struct TaskCreationResponse {
@@ -38,39 +214,38 @@ struct TaskCreationResponse {
 struct TaskFilterParams {
     state: Option<String>,
     priority: Option<String>,
+    name: Option<String>,
+    /// Comma-separated list of tags that must all be present
+    tags: Option<String>,
+    created_after: Option<DateTime<Utc>>,
+    created_before: Option<DateTime<Utc>>,
+    error_contains: Option<String>,
+    tenant: Option<String>,
     limit: Option<u32>,
-    offset: Option<u32>,
+    cursor: Option<String>,
+    count: Option<String>,
+}
+
+// Trace ID stored on tasks created by this request: the caller's W3C trace
+// context if it sent one, otherwise the request ID assigned by the logger
+fn request_trace_id(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get("traceparent")
+        .and_then(|value| value.to_str().ok())
+        .and_then(trace_id_from_traceparent)
+        .or_else(|| req.extensions().get::<RequestId>().map(|id| id.to_string()))
 }
 
 // Create a new task
 async fn create_task(
     task_queue: web::Data<TaskQueue>,
+    http_req: HttpRequest,
     req: web::Json<CreateTaskRequest>,
 ) -> AppResult<impl Responder> {
-    let request = req.into_inner();
-    
-    // Create a new task
-    let mut task = Task::new(request.name, request.payload);
-    
-    // Set priority if provided
-    if let Some(priority) = request.priority {
-        task = task.with_priority(priority);
-    }
-    
-    // Set scheduled time if provided
-    if let Some(scheduled_at) = request.scheduled_at {
-        task = task.with_scheduled_time(scheduled_at);
-    }
-    
-    // Set max attempts if provided
-    if let Some(max_attempts) = request.max_attempts {
-        task = task.with_max_attempts(max_attempts);
-    }
-    
-    // Set tags if provided
-    if let Some(tags) = request.tags {
-        task = task.with_tags(tags);
-    }
+    let mut task = req
+        .into_inner()
+        .into_task(task_queue.config().max_task_timeout_seconds)?;
+    task.trace_id = request_trace_id(&http_req);
     
     // Submit task to the queue
     task_queue.submit_task(task.clone()).await?;
@@ -81,6 +256,331 @@ async fn create_task(
     }))
 }
 
+// Per-item results of a batch submission not yet sent, and the valid tasks not yet
+// submitted
+struct BatchIngest {
+    trace_id: Option<String>,
+    max_timeout_seconds: u64,
+    /// Whether results are sent as each chunk is submitted, rather than all at the end
+    streamed: bool,
+    results: Vec<BatchItemResult>,
+    tasks: Vec<Task>,
+    /// Indexes in `results` of the items in `tasks`
+    task_indexes: Vec<usize>,
+    items: usize,
+    accepted: usize,
+}
+
+impl BatchIngest {
+    fn new(trace_id: Option<String>, max_timeout_seconds: u64, streamed: bool) -> Self {
+        Self {
+            trace_id,
+            max_timeout_seconds,
+            streamed,
+            results: Vec::new(),
+            tasks: Vec::new(),
+            task_indexes: Vec::new(),
+            items: 0,
+            accepted: 0,
+        }
+    }
+
+    fn item_count(&self) -> usize {
+        self.items
+    }
+
+    fn rejected(&self) -> usize {
+        self.items - self.accepted - self.tasks.len()
+    }
+
+    /// Record the next item, keeping its task for the next submission if it is valid
+    fn push(&mut self, item: AppResult<CreateTaskRequest>) {
+        let index = self.items;
+        self.items += 1;
+        match item.and_then(|item| item.into_task(self.max_timeout_seconds)) {
+            Ok(mut task) => {
+                task.trace_id = self.trace_id.clone();
+                self.results.push(BatchItemResult {
+                    index,
+                    task_id: Some(task.id.clone()),
+                    status: Some(task.state.to_string()),
+                    error: None,
+                });
+                self.task_indexes.push(self.results.len() - 1);
+                self.tasks.push(task);
+            }
+            Err(e) => self.results.push(BatchItemResult {
+                index,
+                task_id: None,
+                status: None,
+                error: Some(e.to_string()),
+            }),
+        }
+    }
+
+    /// Store and queue the tasks collected since the last submission. Once some
+    /// tasks were accepted, a failure only rejects this chunk's items, since
+    /// the earlier ones are already stored. So does any failure while streaming,
+    /// since the response has already begun.
+    async fn submit(&mut self, task_queue: &TaskQueue) -> AppResult<()> {
+        if self.tasks.is_empty() {
+            return Ok(());
+        }
+
+        let tasks = std::mem::take(&mut self.tasks);
+        let indexes = std::mem::take(&mut self.task_indexes);
+        let count = tasks.len();
+        match task_queue.submit_tasks(tasks).await {
+            Ok(()) => {
+                self.accepted += count;
+                Ok(())
+            }
+            Err(e) if self.accepted > 0 || self.streamed => {
+                let error = e.to_string();
+                for index in indexes {
+                    let result = &mut self.results[index];
+                    result.task_id = None;
+                    result.status = None;
+                    result.error = Some(error.clone());
+                }
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Reject every item, e.g. because atomic mode found an invalid one
+    fn reject_all(self) -> HttpResponse {
+        let mut results = self.results;
+        for result in results.iter_mut() {
+            result.task_id = None;
+            result.status = None;
+        }
+        HttpResponse::BadRequest().json(BatchResponse {
+            accepted: 0,
+            rejected: results.len(),
+            results,
+        })
+    }
+
+    fn into_response(self) -> HttpResponse {
+        let rejected = self.rejected();
+        if self.accepted == 0 {
+            return self.reject_all();
+        }
+        HttpResponse::Created().json(BatchResponse {
+            accepted: self.accepted,
+            rejected,
+            results: self.results,
+        })
+    }
+}
+
+fn batch_size_error(max_batch_size: usize) -> AppError {
+    AppError::InvalidRequest(format!(
+        "Batch must contain between 1 and {} tasks", max_batch_size
+    ))
+}
+
+fn payload_error(e: actix_web::error::PayloadError) -> AppError {
+    AppError::InvalidRequest(format!("Failed to read request body: {}", e))
+}
+
+// Create many tasks at once from a JSON array or an NDJSON stream. Partial NDJSON
+// batches get an NDJSON stream of results back.
+async fn create_tasks_batch(
+    task_queue: web::Data<TaskQueue>,
+    req: HttpRequest,
+    query: web::Query<BatchParams>,
+    payload: web::Payload,
+) -> AppResult<impl Responder> {
+    let partial = match query.mode.as_deref() {
+        None | Some("atomic") => false,
+        Some("partial") => true,
+        Some(mode) => {
+            return Err(AppError::InvalidRequest(format!("Unknown batch mode: {}", mode)));
+        }
+    };
+
+    let is_ndjson = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.starts_with("application/x-ndjson"))
+        .unwrap_or(false);
+
+    let max_batch_size = task_queue.config().max_batch_size;
+    let streamed = is_ndjson && partial;
+    let mut ingest = BatchIngest::new(
+        request_trace_id(&req),
+        task_queue.config().max_task_timeout_seconds,
+        streamed,
+    );
+
+    if streamed {
+        return Ok(HttpResponse::Ok()
+            .content_type("application/x-ndjson")
+            .streaming(stream_ndjson_batch(task_queue, payload, ingest)));
+    }
+    if is_ndjson {
+        read_ndjson(&task_queue, payload, &mut ingest).await?;
+    } else {
+        let body = payload
+            .to_bytes_limited(MAX_BATCH_PAYLOAD_BYTES)
+            .await
+            .map_err(|_| {
+                AppError::InvalidRequest(format!(
+                    "Batch body is larger than {} bytes", MAX_BATCH_PAYLOAD_BYTES
+                ))
+            })?
+            .map_err(|e| AppError::InvalidRequest(format!("Failed to read request body: {}", e)))?;
+        let values: Vec<serde_json::Value> = serde_json::from_slice(&body)
+            .map_err(|e| AppError::InvalidRequest(format!("Expected a JSON array: {}", e)))?;
+        if values.len() > max_batch_size {
+            return Err(batch_size_error(max_batch_size));
+        }
+        for value in values {
+            ingest.push(serde_json::from_value(value).map_err(AppError::from));
+        }
+    }
+
+    if ingest.item_count() == 0 {
+        return Err(batch_size_error(max_batch_size));
+    }
+
+    // In atomic mode a single bad item rejects the whole batch
+    if !partial && ingest.rejected() > 0 {
+        return Ok(ingest.reject_all());
+    }
+
+    ingest.submit(&task_queue).await?;
+    Ok(ingest.into_response())
+}
+
+// Lines of an NDJSON body, read as it arrives
+struct NdjsonLines {
+    payload: web::Payload,
+    buffer: web::BytesMut,
+    finished: bool,
+}
+
+impl NdjsonLines {
+    fn new(payload: web::Payload) -> Self {
+        Self {
+            payload,
+            buffer: web::BytesMut::new(),
+            finished: false,
+        }
+    }
+
+    /// The next line that isn't blank, or `None` at the end of the body
+    async fn next(&mut self) -> AppResult<Option<web::BytesMut>> {
+        loop {
+            if let Some(end) = self.buffer.iter().position(|&byte| byte == b'\n') {
+                let line = self.buffer.split_to(end + 1);
+                if line.trim_ascii().is_empty() {
+                    continue;
+                }
+                return Ok(Some(line));
+            }
+
+            if self.buffer.len() > MAX_BATCH_LINE_BYTES {
+                return Err(AppError::InvalidRequest(format!(
+                    "Batch lines must be at most {} bytes", MAX_BATCH_LINE_BYTES
+                )));
+            }
+            if self.finished {
+                return Ok(None);
+            }
+            match self.payload.next().await {
+                Some(chunk) => self.buffer.extend_from_slice(&chunk.map_err(payload_error)?),
+                // Whatever follows the last newline is the final line
+                None => {
+                    self.finished = true;
+                    self.buffer.extend_from_slice(b"\n");
+                }
+            }
+        }
+    }
+}
+
+// Read an atomic NDJSON batch, which is submitted in one transaction and so may
+// hold at most `max_batch_size` items
+async fn read_ndjson(
+    task_queue: &TaskQueue,
+    payload: web::Payload,
+    ingest: &mut BatchIngest,
+) -> AppResult<()> {
+    let max_batch_size = task_queue.config().max_batch_size;
+    let mut lines = NdjsonLines::new(payload);
+
+    while let Some(line) = lines.next().await? {
+        if ingest.item_count() == max_batch_size {
+            return Err(batch_size_error(max_batch_size));
+        }
+        ingest.push(serde_json::from_slice(&line).map_err(AppError::from));
+    }
+
+    Ok(())
+}
+
+// Submit a partial NDJSON batch every `max_batch_size` items as they arrive, and
+// stream each chunk's results back once it is stored, so memory use depends on
+// the chunk size rather than the size of the request. The last line holds the
+// totals, and why reading stopped early if it did.
+fn stream_ndjson_batch(
+    task_queue: web::Data<TaskQueue>,
+    payload: web::Payload,
+    ingest: BatchIngest,
+) -> impl futures::Stream<Item = AppResult<web::Bytes>> {
+    let lines = NdjsonLines::new(payload);
+    futures::stream::try_unfold(Some((lines, ingest)), move |state| {
+        let task_queue = task_queue.clone();
+        async move {
+            let Some((mut lines, mut ingest)) = state else {
+                return Ok(None);
+            };
+
+            let max_batch_size = task_queue.config().max_batch_size;
+            let mut end = None;
+            while ingest.results.len() < max_batch_size {
+                match lines.next().await {
+                    Ok(Some(line)) => {
+                        ingest.push(serde_json::from_slice(&line).map_err(AppError::from))
+                    }
+                    Ok(None) => {
+                        end = Some(None);
+                        break;
+                    }
+                    Err(e) => {
+                        end = Some(Some(e.to_string()));
+                        break;
+                    }
+                }
+            }
+            ingest.submit(&task_queue).await?;
+
+            let mut chunk = Vec::new();
+            for result in ingest.results.drain(..) {
+                serde_json::to_writer(&mut chunk, &result)?;
+                chunk.push(b'\n');
+            }
+            let Some(error) = end else {
+                return Ok(Some((web::Bytes::from(chunk), Some((lines, ingest)))));
+            };
+
+            let summary = BatchSummary {
+                accepted: ingest.accepted,
+                rejected: ingest.rejected(),
+                error,
+            };
+            serde_json::to_writer(&mut chunk, &summary)?;
+            chunk.push(b'\n');
+            Ok(Some((web::Bytes::from(chunk), None)))
+        }
+    })
+}
+
 // Get a task by ID
 async fn get_task(
     task_queue: web::Data<TaskQueue>,
@@ -92,6 +592,112 @@ async fn get_task(
     Ok(HttpResponse::Ok().json(TaskResponse::from(task)))
 }
 
+// Read a task's log, one page at a time or followed as it is written
+async fn get_task_logs(
+    task_queue: web::Data<TaskQueue>,
+    path: web::Path<String>,
+    query: web::Query<TaskLogParams>,
+) -> AppResult<HttpResponse> {
+    let task_id = path.into_inner();
+    let query = query.into_inner();
+
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if limit == 0 || limit > MAX_PAGE_SIZE {
+        return Err(AppError::InvalidRequest(format!(
+            "limit must be between 1 and {}", MAX_PAGE_SIZE
+        )));
+    }
+
+    // Make sure the task exists so unknown IDs get a 404 rather than an empty log
+    task_queue.get_task(&task_id).await?;
+
+    if query.follow {
+        return Ok(HttpResponse::Ok()
+            .content_type("application/x-ndjson")
+            .streaming(follow_task_logs(task_queue, task_id, query.after, limit)));
+    }
+
+    let logs = task_queue.get_task_logs(&task_id, query.after, limit).await?;
+    let next_after = logs.last().map(|entry| entry.seq).or(query.after);
+
+    Ok(HttpResponse::Ok().json(TaskLogResponse { logs, next_after }))
+}
+
+// Stream a task's log lines as NDJSON, polling for new ones until the task finishes
+fn follow_task_logs(
+    task_queue: web::Data<TaskQueue>,
+    task_id: String,
+    after: Option<i64>,
+    limit: u32,
+) -> impl futures::Stream<Item = AppResult<web::Bytes>> {
+    futures::stream::try_unfold(after, move |mut after| {
+        let task_queue = task_queue.clone();
+        let task_id = task_id.clone();
+        async move {
+            loop {
+                // Check the state before reading so lines written just before
+                // the task finished are never missed
+                let finished = task_queue.get_task(&task_id).await?.state.is_finished();
+                let logs = task_queue.get_task_logs(&task_id, after, limit).await?;
+
+                if !logs.is_empty() {
+                    after = logs.last().map(|entry| entry.seq);
+                    let mut chunk = Vec::new();
+                    for entry in &logs {
+                        serde_json::to_writer(&mut chunk, entry)?;
+                        chunk.push(b'\n');
+                    }
+                    return Ok(Some((web::Bytes::from(chunk), after)));
+                }
+
+                if finished {
+                    return Ok(None);
+                }
+
+                tokio::time::sleep(LOG_FOLLOW_POLL_INTERVAL).await;
+            }
+        }
+    })
+}
+
+// Stream task events as server-sent events
+async fn stream_events(
+    task_queue: web::Data<TaskQueue>,
+    query: web::Query<EventStreamParams>,
+) -> HttpResponse {
+    let task_id = query.into_inner().task_id;
+    let receiver = task_queue.subscribe_events();
+
+    let events = futures::stream::unfold(receiver, move |mut receiver| {
+        let task_id = task_id.clone();
+        async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => {
+                        if task_id.as_deref().is_some_and(|id| id != event.task_id()) {
+                            continue;
+                        }
+                        let data = match serde_json::to_string(&event) {
+                            Ok(data) => data,
+                            Err(e) => return Some((Err(AppError::from(e)), receiver)),
+                        };
+                        let frame = format!("data: {}\n\n", data);
+                        return Some((Ok(web::Bytes::from(frame)), receiver));
+                    }
+                    // A slow client misses events rather than holding up the queue
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(events)
+}
+
 // Cancel a task
 async fn cancel_task(
     task_queue: web::Data<TaskQueue>,
@@ -106,24 +712,105 @@ async fn cancel_task(
     }))
 }
 
-// List tasks with optional filtering
-async fn list_tasks(
+// Requeue a failed or cancelled task
+async fn retry_task(
     task_queue: web::Data<TaskQueue>,
+    path: web::Path<String>,
+    req: Option<web::Json<RetryTaskRequest>>,
+) -> AppResult<impl Responder> {
+    let task_id = path.into_inner();
+    let reset_attempts = req.map(|req| req.into_inner()).unwrap_or_default().reset_attempts;
+    let task = task_queue.retry_task(&task_id, reset_attempts).await?;
+    
+    Ok(HttpResponse::Ok().json(TaskResponse::from(task)))
+}
+
+// Change a task that hasn't started running
+async fn update_task(
+    task_queue: web::Data<TaskQueue>,
+    path: web::Path<String>,
+    req: web::Json<UpdateTaskRequest>,
+) -> AppResult<impl Responder> {
+    let task_id = path.into_inner();
+    let task = task_queue.update_task(&task_id, req.into_inner()).await?;
+    
+    Ok(HttpResponse::Ok().json(TaskResponse::from(task)))
+}
+
+// Delete a task that isn't running
+async fn delete_task(
+    task_queue: web::Data<TaskQueue>,
+    path: web::Path<String>,
+) -> AppResult<impl Responder> {
+    let task_id = path.into_inner();
+    task_queue.delete_task(&task_id).await?;
+    
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// List tasks with optional filtering, one page at a time
+async fn list_tasks(
     db: web::Data<std::sync::Arc<dyn crate::storage::Database>>,
     query: web::Query<TaskFilterParams>,
 ) -> AppResult<impl Responder> {
-    let state_filter = query.state.as_deref();
-    let priority_filter = query.priority.as_deref();
-    let limit = query.limit;
-    let offset = query.offset;
+    let query = query.into_inner();
+
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if limit == 0 || limit > MAX_PAGE_SIZE {
+        return Err(AppError::InvalidRequest(format!(
+            "limit must be between 1 and {}", MAX_PAGE_SIZE
+        )));
+    }
+
+    let count_mode = match query.count.as_deref() {
+        Some(mode) => mode.parse()?,
+        None => CountMode::None,
+    };
+
+    let cursor = query.cursor.as_deref().map(TaskCursor::decode).transpose()?;
+
+    let filter = TaskFilter {
+        state: query.state,
+        priority: query.priority,
+        name: query.name,
+        created_after: query.created_after,
+        created_before: query.created_before,
+        error_contains: query.error_contains,
+        tenant: query.tenant,
+        tags: query
+            .tags
+            .as_deref()
+            .map(|tags| {
+                tags.split(',')
+                    .map(str::trim)
+                    .filter(|tag| !tag.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        ..TaskFilter::default()
+    };
     
-    let tasks = db.get_tasks(state_filter, priority_filter, limit, offset).await?;
-    let total = tasks.len();
+    // Fetch one extra row to find out whether there is another page
+    let mut tasks = db.get_tasks(&filter, cursor.as_ref(), Some(limit + 1)).await?;
+    let next_cursor = if tasks.len() > limit as usize {
+        tasks.truncate(limit as usize);
+        tasks.last().map(|task| TaskCursor::after(task).encode())
+    } else {
+        None
+    };
+
+    let total = match count_mode {
+        CountMode::None => None,
+        CountMode::Exact => Some(db.count_tasks(&filter).await?),
+        CountMode::Estimated => Some(db.estimate_task_count(&filter).await?),
+    };
     
     let task_responses: Vec<TaskResponse> = tasks.into_iter().map(TaskResponse::from).collect();
     
     Ok(HttpResponse::Ok().json(TaskListResponse {
         tasks: task_responses,
+        next_cursor,
         total,
     }))
 }
@@ -144,6 +831,186 @@ async fn get_task_counts(
     }))
 }
 
+// Apply the retention rules now instead of waiting for the next scheduled purge
+async fn purge_tasks(purger: web::Data<RetentionPurger>) -> AppResult<impl Responder> {
+    let report = purger.purge().await?;
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+// Parse a window such as `30s`, `5m`, `1h` or `7d`, up to `MAX_STATS_WINDOW_DAYS`
+fn parse_window(window: &str) -> AppResult<chrono::Duration> {
+    let invalid = || AppError::InvalidRequest(format!("Invalid window: {}", window));
+
+    let (split, _) = window.char_indices().last().ok_or_else(invalid)?;
+    let (amount, unit) = window.split_at(split);
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+    if amount <= 0 {
+        return Err(invalid());
+    }
+
+    let duration = match unit {
+        "s" => chrono::Duration::try_seconds(amount),
+        "m" => chrono::Duration::try_minutes(amount),
+        "h" => chrono::Duration::try_hours(amount),
+        "d" => chrono::Duration::try_days(amount),
+        _ => return Err(invalid()),
+    };
+    match duration {
+        Some(duration) if duration <= chrono::Duration::days(MAX_STATS_WINDOW_DAYS) => Ok(duration),
+        _ => Err(AppError::InvalidRequest(format!(
+            "Window {} is longer than {} days", window, MAX_STATS_WINDOW_DAYS
+        ))),
+    }
+}
+
+// Task counts by state, priority and name, with throughput and latency percentiles
+async fn get_stats(
+    db: web::Data<std::sync::Arc<dyn crate::storage::Database>>,
+    query: web::Query<StatsParams>,
+) -> AppResult<impl Responder> {
+    let windows: Vec<&str> = query
+        .windows
+        .as_deref()
+        .unwrap_or(DEFAULT_STATS_WINDOWS)
+        .split(',')
+        .map(str::trim)
+        .filter(|window| !window.is_empty())
+        .collect();
+    if windows.is_empty() || windows.len() > MAX_STATS_WINDOWS {
+        return Err(AppError::InvalidRequest(format!(
+            "Between 1 and {} windows can be requested", MAX_STATS_WINDOWS
+        )));
+    }
+
+    let counts = db.count_tasks_by_group().await?;
+
+    let now = Utc::now();
+    let mut window_stats = Vec::with_capacity(windows.len());
+    for window in windows {
+        let duration = parse_window(window)?;
+        let since = now.checked_sub_signed(duration).ok_or_else(|| {
+            AppError::InvalidRequest(format!("Window {} starts too far in the past", window))
+        })?;
+        let timing = db.task_timing_stats(since).await?;
+        let minutes = duration.num_seconds() as f64 / 60.0;
+
+        window_stats.push(WindowStats {
+            window: window.to_string(),
+            window_seconds: duration.num_seconds(),
+            completed: timing.run.count,
+            throughput_per_minute: timing.run.count as f64 / minutes,
+            wait_seconds: timing.wait,
+            run_seconds: timing.run,
+        });
+    }
+
+    Ok(HttpResponse::Ok().json(StatsResponse {
+        counts,
+        windows: window_stats,
+    }))
+}
+
+// List all tags with task counts per state
+async fn list_tags(
+    db: web::Data<std::sync::Arc<dyn crate::storage::Database>>,
+) -> AppResult<impl Responder> {
+    let counts = db.count_tasks_by_tag().await?;
+
+    let mut summaries: BTreeMap<String, TagSummary> = BTreeMap::new();
+    for count in counts {
+        let summary = summaries.entry(count.tag.clone()).or_insert_with(|| TagSummary {
+            tag: count.tag,
+            total: 0,
+            states: BTreeMap::new(),
+        });
+        summary.total += count.count;
+        *summary.states.entry(count.state).or_insert(0) += count.count;
+    }
+
+    Ok(HttpResponse::Ok().json(TagListResponse {
+        tags: summaries.into_values().collect(),
+    }))
+}
+
+// Cancel every pending or scheduled task with a tag
+async fn cancel_tasks_by_tag(
+    task_queue: web::Data<TaskQueue>,
+    path: web::Path<String>,
+) -> AppResult<impl Responder> {
+    let filter = TaskFilter::default().with_tag(path.into_inner());
+    let task_ids = task_queue.cancel_tasks(&filter).await?;
+
+    Ok(HttpResponse::Ok().json(BulkOperationResponse {
+        affected: task_ids.len(),
+        task_ids,
+    }))
+}
+
+// Change the priority of every not-yet-started task with a tag
+async fn reprioritize_tasks_by_tag(
+    task_queue: web::Data<TaskQueue>,
+    path: web::Path<String>,
+    req: web::Json<ReprioritizeRequest>,
+) -> AppResult<impl Responder> {
+    let filter = TaskFilter::default().with_tag(path.into_inner());
+    let task_ids = task_queue
+        .reprioritize_tasks(&filter, req.into_inner().priority)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(BulkOperationResponse {
+        affected: task_ids.len(),
+        task_ids,
+    }))
+}
+
+// Apply cancel, retry, reprioritize or delete to every task matching a filter
+async fn bulk_action(
+    task_queue: web::Data<TaskQueue>,
+    path: web::Path<BulkAction>,
+    req: web::Json<BulkActionRequest>,
+) -> AppResult<impl Responder> {
+    let action = path.into_inner();
+    let req = req.into_inner();
+
+    if req.dry_run {
+        let affected = task_queue.count_bulk_action_matches(action, &req.filter).await?;
+        return Ok(HttpResponse::Ok().json(BulkActionResponse {
+            action,
+            dry_run: true,
+            affected,
+            task_ids: None,
+        }));
+    }
+
+    // Guard against accidentally touching every task in the system
+    if req.filter.is_empty() {
+        return Err(AppError::InvalidRequest(format!(
+            "Refusing to {} every task; provide a filter", action
+        )));
+    }
+
+    let task_ids = task_queue
+        .apply_bulk_action(action, &req.filter, req.priority, req.reset_attempts)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(BulkActionResponse {
+        action,
+        dry_run: false,
+        affected: task_ids.len() as i64,
+        task_ids: req.return_ids.then_some(task_ids),
+    }))
+}
+
+// Prometheus metrics endpoint
+async fn metrics(task_queue: web::Data<TaskQueue>) -> AppResult<impl Responder> {
+    let body = task_queue.render_metrics().await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body))
+}
+
 // Health check endpoint
 async fn health_check() -> impl Responder {
     HttpResponse::Ok().json(serde_json::json!({
@@ -156,18 +1023,198 @@ async fn health_check() -> impl Responder {
 // Configure all routes
 pub fn configure_routes(cfg: &mut web::ServiceConfig) {
     cfg
+        .route("/metrics", web::get().to(metrics))
         .service(
             web::scope("/api/v1")
                 // Task management endpoints
                 .service(
                     web::scope("/tasks")
                         .route("", web::post().to(create_task))
+                        .route("/batch", web::post().to(create_tasks_batch))
                         .route("", web::get().to(list_tasks))
                         .route("/counts", web::get().to(get_task_counts))
                         .route("/{id}", web::get().to(get_task))
+                        .route("/{id}", web::patch().to(update_task))
+                        .route("/{id}", web::delete().to(delete_task))
                         .route("/{id}/cancel", web::post().to(cancel_task))
+                        .route("/{id}/retry", web::post().to(retry_task))
+                        .route("/{id}/logs", web::get().to(get_task_logs))
+                )
+                // Tag management endpoints
+                .service(
+                    web::scope("/tags")
+                        .route("", web::get().to(list_tags))
+                        .route("/{tag}/cancel", web::post().to(cancel_tasks_by_tag))
+                        .route("/{tag}/reprioritize", web::post().to(reprioritize_tasks_by_tag))
+                )
+                // Administrative bulk operations
+                .service(
+                    web::scope("/admin")
+                        .route("/tasks/{action}", web::post().to(bulk_action))
+                        .route("/purge", web::post().to(purge_tasks))
                 )
+                // Queue statistics
+                .route("/stats", web::get().to(get_stats))
+                // Live task events
+                .route("/events", web::get().to(stream_events))
                 // Health check
                 .route("/health", web::get().to(health_check))
         );
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::QueueConfig;
+    use crate::metrics::Metrics;
+    use crate::storage::{Database, MemoryDatabase};
+    use actix_web::{test, App};
+    use std::sync::Arc;
+
+    fn queue(db: Arc<dyn Database>, max_batch_size: usize) -> TaskQueue {
+        let config = QueueConfig {
+            max_batch_size,
+            ..QueueConfig::for_tests()
+        };
+        TaskQueue::new(db, config, Arc::new(Metrics::new().unwrap()))
+    }
+
+    fn ndjson_batch(mode: &str, lines: &[&str]) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri(&format!("/api/v1/tasks/batch?mode={}", mode))
+            .insert_header((header::CONTENT_TYPE, "application/x-ndjson"))
+            .set_payload(lines.join("\n"))
+    }
+
+    fn ndjson_lines(body: &[u8]) -> Vec<serde_json::Value> {
+        body.split(|&byte| byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn test_ndjson_batch_is_submitted_in_chunks() {
+        let db: Arc<dyn Database> = Arc::new(MemoryDatabase::new());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(queue(db.clone(), 2)))
+                .configure(configure_routes),
+        )
+        .await;
+
+        // More valid items than fit in one chunk, with an invalid one in between
+        let lines = [
+            r#"{"name": "a", "payload": {}}"#,
+            r#"{"name": "b", "payload": {}}"#,
+            "not json",
+            r#"{"name": "c", "payload": {}}"#,
+            "",
+            r#"{"name": "d", "payload": {}}"#,
+            r#"{"name": "e", "payload": {}}"#,
+        ];
+        let response = test::call_service(&app, ndjson_batch("partial", &lines).to_request()).await;
+        assert_eq!(response.status(), 200);
+
+        // A result per item, streamed as its chunk is stored, then the totals
+        let body = test::read_body(response).await;
+        let lines = ndjson_lines(&body);
+        assert_eq!(lines.len(), 7);
+        assert!(lines[2]["error"].is_string());
+        assert!(lines[5]["task_id"].is_string());
+        assert_eq!(lines[5]["index"], 5);
+        assert_eq!(lines[6], serde_json::json!({ "accepted": 5, "rejected": 1 }));
+        assert_eq!(db.count_tasks(&TaskFilter::default()).await.unwrap(), 5);
+    }
+
+    #[actix_web::test]
+    async fn test_ndjson_batch_reports_why_it_stopped_reading() {
+        let db: Arc<dyn Database> = Arc::new(MemoryDatabase::new());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(queue(db.clone(), 2)))
+                .configure(configure_routes),
+        )
+        .await;
+
+        let long = format!(r#"{{"name": "{}", "payload": {{}}}}"#, "x".repeat(MAX_BATCH_LINE_BYTES));
+        let lines = [r#"{"name": "a", "payload": {}}"#, long.as_str()];
+        let response = test::call_service(&app, ndjson_batch("partial", &lines).to_request()).await;
+        assert_eq!(response.status(), 200);
+
+        // The items read before the long line are still stored and reported
+        let body = test::read_body(response).await;
+        let lines = ndjson_lines(&body);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0]["task_id"].is_string());
+        assert_eq!(lines[1]["accepted"], 1);
+        assert!(lines[1]["error"].as_str().unwrap().contains("at most"));
+        assert_eq!(db.count_tasks(&TaskFilter::default()).await.unwrap(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_atomic_ndjson_batch_is_limited_to_one_chunk() {
+        let db: Arc<dyn Database> = Arc::new(MemoryDatabase::new());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(queue(db.clone(), 2)))
+                .configure(configure_routes),
+        )
+        .await;
+
+        let lines = [r#"{"name": "a", "payload": {}}"#; 3];
+        let response = test::call_service(&app, ndjson_batch("atomic", &lines).to_request()).await;
+        assert_eq!(response.status(), 400);
+        assert_eq!(db.count_tasks(&TaskFilter::default()).await.unwrap(), 0);
+
+        let lines = [r#"{"name": "a", "payload": {}}"#, "not json"];
+        let response = test::call_service(&app, ndjson_batch("atomic", &lines).to_request()).await;
+        assert_eq!(response.status(), 400);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["rejected"], 2);
+        assert_eq!(db.count_tasks(&TaskFilter::default()).await.unwrap(), 0);
+    }
+
+    #[actix_web::test]
+    async fn test_stats_rejects_windows_out_of_range() {
+        let db: Arc<dyn Database> = Arc::new(MemoryDatabase::new());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db))
+                .configure(configure_routes),
+        )
+        .await;
+
+        for windows in ["999999999999d", "9223372036854775807s", "400d", "0h", "5x"] {
+            let request = test::TestRequest::get()
+                .uri(&format!("/api/v1/stats?windows={}", windows))
+                .to_request();
+            let response = test::call_service(&app, request).await;
+            assert_eq!(response.status(), 400, "window {}", windows);
+        }
+
+        let request = test::TestRequest::get().uri("/api/v1/stats?windows=1h,366d").to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_task_timeouts_are_bounded() {
+        let db: Arc<dyn Database> = Arc::new(MemoryDatabase::new());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(queue(db.clone(), 10)))
+                .configure(configure_routes),
+        )
+        .await;
+
+        let max = QueueConfig::for_tests().max_task_timeout_seconds;
+        for (timeout, status) in [(0, 400), (max + 1, 400), (u64::MAX, 400), (max, 201)] {
+            let request = test::TestRequest::post()
+                .uri("/api/v1/tasks")
+                .set_json(serde_json::json!({ "name": "a", "payload": {}, "timeout_seconds": timeout }))
+                .to_request();
+            let response = test::call_service(&app, request).await;
+            assert_eq!(response.status(), status, "timeout {}", timeout);
+        }
+    }
+}
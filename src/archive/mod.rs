@@ -0,0 +1,275 @@
+use crate::error::{AppError, AppResult};
+use crate::models::{Task, TaskFilter};
+use crate::storage::Database;
+use chrono::{DateTime, Utc};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::info;
+
+const MANIFEST_FILE: &str = "manifest.jsonl";
+
+// Tasks restored per transaction
+const RESTORE_CHUNK_SIZE: usize = 500;
+
+/// One batch of tasks written to an archive file, as recorded in the manifest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Archive file name, relative to the archive directory
+    pub file: String,
+    pub tasks: usize,
+    pub oldest_updated_at: DateTime<Utc>,
+    pub newest_updated_at: DateTime<Utc>,
+    pub archived_at: DateTime<Utc>,
+}
+
+/// Writes finished tasks to gzip'd JSONL files before they are purged.
+///
+/// Each batch is appended to the current file as its own gzip member and
+/// synced to disk, together with a manifest entry, before `archive` returns,
+/// so callers can safely delete the rows afterwards. A new file is started
+/// once the current one holds `max_tasks_per_file` tasks.
+pub struct TaskArchiver {
+    directory: PathBuf,
+    max_tasks_per_file: usize,
+    current: Mutex<Option<CurrentFile>>,
+}
+
+struct CurrentFile {
+    name: String,
+    tasks: usize,
+}
+
+impl TaskArchiver {
+    pub fn new(directory: impl Into<PathBuf>, max_tasks_per_file: usize) -> AppResult<Self> {
+        if max_tasks_per_file == 0 {
+            return Err(AppError::ConfigError(
+                "archive max_tasks_per_file must be at least 1".to_string(),
+            ));
+        }
+
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+
+        Ok(Self {
+            directory,
+            max_tasks_per_file,
+            current: Mutex::new(None),
+        })
+    }
+
+    /// Append tasks to the archive, returning once they are durably on disk
+    pub async fn archive(self: &Arc<Self>, tasks: Vec<Task>) -> AppResult<()> {
+        if tasks.is_empty() {
+            return Ok(());
+        }
+
+        let archiver = self.clone();
+        tokio::task::spawn_blocking(move || archiver.write_batch(&tasks))
+            .await
+            .map_err(|e| AppError::InternalServerError(format!("Archive writer failed: {}", e)))?
+    }
+
+    fn write_batch(&self, tasks: &[Task]) -> AppResult<()> {
+        let mut current = self.current.lock();
+
+        if current
+            .as_ref()
+            .is_none_or(|file| file.tasks >= self.max_tasks_per_file)
+        {
+            let name = format!(
+                "tasks-{}-{}.jsonl.gz",
+                Utc::now().format("%Y%m%dT%H%M%S"),
+                &uuid::Uuid::new_v4().simple().to_string()[..8]
+            );
+            *current = Some(CurrentFile { name, tasks: 0 });
+        }
+        let file = current.as_mut().expect("archive file was just opened");
+
+        let written = self.append(file, tasks);
+        if written.is_err() {
+            // The file may end in a partial gzip member, which would stop a restore
+            // from reading any batch appended after it
+            *current = None;
+        }
+        written
+    }
+
+    fn append(&self, file: &mut CurrentFile, tasks: &[Task]) -> AppResult<()> {
+        let path = self.directory.join(&file.name);
+        let is_new = !path.exists();
+        let output = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        let mut encoder = GzEncoder::new(output, Compression::default());
+        for task in tasks {
+            serde_json::to_writer(&mut encoder, task)?;
+            encoder.write_all(b"\n")?;
+        }
+        encoder.finish()?.sync_all()?;
+        if is_new {
+            sync_directory(&self.directory)?;
+        }
+
+        let entry = ManifestEntry {
+            file: file.name.clone(),
+            tasks: tasks.len(),
+            oldest_updated_at: tasks.iter().map(|t| t.updated_at).min().unwrap_or_else(Utc::now),
+            newest_updated_at: tasks.iter().map(|t| t.updated_at).max().unwrap_or_else(Utc::now),
+            archived_at: Utc::now(),
+        };
+        let mut manifest = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.directory.join(MANIFEST_FILE))?;
+        serde_json::to_writer(&mut manifest, &entry)?;
+        manifest.write_all(b"\n")?;
+        manifest.sync_all()?;
+        file.tasks += tasks.len();
+
+        Ok(())
+    }
+}
+
+// Make a newly created file's directory entry durable
+#[cfg(unix)]
+fn sync_directory(directory: &Path) -> AppResult<()> {
+    File::open(directory)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_directory(_directory: &Path) -> AppResult<()> {
+    Ok(())
+}
+
+/// Load the tasks in an archive file back into the database. Tasks that
+/// already exist are left untouched. Returns the number of tasks restored.
+pub async fn restore_archive(db: &dyn Database, path: &Path) -> AppResult<usize> {
+    let reader = BufReader::new(MultiGzDecoder::new(File::open(path)?));
+
+    let mut restored = 0;
+    let mut chunk = Vec::with_capacity(RESTORE_CHUNK_SIZE);
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        chunk.push(serde_json::from_str::<Task>(&line)?);
+
+        if chunk.len() == RESTORE_CHUNK_SIZE {
+            restored += restore_chunk(db, std::mem::take(&mut chunk)).await?;
+        }
+    }
+    restored += restore_chunk(db, chunk).await?;
+
+    info!("Restored {} tasks from {}", restored, path.display());
+    Ok(restored)
+}
+
+async fn restore_chunk(db: &dyn Database, tasks: Vec<Task>) -> AppResult<usize> {
+    if tasks.is_empty() {
+        return Ok(0);
+    }
+
+    let filter = TaskFilter {
+        ids: tasks.iter().map(|task| task.id.clone()).collect(),
+        ..TaskFilter::default()
+    };
+    let existing: HashSet<String> = db
+        .get_tasks(&filter, None, Some(tasks.len() as u32))
+        .await?
+        .into_iter()
+        .map(|task| task.id)
+        .collect();
+
+    let missing: Vec<Task> = tasks
+        .into_iter()
+        .filter(|task| !existing.contains(&task.id))
+        .collect();
+    db.create_tasks(&missing).await?;
+
+    Ok(missing.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::create_database;
+
+    #[tokio::test]
+    async fn test_archive_round_trip() {
+        let dir = std::env::temp_dir().join(format!("taskqueue-archive-{}", uuid::Uuid::new_v4()));
+        let archiver = Arc::new(TaskArchiver::new(&dir, 3).unwrap());
+
+        let tasks: Vec<Task> = (0..5)
+            .map(|i| {
+                let mut task = Task::new(format!("task{}", i), serde_json::json!({ "i": i }));
+                task.mark_completed(Some(serde_json::json!("done")));
+                task
+            })
+            .collect();
+        archiver.archive(tasks[..2].to_vec()).await.unwrap();
+        archiver.archive(tasks[2..4].to_vec()).await.unwrap();
+        archiver.archive(tasks[4..].to_vec()).await.unwrap();
+
+        // Two batches share the first file, the third rotates to a new one
+        let manifest: Vec<ManifestEntry> = fs::read_to_string(dir.join(MANIFEST_FILE))
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(manifest.len(), 3);
+        assert_eq!(manifest[0].file, manifest[1].file);
+        assert_ne!(manifest[1].file, manifest[2].file);
+
+        let path = dir.join(format!("taskqueue-{}.db", uuid::Uuid::new_v4()));
+        let db = create_database(&format!("sqlite:{}?mode=rwc", path.display()))
+            .await
+            .unwrap();
+        db.setup().await.unwrap();
+        db.create_task(&tasks[0]).await.unwrap();
+
+        let restored = restore_archive(db.as_ref(), &dir.join(&manifest[0].file)).await.unwrap();
+        assert_eq!(restored, 3);
+        let task = db.get_task(&tasks[3].id).await.unwrap();
+        assert_eq!(task.payload, serde_json::json!({ "i": 3 }));
+        assert_eq!(task.result, Some(serde_json::json!("done")));
+    }
+
+    #[tokio::test]
+    async fn test_failed_write_starts_a_new_file() {
+        let dir = std::env::temp_dir().join(format!("taskqueue-archive-{}", uuid::Uuid::new_v4()));
+        let archiver = Arc::new(TaskArchiver::new(&dir, 10).unwrap());
+        let task = |i: usize| Task::new(format!("task{}", i), serde_json::json!({ "i": i }));
+        let manifest = || -> Vec<ManifestEntry> {
+            fs::read_to_string(dir.join(MANIFEST_FILE))
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        };
+
+        archiver.archive(vec![task(0)]).await.unwrap();
+        let first = manifest()[0].file.clone();
+
+        // The file can no longer be written, so the next batch fails
+        fs::remove_file(dir.join(&first)).unwrap();
+        fs::create_dir(dir.join(&first)).unwrap();
+        assert!(archiver.archive(vec![task(1)]).await.is_err());
+
+        // and the one after it goes to a file of its own
+        archiver.archive(vec![task(2)]).await.unwrap();
+        let manifest = manifest();
+        assert_eq!(manifest.len(), 2);
+        assert_ne!(manifest[1].file, first);
+        assert_eq!(manifest[1].tasks, 1);
+        assert_eq!(archiver.current.lock().as_ref().unwrap().tasks, 1);
+    }
+}
@@ -1,6 +1,6 @@
+use actix_web::http::header;
 use actix_web::{HttpResponse, ResponseError};
 use serde::Serialize;
-use std::fmt;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -8,15 +8,24 @@ pub enum AppError {
     #[error("Database error: {0}")]
     DatabaseError(#[from] sqlx::Error),
 
+    #[error("Storage error: {0}")]
+    StorageError(#[source] Box<redb::Error>),
+
     #[error("Task not found with ID: {0}")]
     TaskNotFound(String),
 
     #[error("Task already exists with ID: {0}")]
     TaskAlreadyExists(String),
 
+    #[error("Task is running and can't be modified: {0}")]
+    TaskRunning(String),
+
     #[error("Queue is full")]
     QueueFull,
 
+    #[error("Too many pending tasks: {message}")]
+    TooManyPendingTasks { message: String, retry_after_seconds: u64 },
+
     #[error("Worker is busy")]
     WorkerBusy,
 
@@ -26,12 +35,21 @@ pub enum AppError {
     #[error("Task execution timed out after {0} seconds")]
     TaskTimeout(u64),
 
+    #[error("Task execution aborted after {0} seconds without wrapping up")]
+    TaskAborted(u64),
+
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
     #[error("Configuration error: {0}")]
     ConfigError(String),
 
     #[error("Serialization error: {0}")]
     SerializationError(#[from] serde_json::Error),
 
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+
     #[error("Internal server error: {0}")]
     InternalServerError(String),
 }
@@ -51,7 +69,11 @@ impl ResponseError for AppError {
             message: self.to_string(),
         };
         
-        HttpResponse::build(status).json(error_response)
+        let mut response = HttpResponse::build(status);
+        if let AppError::TooManyPendingTasks { retry_after_seconds, .. } = self {
+            response.insert_header((header::RETRY_AFTER, retry_after_seconds.to_string()));
+        }
+        response.json(error_response)
     }
 
     fn status_code(&self) -> actix_web::http::StatusCode {
@@ -60,10 +82,14 @@ impl ResponseError for AppError {
         match self {
             AppError::TaskNotFound(_) => StatusCode::NOT_FOUND,
             AppError::TaskAlreadyExists(_) => StatusCode::CONFLICT,
+            AppError::TaskRunning(_) => StatusCode::CONFLICT,
             AppError::QueueFull => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::TooManyPendingTasks { .. } => StatusCode::TOO_MANY_REQUESTS,
             AppError::WorkerBusy => StatusCode::SERVICE_UNAVAILABLE,
             AppError::InvalidStateTransition { .. } => StatusCode::BAD_REQUEST,
             AppError::TaskTimeout(_) => StatusCode::REQUEST_TIMEOUT,
+            AppError::TaskAborted(_) => StatusCode::REQUEST_TIMEOUT,
+            AppError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
             AppError::ConfigError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
@@ -0,0 +1,10 @@
+pub mod api;
+pub mod archive;
+pub mod config;
+pub mod error;
+pub mod metrics;
+pub mod models;
+pub mod queue;
+pub mod retention;
+pub mod storage;
+pub mod telemetry;
@@ -1,32 +1,34 @@
-mod api;
-mod config;
-mod error;
-mod models;
-mod queue;
-mod storage;
-
-use actix_web::{middleware, web, App, HttpServer};
-use log::{error, info};
+use actix_web::{web, App, HttpServer};
 use std::sync::Arc;
+use std::time::Duration;
+use task_queue_system::{api, archive, config, error, metrics, queue, retention, storage, telemetry};
 use tokio::signal;
+use tracing::{error, info};
+use tracing_actix_web::TracingLogger;
+
+// Exit status when shutdown had to interrupt running tasks
+const EXIT_UNCLEAN_SHUTDOWN: i32 = 2;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     // Initialize environment variables from .env file
     dotenv::dotenv().ok();
     
-    // Initialize logger
-    env_logger::init();
-
-    // Load application configuration
+    // Load application configuration; logging isn't set up yet, so report to stderr
     let app_config = match config::AppConfig::from_env() {
         Ok(config) => config,
         Err(e) => {
-            error!("Failed to load configuration: {}", e);
+            eprintln!("Failed to load configuration: {}", e);
             std::process::exit(1);
         }
     };
 
+    // Initialize logging
+    if let Err(e) = telemetry::init(&app_config.logging) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
     // Wait for database to be ready with retries
     info!("Connecting to database at {}", app_config.get_database_url());
     let db = match wait_for_database(&app_config).await {
@@ -38,38 +40,78 @@ async fn main() -> std::io::Result<()> {
         }
     };
 
-    // Set up database tables, indexes, etc.
-    if let Err(e) = db.setup().await {
+    // Record the latency of every database call
+    let metrics = match metrics::Metrics::new() {
+        Ok(metrics) => Arc::new(metrics),
+        Err(e) => {
+            error!("Failed to initialize metrics: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let db: Arc<dyn storage::Database> =
+        Arc::new(storage::InstrumentedDatabase::new(db, metrics.clone()));
+
+    // One-off commands run against the configured database instead of starting the server
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(command) = args.first() {
+        if let Err(e) = run_command(db.as_ref(), &app_config, command, &args[1..]).await {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // Bring the schema up to date, or make sure it already is
+    if let Err(e) = prepare_schema(db.as_ref(), &app_config).await {
         error!("Failed to set up database: {}", e);
         std::process::exit(1);
     }
 
+    // Purge finished tasks according to the retention rules
+    let purger = match retention::RetentionPurger::new(db.clone(), app_config.retention.clone(), metrics.clone()) {
+        Ok(purger) => Arc::new(purger),
+        Err(e) => {
+            error!("Invalid retention configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+    purger.start();
+    let purger = web::Data::from(purger);
+
     // Create shared task queue instance
-    let task_queue = web::Data::new(queue::TaskQueue::new(db.clone(), app_config.queue.clone()));
+    let task_queue = web::Data::new(queue::TaskQueue::new(db.clone(), app_config.queue.clone(), metrics));
     
     // Start the task queue in a separate task
     let queue_handle = task_queue.clone();
-    let queue_task = actix_web::rt::spawn(async move {
+    actix_web::rt::spawn(async move {
         if let Err(e) = queue_handle.start().await {
             error!("Task queue error: {}", e);
             std::process::exit(1);
         }
     });
 
+    // Kept for the shutdown sequence once the server has stopped
+    let queue_shutdown = task_queue.clone();
+    let db_shutdown = db.clone();
+    let grace_period = Duration::from_secs(app_config.queue.shutdown_grace_period_seconds);
+
     // Start HTTP server with graceful shutdown
     info!("Starting server at {}:{}", app_config.server.host, app_config.server.port);
     
     let server = HttpServer::new(move || {
         App::new()
-            // Enable logger middleware
-            .wrap(middleware::Logger::default())
+            // Open a span per request and log its outcome
+            .wrap(TracingLogger::default())
             // Register shared data
             .app_data(task_queue.clone())
+            .app_data(purger.clone())
             .app_data(web::Data::new(db.clone()))
             // Configure routes
             .configure(api::configure_routes)
     })
     .bind(format!("{}:{}", app_config.server.host, app_config.server.port))?
+    // Signals are handled below so the queue drains as part of shutdown
+    .disable_signals()
     .run();
 
     // Get the server handle for shutdown
@@ -77,24 +119,117 @@ async fn main() -> std::io::Result<()> {
 
     // Set up signal handling for graceful shutdown
     let shutdown_future = async {
-        match signal::ctrl_c().await {
-            Ok(()) => {
-                info!("Shutdown signal received, initiating graceful shutdown...");
-                // Stop the HTTP server gracefully
-                server_handle.stop(true).await;
-                info!("HTTP server stopped gracefully");
-            }
-            Err(err) => {
-                error!("Error setting up shutdown handler: {}", err);
-            }
+        if let Err(err) = shutdown_signal().await {
+            error!("Error setting up shutdown handler: {}", err);
+            return true;
         }
+        info!("Shutdown signal received, initiating graceful shutdown...");
+
+        // Stop the HTTP server gracefully
+        server_handle.stop(true).await;
+        info!("HTTP server stopped gracefully");
+
+        // Stop claiming tasks and let running ones finish
+        let clean = queue_shutdown.shutdown(grace_period).await;
+
+        // Wait for outstanding writes before exiting
+        db_shutdown.close().await;
+        clean
     };
 
     // Run both the server and the shutdown handler
-    let (result, _) = tokio::join!(server, shutdown_future);
-    
+    let (result, clean) = tokio::join!(server, shutdown_future);
+    result?;
+
+    if !clean {
+        error!("Application stopped with tasks interrupted");
+        std::process::exit(EXIT_UNCLEAN_SHUTDOWN);
+    }
     info!("Application stopped");
-    result
+    Ok(())
+}
+
+// Resolve on Ctrl-C, or on SIGTERM where there is one
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+
+    #[cfg(not(unix))]
+    signal::ctrl_c().await
+}
+
+// Run a command given on the command line
+async fn run_command(
+    db: &dyn storage::Database,
+    config: &config::AppConfig,
+    command: &str,
+    args: &[String],
+) -> error::AppResult<()> {
+    match command {
+        "migrate" => run_migrate(db, args).await,
+        "restore-archive" => {
+            if args.is_empty() {
+                return Err(error::AppError::InvalidRequest(
+                    "Usage: restore-archive <archive file>...".to_string(),
+                ));
+            }
+            prepare_schema(db, config).await?;
+            for path in args {
+                archive::restore_archive(db, std::path::Path::new(path)).await?;
+            }
+            Ok(())
+        }
+        _ => Err(error::AppError::InvalidRequest(format!("Unknown command: {}", command))),
+    }
+}
+
+// migrate status | migrate up [--dry-run]
+async fn run_migrate(db: &dyn storage::Database, args: &[String]) -> error::AppResult<()> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["status"] => {
+            for migration in storage::migrate::status(db).await? {
+                let applied = migration
+                    .applied_at
+                    .map_or_else(|| "pending".to_string(), |at| format!("applied {}", at.to_rfc3339()));
+                println!("{:>4}  {:<40} {}", migration.version, migration.description, applied);
+            }
+            Ok(())
+        }
+        ["up"] | ["up", "--dry-run"] => {
+            let dry_run = args.len() == 2;
+            let migrations = storage::migrate::migrate(db, dry_run).await?;
+            if migrations.is_empty() {
+                println!("Schema is up to date");
+            }
+            for migration in migrations {
+                let verb = if dry_run { "Would apply" } else { "Applied" };
+                println!("{} {:>4}  {}", verb, migration.version, migration.description);
+                if dry_run {
+                    println!("{}", migration.sql);
+                }
+            }
+            Ok(())
+        }
+        _ => Err(error::AppError::InvalidRequest(
+            "Usage: migrate status | migrate up [--dry-run]".to_string(),
+        )),
+    }
+}
+
+// Apply pending migrations if allowed, otherwise refuse to run against an out of date schema
+async fn prepare_schema(db: &dyn storage::Database, config: &config::AppConfig) -> error::AppResult<()> {
+    if config.database.auto_migrate {
+        db.setup().await
+    } else {
+        storage::migrate::verify(db).await
+    }
 }
 
 // Wait for database to be ready with retries
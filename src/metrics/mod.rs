@@ -0,0 +1,261 @@
+use crate::error::{AppError, AppResult};
+use crate::models::Task;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::time::Duration;
+
+// Buckets for task execution and queue wait times, in seconds
+const TASK_DURATION_BUCKETS: &[f64] = &[
+    0.01, 0.05, 0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 900.0, 3600.0,
+];
+
+// Buckets for database query latency, in seconds
+const QUERY_DURATION_BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0,
+];
+
+/// Prometheus metrics for the queue, its workers and storage
+pub struct Metrics {
+    registry: Registry,
+    /// Tasks accepted for execution, by name and priority
+    pub tasks_submitted: IntCounterVec,
+    /// Tasks that finished successfully, by name and priority
+    pub tasks_completed: IntCounterVec,
+    /// Failed task attempts, by name and priority
+    pub tasks_failed: IntCounterVec,
+    /// Tasks cancelled before running, by name and priority
+    pub tasks_cancelled: IntCounterVec,
+    /// Tasks that expired before they could run, by name and priority
+    pub tasks_expired: IntCounterVec,
+    /// Task executions that ran past their timeout, by name and whether they wrapped
+    /// up in time (`soft`) or were aborted (`hard`)
+    pub task_timeouts: IntCounterVec,
+    /// Tasks that completed after their deadline, by name and priority
+    pub deadline_misses: IntCounterVec,
+    /// Time spent executing a task, by name
+    pub execution_seconds: HistogramVec,
+    /// Time between a task becoming runnable and being claimed, by name
+    pub queue_wait_seconds: HistogramVec,
+    /// Tasks waiting in the in-memory priority queue
+    pub pending_queue_depth: IntGauge,
+    /// Tasks waiting in memory whose deadline is close or already passed
+    pub tasks_at_deadline_risk: IntGauge,
+    /// Tasks currently being executed by this worker
+    pub processing_tasks: IntGauge,
+    /// Tasks buffered in the submission channel
+    pub channel_occupancy: IntGauge,
+    /// Capacity of the submission channel
+    pub channel_capacity: IntGauge,
+    /// Latency of each `Database` method
+    pub db_query_seconds: HistogramVec,
+    /// Finished tasks deleted by retention rules, by state
+    pub tasks_purged: IntCounterVec,
+    /// Submissions turned away by admission control, by the limit they hit
+    pub admission_rejections: IntCounterVec,
+}
+
+impl Metrics {
+    /// Create the metrics and register them in a fresh registry
+    pub fn new() -> AppResult<Self> {
+        let registry = Registry::new();
+
+        let task_labels = &["name", "priority"];
+        let tasks_submitted = IntCounterVec::new(
+            Opts::new("taskqueue_tasks_submitted_total", "Tasks submitted to the queue"),
+            task_labels,
+        )?;
+        let tasks_completed = IntCounterVec::new(
+            Opts::new("taskqueue_tasks_completed_total", "Tasks completed successfully"),
+            task_labels,
+        )?;
+        let tasks_failed = IntCounterVec::new(
+            Opts::new("taskqueue_tasks_failed_total", "Failed task attempts"),
+            task_labels,
+        )?;
+        let tasks_cancelled = IntCounterVec::new(
+            Opts::new("taskqueue_tasks_cancelled_total", "Tasks cancelled before running"),
+            task_labels,
+        )?;
+        let tasks_expired = IntCounterVec::new(
+            Opts::new("taskqueue_tasks_expired_total", "Tasks that expired before they could run"),
+            task_labels,
+        )?;
+        let task_timeouts = IntCounterVec::new(
+            Opts::new("taskqueue_task_timeouts_total", "Task executions that ran past their timeout"),
+            &["name", "limit"],
+        )?;
+        let deadline_misses = IntCounterVec::new(
+            Opts::new("taskqueue_deadline_misses_total", "Tasks completed after their deadline"),
+            task_labels,
+        )?;
+        let execution_seconds = HistogramVec::new(
+            HistogramOpts::new("taskqueue_task_execution_seconds", "Task execution time")
+                .buckets(TASK_DURATION_BUCKETS.to_vec()),
+            &["name"],
+        )?;
+        let queue_wait_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "taskqueue_task_queue_wait_seconds",
+                "Time from a task becoming runnable until it is claimed",
+            )
+            .buckets(TASK_DURATION_BUCKETS.to_vec()),
+            &["name"],
+        )?;
+        let pending_queue_depth = IntGauge::new(
+            "taskqueue_pending_queue_depth",
+            "Tasks waiting in the in-memory priority queue",
+        )?;
+        let tasks_at_deadline_risk = IntGauge::new(
+            "taskqueue_tasks_at_deadline_risk",
+            "Tasks waiting to run whose deadline is close or already passed",
+        )?;
+        let processing_tasks = IntGauge::new(
+            "taskqueue_processing_tasks",
+            "Tasks currently executing on this worker",
+        )?;
+        let channel_occupancy = IntGauge::new(
+            "taskqueue_channel_occupancy",
+            "Tasks buffered in the submission channel",
+        )?;
+        let channel_capacity = IntGauge::new(
+            "taskqueue_channel_capacity",
+            "Capacity of the submission channel",
+        )?;
+        let db_query_seconds = HistogramVec::new(
+            HistogramOpts::new("taskqueue_db_query_seconds", "Database query latency")
+                .buckets(QUERY_DURATION_BUCKETS.to_vec()),
+            &["method"],
+        )?;
+        let tasks_purged = IntCounterVec::new(
+            Opts::new("taskqueue_tasks_purged_total", "Finished tasks deleted by retention rules"),
+            &["state"],
+        )?;
+        let admission_rejections = IntCounterVec::new(
+            Opts::new(
+                "taskqueue_admission_rejections_total",
+                "Submissions rejected because too many tasks were pending",
+            ),
+            &["limit"],
+        )?;
+
+        registry.register(Box::new(tasks_submitted.clone()))?;
+        registry.register(Box::new(tasks_completed.clone()))?;
+        registry.register(Box::new(tasks_failed.clone()))?;
+        registry.register(Box::new(tasks_cancelled.clone()))?;
+        registry.register(Box::new(tasks_expired.clone()))?;
+        registry.register(Box::new(task_timeouts.clone()))?;
+        registry.register(Box::new(deadline_misses.clone()))?;
+        registry.register(Box::new(execution_seconds.clone()))?;
+        registry.register(Box::new(queue_wait_seconds.clone()))?;
+        registry.register(Box::new(pending_queue_depth.clone()))?;
+        registry.register(Box::new(tasks_at_deadline_risk.clone()))?;
+        registry.register(Box::new(processing_tasks.clone()))?;
+        registry.register(Box::new(channel_occupancy.clone()))?;
+        registry.register(Box::new(channel_capacity.clone()))?;
+        registry.register(Box::new(db_query_seconds.clone()))?;
+        registry.register(Box::new(tasks_purged.clone()))?;
+        registry.register(Box::new(admission_rejections.clone()))?;
+
+        Ok(Self {
+            registry,
+            tasks_submitted,
+            tasks_completed,
+            tasks_failed,
+            tasks_cancelled,
+            tasks_expired,
+            task_timeouts,
+            deadline_misses,
+            execution_seconds,
+            queue_wait_seconds,
+            pending_queue_depth,
+            tasks_at_deadline_risk,
+            processing_tasks,
+            channel_occupancy,
+            channel_capacity,
+            db_query_seconds,
+            tasks_purged,
+            admission_rejections,
+        })
+    }
+
+    /// Record a task accepted by the queue
+    pub fn record_submitted(&self, task: &Task) {
+        self.tasks_submitted
+            .with_label_values(&[&task.name, &task.priority.to_string()])
+            .inc();
+    }
+
+    /// Record a task being claimed for execution, observing how long it waited
+    pub fn record_claimed(&self, task: &Task) {
+        let runnable_since = task.scheduled_at.unwrap_or(task.created_at).max(task.created_at);
+        if let Some(started_at) = task.started_at {
+            let waited = (started_at - runnable_since).num_milliseconds().max(0) as f64 / 1000.0;
+            self.queue_wait_seconds
+                .with_label_values(&[&task.name])
+                .observe(waited);
+        }
+    }
+
+    /// Record a successful execution and how long it took, and whether it finished
+    /// too late for its deadline
+    pub fn record_completed(&self, task: &Task, execution: Duration) {
+        self.tasks_completed
+            .with_label_values(&[&task.name, &task.priority.to_string()])
+            .inc();
+        if let (Some(deadline), Some(completed_at)) = (task.deadline, task.completed_at) {
+            if completed_at > deadline {
+                self.deadline_misses
+                    .with_label_values(&[&task.name, &task.priority.to_string()])
+                    .inc();
+            }
+        }
+        self.execution_seconds
+            .with_label_values(&[&task.name])
+            .observe(execution.as_secs_f64());
+    }
+
+    /// Record a failed execution attempt
+    pub fn record_failed(&self, task: &Task, execution: Duration) {
+        self.tasks_failed
+            .with_label_values(&[&task.name, &task.priority.to_string()])
+            .inc();
+        self.execution_seconds
+            .with_label_values(&[&task.name])
+            .observe(execution.as_secs_f64());
+    }
+
+    /// Record an execution that ran past its timeout, either wrapping up when told
+    /// to or being aborted
+    pub fn record_timeout(&self, task: &Task, aborted: bool) {
+        let limit = if aborted { "hard" } else { "soft" };
+        self.task_timeouts.with_label_values(&[&task.name, limit]).inc();
+    }
+
+    /// Record a task cancelled before it ran
+    pub fn record_cancelled(&self, task: &Task) {
+        self.tasks_cancelled
+            .with_label_values(&[&task.name, &task.priority.to_string()])
+            .inc();
+    }
+
+    /// Record a task that expired before it could run
+    pub fn record_expired(&self, task: &Task) {
+        self.tasks_expired
+            .with_label_values(&[&task.name, &task.priority.to_string()])
+            .inc();
+    }
+
+    /// Render every registered metric in the Prometheus text format
+    pub fn render(&self) -> AppResult<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        String::from_utf8(buffer).map_err(|e| AppError::InternalServerError(e.to_string()))
+    }
+}
+
+impl From<prometheus::Error> for AppError {
+    fn from(error: prometheus::Error) -> Self {
+        AppError::InternalServerError(format!("Metrics error: {}", error))
+    }
+}
@@ -0,0 +1,23 @@
+use serde::Serialize;
+
+use crate::models::TaskProgress;
+
+/// Event published by the queue while tasks run
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TaskEvent {
+    /// A handler reported progress
+    Progress {
+        task_id: String,
+        progress: TaskProgress,
+    },
+}
+
+impl TaskEvent {
+    /// ID of the task the event is about
+    pub fn task_id(&self) -> &str {
+        match self {
+            TaskEvent::Progress { task_id, .. } => task_id,
+        }
+    }
+}
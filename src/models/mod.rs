@@ -1,3 +1,11 @@
+pub mod event;
+pub mod log;
+pub mod query;
+pub mod stats;
 pub mod task;
 
-pub use task::*;
\ No newline at end of file
+pub use event::*;
+pub use log::*;
+pub use query::*;
+pub use stats::*;
+pub use task::*;
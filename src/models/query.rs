@@ -0,0 +1,275 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::error::{AppError, AppResult};
+use crate::models::{Task, TaskState};
+
+/// Criteria used to select tasks when listing, counting or applying bulk operations
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TaskFilter {
+    /// Only tasks with one of these IDs
+    pub ids: Vec<String>,
+    pub state: Option<String>,
+    pub priority: Option<String>,
+    pub name: Option<String>,
+    /// Tasks must carry every one of these tags
+    pub tags: Vec<String>,
+    /// Only tasks created at or after this time
+    pub created_after: Option<DateTime<Utc>>,
+    /// Only tasks created strictly before this time
+    pub created_before: Option<DateTime<Utc>>,
+    /// Only tasks last changed strictly before this time
+    pub updated_before: Option<DateTime<Utc>>,
+    /// Only tasks with a deadline at or before this time
+    pub deadline_before: Option<DateTime<Utc>>,
+    /// Only tasks whose last error contains this text
+    pub error_contains: Option<String>,
+    pub tenant: Option<String>,
+}
+
+impl TaskFilter {
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.ids.push(id.into());
+        self
+    }
+
+    pub fn with_state(mut self, state: impl Into<String>) -> Self {
+        self.state = Some(state.into());
+        self
+    }
+
+    pub fn with_priority(mut self, priority: impl Into<String>) -> Self {
+        self.priority = Some(priority.into());
+        self
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn with_tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    pub fn with_tenant(mut self, tenant: impl Into<String>) -> Self {
+        self.tenant = Some(tenant.into());
+        self
+    }
+
+    /// Check whether the filter has no conditions at all
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+            && self.state.is_none()
+            && self.priority.is_none()
+            && self.name.is_none()
+            && self.tags.is_empty()
+            && self.created_after.is_none()
+            && self.created_before.is_none()
+            && self.updated_before.is_none()
+            && self.deadline_before.is_none()
+            && self.error_contains.is_none()
+            && self.tenant.is_none()
+    }
+
+    /// Check whether a task meets every condition, as the SQL backends' queries would
+    pub fn matches(&self, task: &Task) -> bool {
+        (self.ids.is_empty() || self.ids.contains(&task.id))
+            && self.state.as_ref().is_none_or(|state| task.state.to_string() == *state)
+            && self.priority.as_ref().is_none_or(|priority| task.priority.to_string() == *priority)
+            && self.name.as_ref().is_none_or(|name| task.name == *name)
+            && self.created_after.is_none_or(|after| task.created_at >= after)
+            && self.created_before.is_none_or(|before| task.created_at < before)
+            && self.updated_before.is_none_or(|before| task.updated_at < before)
+            && self
+                .deadline_before
+                .is_none_or(|before| task.deadline.is_some_and(|deadline| deadline <= before))
+            && self.error_contains.as_ref().is_none_or(|error| {
+                task.last_error.as_ref().is_some_and(|last| last.contains(error.as_str()))
+            })
+            && self.tags.iter().all(|tag| task.tags.contains(tag))
+            && self.tenant.as_ref().is_none_or(|tenant| task.tenant.as_ref() == Some(tenant))
+    }
+}
+
+/// Operation applied to every task matching a filter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BulkAction {
+    Cancel,
+    Retry,
+    Reprioritize,
+    Delete,
+}
+
+impl BulkAction {
+    /// States a task must be in for the action to apply to it
+    pub fn eligible_states(&self) -> &'static [TaskState] {
+        match self {
+            BulkAction::Cancel => &[TaskState::Pending, TaskState::Scheduled],
+            BulkAction::Retry => &[TaskState::Failed, TaskState::Cancelled],
+            BulkAction::Reprioritize => &[TaskState::Pending, TaskState::Scheduled, TaskState::Failed],
+            // Running tasks are never deleted out from under their worker
+            BulkAction::Delete => &[
+                TaskState::Pending,
+                TaskState::Scheduled,
+                TaskState::Completed,
+                TaskState::Failed,
+                TaskState::Cancelled,
+                TaskState::Expired,
+            ],
+        }
+    }
+}
+
+impl fmt::Display for BulkAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BulkAction::Cancel => write!(f, "cancel"),
+            BulkAction::Retry => write!(f, "retry"),
+            BulkAction::Reprioritize => write!(f, "reprioritize"),
+            BulkAction::Delete => write!(f, "delete"),
+        }
+    }
+}
+
+/// Number of tasks carrying a tag in a given state
+#[derive(Debug, Clone, Serialize)]
+pub struct TagStateCount {
+    pub tag: String,
+    pub state: String,
+    pub count: i64,
+}
+
+/// Position in a task listing ordered by `(created_at, id)` descending.
+///
+/// The cursor is handed to clients as an opaque string, so its encoding
+/// can change without breaking the API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskCursor {
+    pub created_at: DateTime<Utc>,
+    pub id: String,
+}
+
+impl TaskCursor {
+    /// Build a cursor pointing just past the given task
+    pub fn after(task: &Task) -> Self {
+        Self {
+            created_at: task.created_at,
+            id: task.id.clone(),
+        }
+    }
+
+    /// Encode the cursor as an opaque, URL-safe token
+    pub fn encode(&self) -> String {
+        let raw = format!("{}:{}", self.created_at.timestamp_micros(), self.id);
+        URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    /// Decode a token previously produced by [`TaskCursor::encode`]
+    pub fn decode(token: &str) -> AppResult<Self> {
+        let invalid = || AppError::InvalidRequest(format!("Invalid cursor: {}", token));
+
+        let bytes = URL_SAFE_NO_PAD.decode(token).map_err(|_| invalid())?;
+        let raw = String::from_utf8(bytes).map_err(|_| invalid())?;
+        let (micros, id) = raw.split_once(':').ok_or_else(invalid)?;
+        let micros: i64 = micros.parse().map_err(|_| invalid())?;
+        let created_at = DateTime::from_timestamp_micros(micros).ok_or_else(invalid)?;
+
+        if id.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            created_at,
+            id: id.to_string(),
+        })
+    }
+}
+
+/// How the total number of matching tasks should be computed for a listing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountMode {
+    /// Don't compute a total
+    None,
+    /// Run an exact `COUNT(*)` over the filter
+    Exact,
+    /// Use the planner's row estimate, which is cheap but approximate
+    Estimated,
+}
+
+impl std::str::FromStr for CountMode {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(CountMode::None),
+            "exact" => Ok(CountMode::Exact),
+            "estimated" => Ok(CountMode::Estimated),
+            _ => Err(AppError::InvalidRequest(format!("Unknown count mode: {}", s))),
+        }
+    }
+}
+
+/// A task's place in queue order at a precision every backend stores: its
+/// priority, then the second it was created in. Tasks at the same position run
+/// in order of their exact creation time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct QueuePosition {
+    /// See [`TaskPriority::rank`](crate::models::TaskPriority::rank)
+    pub priority_rank: u8,
+    /// Creation time in whole seconds since the epoch
+    pub created_second: i64,
+}
+
+impl QueuePosition {
+    /// The position before every task
+    pub const FIRST: Self = Self {
+        priority_rank: 0,
+        created_second: i64::MIN,
+    };
+
+    pub fn of(task: &Task) -> Self {
+        Self {
+            priority_rank: task.priority.rank(),
+            created_second: task.created_at.timestamp(),
+        }
+    }
+
+    /// Start of the second the position covers, no earlier than the epoch
+    pub fn created_from(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp(self.created_second.max(0), 0).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let task = Task::new("cursor".to_string(), serde_json::json!({}));
+        let cursor = TaskCursor::after(&task);
+
+        let decoded = TaskCursor::decode(&cursor.encode()).unwrap();
+
+        assert_eq!(decoded.id, task.id);
+        assert_eq!(
+            decoded.created_at.timestamp_micros(),
+            task.created_at.timestamp_micros()
+        );
+    }
+
+    #[test]
+    fn test_cursor_rejects_garbage() {
+        assert!(TaskCursor::decode("not a cursor").is_err());
+        assert!(TaskCursor::decode(&URL_SAFE_NO_PAD.encode("abc:id")).is_err());
+        assert!(TaskCursor::decode(&URL_SAFE_NO_PAD.encode("123:")).is_err());
+    }
+}
+
@@ -0,0 +1,29 @@
+use serde::Serialize;
+
+/// Number of tasks sharing a state, priority and name
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskGroupCount {
+    pub state: String,
+    pub priority: String,
+    pub name: String,
+    pub count: i64,
+}
+
+/// Nearest-rank percentiles of a set of durations, in seconds. The
+/// percentiles are absent when there are no samples.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DurationPercentiles {
+    pub count: i64,
+    pub p50: Option<f64>,
+    pub p95: Option<f64>,
+    pub p99: Option<f64>,
+}
+
+/// How long tasks waited and ran over a time window
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TimingStats {
+    /// From becoming runnable until being claimed, for tasks started in the window
+    pub wait: DurationPercentiles,
+    /// From being claimed until completing, for tasks completed in the window
+    pub run: DurationPercentiles,
+}
@@ -1,22 +1,20 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::str::FromStr;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+use crate::error::{AppError, AppResult};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
 pub enum TaskPriority {
     Low,
+    #[default]
     Medium,
     High,
     Critical,
 }
 
-impl Default for TaskPriority {
-    fn default() -> Self {
-        Self::Medium
-    }
-}
-
 impl fmt::Display for TaskPriority {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -28,20 +26,43 @@ impl fmt::Display for TaskPriority {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+impl TaskPriority {
+    /// Place of the priority in queue order, from 0 for critical tasks, which run first
+    pub fn rank(&self) -> u8 {
+        match self {
+            TaskPriority::Critical => 0,
+            TaskPriority::High => 1,
+            TaskPriority::Medium => 2,
+            TaskPriority::Low => 3,
+        }
+    }
+}
+
+impl FromStr for TaskPriority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(TaskPriority::Low),
+            "medium" => Ok(TaskPriority::Medium),
+            "high" => Ok(TaskPriority::High),
+            "critical" => Ok(TaskPriority::Critical),
+            _ => Err(format!("Unknown task priority: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
 pub enum TaskState {
+    #[default]
     Pending,
     Scheduled,
     Running,
     Completed,
     Failed,
     Cancelled,
-}
-
-impl Default for TaskState {
-    fn default() -> Self {
-        Self::Pending
-    }
+    /// Reached its expiry time before it could run
+    Expired,
 }
 
 impl fmt::Display for TaskState {
@@ -53,10 +74,47 @@ impl fmt::Display for TaskState {
             TaskState::Completed => write!(f, "completed"),
             TaskState::Failed => write!(f, "failed"),
             TaskState::Cancelled => write!(f, "cancelled"),
+            TaskState::Expired => write!(f, "expired"),
+        }
+    }
+}
+
+impl TaskState {
+    /// Check whether the task is neither running nor waiting to run
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            TaskState::Completed | TaskState::Failed | TaskState::Cancelled | TaskState::Expired
+        )
+    }
+}
+
+impl FromStr for TaskState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(TaskState::Pending),
+            "scheduled" => Ok(TaskState::Scheduled),
+            "running" => Ok(TaskState::Running),
+            "completed" => Ok(TaskState::Completed),
+            "failed" => Ok(TaskState::Failed),
+            "cancelled" => Ok(TaskState::Cancelled),
+            "expired" => Ok(TaskState::Expired),
+            _ => Err(format!("Unknown task state: {}", s)),
         }
     }
 }
 
+/// Latest progress reported by a running task's handler
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TaskProgress {
+    /// Completion between 0 and 100
+    pub percent: f64,
+    pub message: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Task {
     pub id: String,
@@ -75,6 +133,22 @@ pub struct Task {
     pub worker_id: Option<String>,
     pub result: Option<serde_json::Value>,
     pub tags: Vec<String>,
+    /// Trace ID of the request that created the task, linking its execution back to it
+    pub trace_id: Option<String>,
+    /// Progress of the current or last attempt
+    pub progress: Option<TaskProgress>,
+    /// State saved by the handler so a retried task can resume where it left off
+    pub checkpoint: Option<serde_json::Value>,
+    /// Tenant the task was submitted for, whose pending tasks are limited together
+    pub tenant: Option<String>,
+    /// Time after which the task is worthless and must not start
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Seconds the task may run before it is told to wrap up, overriding the
+    /// configured timeout for its name
+    pub timeout_seconds: Option<u64>,
+    /// Time the task should have finished by. Unlike the expiry, a task past its
+    /// deadline still runs.
+    pub deadline: Option<DateTime<Utc>>,
 }
 
 impl Task {
@@ -97,6 +171,13 @@ impl Task {
             worker_id: None,
             result: None,
             tags: Vec::new(),
+            trace_id: None,
+            progress: None,
+            checkpoint: None,
+            tenant: None,
+            expires_at: None,
+            timeout_seconds: None,
+            deadline: None,
         }
     }
 
@@ -121,6 +202,36 @@ impl Task {
         self
     }
 
+    pub fn with_trace_id(mut self, trace_id: impl Into<String>) -> Self {
+        self.trace_id = Some(trace_id.into());
+        self
+    }
+
+    pub fn with_tenant(mut self, tenant: impl Into<String>) -> Self {
+        self.tenant = Some(tenant.into());
+        self
+    }
+
+    pub fn with_expiry(mut self, expires_at: DateTime<Utc>) -> Self {
+        self.expires_at = Some(expires_at);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout_seconds: u64) -> Self {
+        self.timeout_seconds = Some(timeout_seconds);
+        self
+    }
+
+    pub fn with_deadline(mut self, deadline: DateTime<Utc>) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Check whether the task's expiry time has passed by `now`
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+
     pub fn is_ready_to_run(&self) -> bool {
         match self.state {
             TaskState::Pending => true,
@@ -164,6 +275,11 @@ impl Task {
         self.state = TaskState::Cancelled;
         self.updated_at = Utc::now();
     }
+
+    pub fn mark_expired(&mut self) {
+        self.state = TaskState::Expired;
+        self.updated_at = Utc::now();
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -174,6 +290,129 @@ pub struct CreateTaskRequest {
     pub scheduled_at: Option<DateTime<Utc>>,
     pub max_attempts: Option<u32>,
     pub tags: Option<Vec<String>>,
+    pub tenant: Option<String>,
+    /// Time after which the task must not start
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Seconds the task may run before it is told to wrap up
+    pub timeout_seconds: Option<u64>,
+    /// Time the task should have finished by
+    pub deadline: Option<DateTime<Utc>>,
+}
+
+impl CreateTaskRequest {
+    /// Validate the request and build the task it describes. The task may set a
+    /// timeout of at most `max_timeout_seconds`.
+    pub fn into_task(self, max_timeout_seconds: u64) -> AppResult<Task> {
+        if self.name.trim().is_empty() {
+            return Err(AppError::InvalidRequest("Task name must not be empty".to_string()));
+        }
+
+        if self.max_attempts == Some(0) {
+            return Err(AppError::InvalidRequest("max_attempts must be at least 1".to_string()));
+        }
+
+        if self.timeout_seconds.is_some_and(|t| t == 0 || t > max_timeout_seconds) {
+            return Err(AppError::InvalidRequest(format!(
+                "timeout_seconds must be between 1 and {}", max_timeout_seconds
+            )));
+        }
+
+        let mut task = Task::new(self.name, self.payload);
+
+        if let Some(priority) = self.priority {
+            task = task.with_priority(priority);
+        }
+
+        if let Some(scheduled_at) = self.scheduled_at {
+            task = task.with_scheduled_time(scheduled_at);
+        }
+
+        if let Some(max_attempts) = self.max_attempts {
+            task = task.with_max_attempts(max_attempts);
+        }
+
+        if let Some(tags) = self.tags {
+            task = task.with_tags(tags);
+        }
+
+        if let Some(tenant) = self.tenant {
+            if tenant.trim().is_empty() {
+                return Err(AppError::InvalidRequest("Tenant must not be empty".to_string()));
+            }
+            task = task.with_tenant(tenant);
+        }
+
+        if let Some(expires_at) = self.expires_at {
+            if expires_at <= Utc::now() {
+                return Err(AppError::InvalidRequest("expires_at must be in the future".to_string()));
+            }
+            if task.scheduled_at.is_some_and(|at| at >= expires_at) {
+                return Err(AppError::InvalidRequest(
+                    "expires_at must be after scheduled_at".to_string(),
+                ));
+            }
+            task = task.with_expiry(expires_at);
+        }
+
+        if let Some(timeout_seconds) = self.timeout_seconds {
+            task = task.with_timeout(timeout_seconds);
+        }
+
+        if let Some(deadline) = self.deadline {
+            if deadline <= Utc::now() {
+                return Err(AppError::InvalidRequest("deadline must be in the future".to_string()));
+            }
+            task = task.with_deadline(deadline);
+        }
+
+        Ok(task)
+    }
+}
+
+/// Changes to a task that hasn't started running yet. Absent fields are left as they are.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UpdateTaskRequest {
+    pub priority: Option<TaskPriority>,
+    pub scheduled_at: Option<DateTime<Utc>>,
+    pub max_attempts: Option<u32>,
+    pub tags: Option<Vec<String>>,
+}
+
+impl UpdateTaskRequest {
+    /// Apply the changes to a task
+    pub fn apply(self, task: &mut Task) -> AppResult<()> {
+        if self.max_attempts == Some(0) {
+            return Err(AppError::InvalidRequest("max_attempts must be at least 1".to_string()));
+        }
+
+        if let Some(priority) = self.priority {
+            task.priority = priority;
+        }
+
+        if let Some(scheduled_at) = self.scheduled_at {
+            if task.expires_at.is_some_and(|at| scheduled_at >= at) {
+                return Err(AppError::InvalidRequest(
+                    "scheduled_at must be before the task expires".to_string(),
+                ));
+            }
+            task.scheduled_at = Some(scheduled_at);
+            // Only tasks waiting to run move between pending and scheduled
+            if matches!(task.state, TaskState::Pending | TaskState::Scheduled) {
+                task.state = TaskState::Scheduled;
+            }
+        }
+
+        if let Some(max_attempts) = self.max_attempts {
+            task.max_attempts = max_attempts;
+        }
+
+        if let Some(tags) = self.tags {
+            task.tags = tags;
+        }
+
+        task.updated_at = Utc::now();
+        Ok(())
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -188,6 +427,16 @@ pub struct TaskResponse {
     pub attempts: u32,
     pub max_attempts: u32,
     pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_seconds: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<TaskProgress>,
 }
 
 impl From<Task> for TaskResponse {
@@ -203,6 +452,11 @@ impl From<Task> for TaskResponse {
             attempts: task.attempts,
             max_attempts: task.max_attempts,
             tags: task.tags,
+            tenant: task.tenant,
+            expires_at: task.expires_at,
+            timeout_seconds: task.timeout_seconds,
+            deadline: task.deadline,
+            progress: task.progress,
         }
     }
 }
\ No newline at end of file
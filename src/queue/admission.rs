@@ -0,0 +1,247 @@
+use crate::error::{AppError, AppResult};
+use crate::metrics::Metrics;
+use crate::models::{Task, TaskFilter, TaskState};
+use crate::storage::Database;
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// How long a pending count read from storage is used before it is read again.
+// Tasks admitted in the meantime are added to it, so a burst can't overshoot.
+const COUNT_MAX_AGE: Duration = Duration::from_secs(1);
+
+// Period the drain rate is averaged over
+const DRAIN_WINDOW_SECONDS: u64 = 60;
+
+// Bounds of the Retry-After given with a rejection
+const MIN_RETRY_AFTER_SECONDS: u64 = 1;
+const MAX_RETRY_AFTER_SECONDS: u64 = 300;
+
+/// Turns away submissions while too many tasks are pending, in the whole queue or
+/// for one tenant, before anything is written to storage.
+///
+/// Pending counts come from storage so they cover every instance, and are reused
+/// briefly to keep submissions cheap. Rejections say when to retry based on how
+/// fast this instance has been starting tasks.
+pub struct Admission {
+    max_pending: Option<usize>,
+    max_pending_per_tenant: Option<usize>,
+    /// Recently read pending counts by tenant, with the whole queue under `None`
+    counts: Mutex<HashMap<Option<String>, PendingCount>>,
+    drain: Mutex<DrainRate>,
+    metrics: Arc<Metrics>,
+}
+
+struct PendingCount {
+    count: usize,
+    read_at: Instant,
+}
+
+impl Admission {
+    pub fn new(
+        max_pending: Option<usize>,
+        max_pending_per_tenant: Option<usize>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            max_pending,
+            max_pending_per_tenant,
+            counts: Mutex::new(HashMap::new()),
+            drain: Mutex::new(DrainRate::new()),
+            metrics,
+        }
+    }
+
+    /// Check that the tasks fit within the pending limits, counting them as pending
+    /// if they do. Rejected tasks count against nothing.
+    pub async fn admit(&self, db: &dyn Database, tasks: &[Task]) -> AppResult<()> {
+        let mut by_tenant: HashMap<&str, usize> = HashMap::new();
+        if self.max_pending_per_tenant.is_some() {
+            for tenant in tasks.iter().filter_map(|task| task.tenant.as_deref()) {
+                *by_tenant.entry(tenant).or_default() += 1;
+            }
+        }
+
+        if let Some(max) = self.max_pending {
+            self.check(db, None, tasks.len(), max).await?;
+        }
+        if let Some(max) = self.max_pending_per_tenant {
+            for (&tenant, &incoming) in &by_tenant {
+                self.check(db, Some(tenant), incoming, max).await?;
+            }
+        }
+
+        let mut counts = self.counts.lock();
+        if let Some(pending) = counts.get_mut(&None) {
+            pending.count += tasks.len();
+        }
+        for (tenant, incoming) in by_tenant {
+            if let Some(pending) = counts.get_mut(&Some(tenant.to_string())) {
+                pending.count += incoming;
+            }
+        }
+        Ok(())
+    }
+
+    /// Record a task starting, which is what drains the pending tasks
+    pub fn record_started(&self) {
+        self.drain.lock().record();
+    }
+
+    // Reject the incoming tasks if they would take the tenant, or the whole queue,
+    // past `max` pending tasks
+    async fn check(
+        &self,
+        db: &dyn Database,
+        tenant: Option<&str>,
+        incoming: usize,
+        max: usize,
+    ) -> AppResult<()> {
+        let scope = match tenant {
+            Some(tenant) => format!("Tenant {}", tenant),
+            None => "The queue".to_string(),
+        };
+        if incoming > max {
+            return Err(AppError::InvalidRequest(format!(
+                "{} allows at most {} pending tasks, fewer than the {} submitted",
+                scope, max, incoming
+            )));
+        }
+
+        let pending = self.pending(db, tenant).await?;
+        if pending + incoming <= max {
+            return Ok(());
+        }
+
+        let limit = if tenant.is_some() { "tenant" } else { "queue" };
+        self.metrics.admission_rejections.with_label_values(&[limit]).inc();
+        Err(AppError::TooManyPendingTasks {
+            message: format!("{} has {} pending tasks, the limit is {}", scope, pending, max),
+            retry_after_seconds: self.retry_after(pending + incoming - max),
+        })
+    }
+
+    // Pending tasks for the tenant, or the whole queue, read from storage unless
+    // a recent count is at hand
+    async fn pending(&self, db: &dyn Database, tenant: Option<&str>) -> AppResult<usize> {
+        let key = tenant.map(str::to_string);
+        if let Some(pending) = self.counts.lock().get(&key) {
+            if pending.read_at.elapsed() < COUNT_MAX_AGE {
+                return Ok(pending.count);
+            }
+        }
+
+        let mut filter = TaskFilter::default().with_state(TaskState::Pending.to_string());
+        if let Some(tenant) = tenant {
+            filter = filter.with_tenant(tenant);
+        }
+        let count = db.count_tasks(&filter).await?.max(0) as usize;
+
+        let mut counts = self.counts.lock();
+        counts.retain(|_, pending| pending.read_at.elapsed() < COUNT_MAX_AGE);
+        counts.insert(key, PendingCount { count, read_at: Instant::now() });
+        Ok(count)
+    }
+
+    // Seconds until `excess` more tasks have started at the current drain rate
+    fn retry_after(&self, excess: usize) -> u64 {
+        let rate = self.drain.lock().per_second();
+        if rate <= 0.0 {
+            return MAX_RETRY_AFTER_SECONDS;
+        }
+        ((excess as f64 / rate).ceil() as u64).clamp(MIN_RETRY_AFTER_SECONDS, MAX_RETRY_AFTER_SECONDS)
+    }
+}
+
+/// Tasks started per second, counted in one-second buckets over a sliding window
+struct DrainRate {
+    started: Instant,
+    /// Seconds since `started` and the tasks started in each, oldest first
+    buckets: VecDeque<(u64, u64)>,
+}
+
+impl DrainRate {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            buckets: VecDeque::new(),
+        }
+    }
+
+    fn record(&mut self) {
+        let now = self.started.elapsed().as_secs();
+        match self.buckets.back_mut() {
+            Some((second, count)) if *second == now => *count += 1,
+            _ => self.buckets.push_back((now, 1)),
+        }
+        self.expire(now);
+    }
+
+    fn per_second(&mut self) -> f64 {
+        let elapsed = self.started.elapsed();
+        self.expire(elapsed.as_secs());
+
+        let started: u64 = self.buckets.iter().map(|(_, count)| count).sum();
+        // Until a whole window has passed, average over the time there has been
+        let window = elapsed.as_secs_f64().clamp(1.0, DRAIN_WINDOW_SECONDS as f64);
+        started as f64 / window
+    }
+
+    fn expire(&mut self, now: u64) {
+        while self
+            .buckets
+            .front()
+            .is_some_and(|(second, _)| second + DRAIN_WINDOW_SECONDS <= now)
+        {
+            self.buckets.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryDatabase;
+
+    fn task(tenant: &str) -> Task {
+        Task::new("work".to_string(), serde_json::json!({})).with_tenant(tenant)
+    }
+
+    #[tokio::test]
+    async fn test_rejects_past_queue_and_tenant_limits() {
+        let db = MemoryDatabase::new();
+        let admission = Admission::new(Some(3), Some(2), Arc::new(Metrics::new().unwrap()));
+
+        let admitted = [task("a"), task("a")];
+        admission.admit(&db, &admitted).await.unwrap();
+        db.create_tasks(&admitted).await.unwrap();
+
+        // Tenant a is full, even though the queue isn't
+        assert!(matches!(
+            admission.admit(&db, &[task("a")]).await,
+            Err(AppError::TooManyPendingTasks { .. })
+        ));
+        admission.admit(&db, &[task("b")]).await.unwrap();
+
+        // The count read before still includes the task admitted since
+        match admission.admit(&db, &[task("c")]).await {
+            Err(AppError::TooManyPendingTasks { retry_after_seconds, .. }) => {
+                assert_eq!(retry_after_seconds, MAX_RETRY_AFTER_SECONDS);
+            }
+            other => panic!("expected a rejection, got {:?}", other),
+        }
+
+        // Once tasks have been starting, retrying is worth it sooner
+        for _ in 0..10 {
+            admission.record_started();
+        }
+        assert_eq!(admission.retry_after(1), MIN_RETRY_AFTER_SECONDS);
+
+        let oversized: Vec<Task> = (0..4).map(|_| task("d")).collect();
+        assert!(matches!(
+            admission.admit(&db, &oversized).await,
+            Err(AppError::InvalidRequest(_))
+        ));
+    }
+}
@@ -0,0 +1,128 @@
+use crate::models::Task;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap};
+
+/// Scheduled tasks held in memory until they are due, ordered by `scheduled_at`
+pub struct DelayQueue {
+    /// Tasks keyed by due time, with the ID breaking ties
+    entries: BTreeMap<(DateTime<Utc>, String), Task>,
+    /// Due time of every held task, so tasks can be found by ID
+    due_at: HashMap<String, DateTime<Utc>>,
+}
+
+impl DelayQueue {
+    /// Create a new empty delay queue
+    pub fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            due_at: HashMap::new(),
+        }
+    }
+
+    /// Hold a task until its scheduled time, replacing any earlier copy of it.
+    /// Tasks without a scheduled time are due immediately. Returns true if the
+    /// task is now the first one due.
+    pub fn insert(&mut self, task: Task) -> bool {
+        self.remove(&task.id);
+
+        let at = task.scheduled_at.unwrap_or(DateTime::<Utc>::MIN_UTC);
+        let earliest = self.next_due().is_none_or(|next| at < next);
+        self.due_at.insert(task.id.clone(), at);
+        self.entries.insert((at, task.id.clone()), task);
+        earliest
+    }
+
+    /// Remove a task by ID
+    pub fn remove(&mut self, id: &str) -> Option<Task> {
+        let at = self.due_at.remove(id)?;
+        self.entries.remove(&(at, id.to_string()))
+    }
+
+    /// Check whether a task is held
+    pub fn contains(&self, id: &str) -> bool {
+        self.due_at.contains_key(id)
+    }
+
+    /// Time the first held task becomes due
+    pub fn next_due(&self) -> Option<DateTime<Utc>> {
+        self.entries.keys().next().map(|(at, _)| *at)
+    }
+
+    /// Take every task due at or before `now`, earliest first
+    pub fn pop_due(&mut self, now: DateTime<Utc>) -> Vec<Task> {
+        let mut due = Vec::new();
+        while let Some(entry) = self.entries.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+            let task = entry.remove();
+            self.due_at.remove(&task.id);
+            due.push(task);
+        }
+        due
+    }
+
+    /// Get the number of held tasks
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if the queue is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Default for DelayQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_tasks_come_out_when_due() {
+        let mut queue = DelayQueue::new();
+        let now = Utc::now();
+        let scheduled = |seconds: i64| {
+            Task::new("delayed".to_string(), serde_json::json!({}))
+                .with_scheduled_time(now + Duration::seconds(seconds))
+        };
+
+        let later = scheduled(20);
+        let soon = scheduled(10);
+        assert!(queue.insert(later.clone()));
+        assert!(queue.insert(soon.clone()));
+        assert!(!queue.insert(scheduled(30)));
+        assert_eq!(queue.next_due(), soon.scheduled_at);
+
+        assert!(queue.pop_due(now).is_empty());
+        let due = queue.pop_due(now + Duration::seconds(20));
+        assert_eq!(
+            due.iter().map(|task| task.id.as_str()).collect::<Vec<_>>(),
+            vec![soon.id.as_str(), later.id.as_str()]
+        );
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn test_rescheduling_replaces_the_entry() {
+        let mut queue = DelayQueue::new();
+        let now = Utc::now();
+        let task = Task::new("delayed".to_string(), serde_json::json!({}))
+            .with_scheduled_time(now + Duration::seconds(10));
+
+        queue.insert(task.clone());
+        queue.insert(task.clone().with_scheduled_time(now + Duration::seconds(60)));
+        assert_eq!(queue.len(), 1);
+        assert!(queue.pop_due(now + Duration::seconds(30)).is_empty());
+
+        assert!(queue.remove(&task.id).is_some());
+        assert!(!queue.contains(&task.id));
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,283 @@
+use crate::error::{AppError, AppResult};
+use crate::models::Task;
+use parking_lot::Mutex;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::{watch, Notify, OwnedSemaphorePermit, Semaphore};
+
+use super::Window;
+
+/// Hands ready tasks to workers in priority order, never running more than
+/// `max_concurrent` at once.
+///
+/// Submitted tasks arrive over a channel, which also wakes the dispatch loop when
+/// it is idle. Tasks that don't fit in the channel go straight into the window of
+/// ready tasks held in memory, which is refilled from storage as it drains. Every
+/// dispatched task holds a worker permit until it finishes.
+pub struct Dispatcher {
+    sender: mpsc::Sender<Task>,
+    /// Taken by the dispatch loop when it starts
+    receiver: Arc<Mutex<Option<mpsc::Receiver<Task>>>>,
+    window: Arc<Mutex<Window>>,
+    /// Signalled when the window should be refilled from storage
+    refill_wanted: Arc<Notify>,
+    /// Signalled when tasks were loaded into the window from storage
+    refilled: Arc<Notify>,
+    workers: Arc<Semaphore>,
+    max_concurrent: usize,
+    /// Set once the dispatch loop should stop handing out tasks
+    stopped: Arc<watch::Sender<bool>>,
+}
+
+impl Clone for Dispatcher {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            receiver: self.receiver.clone(),
+            window: self.window.clone(),
+            refill_wanted: self.refill_wanted.clone(),
+            refilled: self.refilled.clone(),
+            workers: self.workers.clone(),
+            max_concurrent: self.max_concurrent,
+            stopped: self.stopped.clone(),
+        }
+    }
+}
+
+impl Dispatcher {
+    /// Create a dispatcher running up to `max_concurrent` tasks at once and holding up
+    /// to `window_size` ready tasks in memory
+    pub fn new(max_concurrent: usize, window_size: usize) -> Self {
+        Self::with_window(max_concurrent, Window::new(window_size))
+    }
+
+    /// Create a dispatcher running up to `max_concurrent` tasks at once from the
+    /// given window
+    pub fn with_window(max_concurrent: usize, window: Window) -> Self {
+        let (sender, receiver) = mpsc::channel(max_concurrent.max(1) * 2);
+
+        Self {
+            sender,
+            receiver: Arc::new(Mutex::new(Some(receiver))),
+            window: Arc::new(Mutex::new(window)),
+            refill_wanted: Arc::new(Notify::new()),
+            refilled: Arc::new(Notify::new()),
+            workers: Arc::new(Semaphore::new(max_concurrent)),
+            max_concurrent,
+            stopped: Arc::new(watch::Sender::new(false)),
+        }
+    }
+
+    /// Queue tasks that are ready to run
+    pub fn enqueue(&self, tasks: impl IntoIterator<Item = Task>) -> AppResult<()> {
+        let mut overflow = Vec::new();
+        for task in tasks {
+            // Hand tasks to the dispatch loop while the channel has room, which also wakes
+            // it up if it is idle. The rest go straight into the window.
+            match self.sender.try_send(task) {
+                Ok(()) => {}
+                Err(TrySendError::Full(task)) => overflow.push(task),
+                Err(TrySendError::Closed(_)) => return Err(AppError::QueueFull),
+            }
+        }
+
+        if !overflow.is_empty() {
+            let mut window = self.window.lock();
+            for task in overflow {
+                window.push(task);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Ready tasks waiting for a worker that are no longer in the channel
+    pub fn window(&self) -> &Mutex<Window> {
+        &self.window
+    }
+
+    /// Wait until the window has drained far enough to be refilled from storage
+    pub async fn refill_wanted(&self) {
+        self.refill_wanted.notified().await;
+    }
+
+    /// Wake the dispatch loop after tasks were loaded into the window
+    pub fn refilled(&self) {
+        self.refilled.notify_one();
+    }
+
+    /// Number of tasks buffered in the channel
+    pub fn channel_occupancy(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+
+    /// Capacity of the channel
+    pub fn channel_capacity(&self) -> usize {
+        self.sender.max_capacity()
+    }
+
+    /// Make the dispatch loop return instead of handing out more tasks. Tasks
+    /// already dispatched keep running.
+    pub fn stop(&self) {
+        self.stopped.send_replace(true);
+    }
+
+    /// Wait up to `timeout` for every dispatched task to give back its worker permit.
+    /// Returns false if some were still running when the time ran out.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        let all = self.workers.acquire_many(self.max_concurrent as u32);
+        tokio::time::timeout(timeout, all).await.is_ok()
+    }
+
+    /// Run the dispatch loop. Each time a worker is free, the first ready task in the
+    /// window is handed to `dispatch` along with the worker's permit, which must be
+    /// held until the task finishes. Returns once stopped, or at the first error
+    /// `dispatch` returns.
+    pub async fn run<F, Fut>(&self, mut dispatch: F) -> AppResult<()>
+    where
+        F: FnMut(Task, OwnedSemaphorePermit) -> Fut,
+        Fut: Future<Output = AppResult<()>>,
+    {
+        let mut receiver = self.receiver.lock().take().ok_or_else(|| {
+            AppError::InternalServerError("Dispatcher is already running".to_string())
+        })?;
+        let mut stopped = self.stopped.subscribe();
+
+        loop {
+            let permit = tokio::select! {
+                biased;
+                _ = stopped.wait_for(|stopped| *stopped) => return Ok(()),
+                permit = self.workers.clone().acquire_owned() => {
+                    permit.map_err(|e| AppError::InternalServerError(e.to_string()))?
+                }
+            };
+
+            let task = loop {
+                // Move everything submitted so far into the window, so the most urgent
+                // task runs first whichever way it arrived
+                let next = {
+                    let mut window = self.window.lock();
+                    while let Ok(task) = receiver.try_recv() {
+                        window.push(task);
+                    }
+                    let next = window.pop();
+                    if window.wants_refill() {
+                        self.refill_wanted.notify_one();
+                    }
+                    next
+                };
+                if let Some(task) = next {
+                    break task;
+                }
+
+                // Nothing is ready, so wait for the next submission or refill
+                tokio::select! {
+                    biased;
+                    _ = stopped.wait_for(|stopped| *stopped) => return Ok(()),
+                    task = receiver.recv() => match task {
+                        Some(task) => {
+                            self.window.lock().push(task);
+                        }
+                        None => return Ok(()),
+                    },
+                    _ = self.refilled.notified() => {}
+                }
+            };
+
+            // Stopping may have been requested while a worker was busy
+            if *stopped.borrow() {
+                return Ok(());
+            }
+            dispatch(task, permit).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::TaskPriority;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_runs_by_priority_within_the_worker_limit() {
+        let dispatcher = Dispatcher::new(2, 100);
+        let started = Arc::new(Mutex::new(Vec::new()));
+
+        // Fill the channel and overflow into the window before the loop starts
+        let tasks: Vec<Task> = [TaskPriority::Low, TaskPriority::Medium, TaskPriority::High]
+            .into_iter()
+            .cycle()
+            .take(6)
+            .map(|priority| Task::new("work".to_string(), serde_json::json!({})).with_priority(priority))
+            .collect();
+        dispatcher.enqueue(tasks).unwrap();
+
+        let running = dispatcher.clone();
+        let log = started.clone();
+        tokio::spawn(async move {
+            running
+                .run(|task, permit| {
+                    log.lock().push(task.priority.clone());
+                    tokio::spawn(async move {
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                        drop(permit);
+                    });
+                    async { Ok(()) }
+                })
+                .await
+        });
+
+        // Only two run until the first ones finish
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(started.lock().len(), 2);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(
+            *started.lock(),
+            vec![
+                TaskPriority::High,
+                TaskPriority::High,
+                TaskPriority::Medium,
+                TaskPriority::Medium,
+                TaskPriority::Low,
+                TaskPriority::Low,
+            ]
+        );
+        assert!(dispatcher.run(|_, _| async { Ok(()) }).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stop_waits_for_running_tasks() {
+        let dispatcher = Dispatcher::new(2, 100);
+        let tasks = (0..4).map(|_| Task::new("work".to_string(), serde_json::json!({})));
+        dispatcher.enqueue(tasks).unwrap();
+
+        let running = dispatcher.clone();
+        let started = Arc::new(Mutex::new(0));
+        let count = started.clone();
+        let loop_handle = tokio::spawn(async move {
+            running
+                .run(|_, permit| {
+                    *count.lock() += 1;
+                    tokio::spawn(async move {
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        drop(permit);
+                    });
+                    async { Ok(()) }
+                })
+                .await
+        });
+
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        dispatcher.stop();
+        loop_handle.await.unwrap().unwrap();
+
+        // The two running tasks outlast a short wait but finish within a longer one
+        assert!(!dispatcher.drain(Duration::from_millis(10)).await);
+        assert!(dispatcher.drain(Duration::from_millis(200)).await);
+        assert_eq!(*started.lock(), 2);
+    }
+}
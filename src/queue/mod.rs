@@ -1,5 +1,22 @@
+mod admission;
+mod delay_queue;
+mod dispatcher;
 mod priority_queue;
+mod progress;
+mod scheduling;
+mod task_logger;
 mod task_queue;
+mod window;
 
+pub use admission::Admission;
+pub use delay_queue::DelayQueue;
+pub use dispatcher::Dispatcher;
 pub use priority_queue::PriorityQueue;
-pub use task_queue::TaskQueue;
\ No newline at end of file
+pub use progress::ProgressReporter;
+pub use scheduling::{
+    policy_for, ByPriority, EarliestDeadline, FairShare, Fifo, Lifo, PriorityDeadline,
+    SchedulingPolicy, SortKey, WeightedRandom,
+};
+pub use task_logger::TaskLogger;
+pub use task_queue::TaskQueue;
+pub use window::{RefillRequest, Window};
@@ -1,96 +1,276 @@
-use crate::models::{Task, TaskPriority};
-use std::cmp::Ordering;
-use std::collections::BinaryHeap;
-
-// Wrapper to make Task comparable for priority queue
-#[derive(Clone)]
-struct PrioritizedTask {
-    task: Task,
-}
+use crate::models::Task;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
 
-// Implement PartialEq manually to avoid issues with serde_json::Value
-impl PartialEq for PrioritizedTask {
-    fn eq(&self, other: &Self) -> bool {
-        self.task.id == other.task.id
-    }
-}
-
-// Implement Eq manually as well
-impl Eq for PrioritizedTask {}
-
-// Define ordering for priority queue
-impl Ord for PrioritizedTask {
-    fn cmp(&self, other: &Self) -> Ordering {
-        // First compare by priority (higher priority comes first)
-        let priority_ordering = match (&self.task.priority, &other.task.priority) {
-            (TaskPriority::Critical, TaskPriority::Critical) => Ordering::Equal,
-            (TaskPriority::Critical, _) => Ordering::Greater,
-            (_, TaskPriority::Critical) => Ordering::Less,
-            (TaskPriority::High, TaskPriority::High) => Ordering::Equal,
-            (TaskPriority::High, _) => Ordering::Greater,
-            (_, TaskPriority::High) => Ordering::Less,
-            (TaskPriority::Medium, TaskPriority::Medium) => Ordering::Equal,
-            (TaskPriority::Medium, _) => Ordering::Greater,
-            (_, TaskPriority::Medium) => Ordering::Less,
-            (TaskPriority::Low, TaskPriority::Low) => Ordering::Equal,
-        };
+use super::{ByPriority, SchedulingPolicy, SortKey};
 
-        if priority_ordering != Ordering::Equal {
-            return priority_ordering;
-        }
+// Queue order: by the policy's key, with the ID breaking ties as storage does
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct QueueKey {
+    key: SortKey,
+    id: String,
+}
 
-        // Then by creation time (older tasks come first)
-        self.task.created_at.cmp(&other.task.created_at)
-    }
+// Tasks ordered by one policy: those of a named queue with its own, or the rest
+struct Lane {
+    policy: Arc<dyn SchedulingPolicy>,
+    tasks: BTreeMap<QueueKey, Task>,
 }
 
-impl PartialOrd for PrioritizedTask {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
+// Order between the first tasks of each lane: by priority, then age, as in storage
+fn across_lanes(task: &Task) -> (u8, DateTime<Utc>, &str) {
+    (task.priority.rank(), task.created_at, &task.id)
 }
 
-/// A priority queue for tasks based on task priority and creation time
+/// A priority queue for tasks, ordered by priority and creation time unless
+/// another scheduling policy is chosen.
+///
+/// Named queues, the tasks sharing a name, may have a policy of their own. Each
+/// then runs its tasks in its own order, and the next task to run is the first of
+/// some queue, whichever comes first by priority and age.
 pub struct PriorityQueue {
-    heap: BinaryHeap<PrioritizedTask>,
+    /// The lane of tasks without a policy of their own first, then one for each
+    /// named queue with one
+    lanes: Vec<Lane>,
+    /// Lane of each named queue with its own policy
+    named: HashMap<String, usize>,
+    /// Lane and key of every queued task by ID
+    keys: HashMap<String, (usize, QueueKey)>,
 }
 
 impl PriorityQueue {
     /// Create a new empty priority queue
     pub fn new() -> Self {
+        Self::with_policy(Arc::new(ByPriority))
+    }
+
+    /// Create a new empty queue running tasks in the order the policy decides
+    pub fn with_policy(policy: Arc<dyn SchedulingPolicy>) -> Self {
+        Self::with_policies(policy, HashMap::new())
+    }
+
+    /// Create a new empty queue running the tasks of each named queue in the order
+    /// its policy decides, and all other tasks in the order the default one does
+    pub fn with_policies(
+        default: Arc<dyn SchedulingPolicy>,
+        named: HashMap<String, Arc<dyn SchedulingPolicy>>,
+    ) -> Self {
+        let mut lanes = vec![Lane {
+            policy: default,
+            tasks: BTreeMap::new(),
+        }];
+        let named = named
+            .into_iter()
+            .map(|(name, policy)| {
+                lanes.push(Lane {
+                    policy,
+                    tasks: BTreeMap::new(),
+                });
+                (name, lanes.len() - 1)
+            })
+            .collect();
         Self {
-            heap: BinaryHeap::new(),
+            lanes,
+            named,
+            keys: HashMap::new(),
         }
     }
 
-    /// Push a task into the queue
+    /// Whether every policy runs tasks in the order storage hands them out, so the
+    /// queue as a whole does too
+    pub fn follows_storage_order(&self) -> bool {
+        self.lanes.iter().all(|lane| lane.policy.follows_storage_order())
+    }
+
+    /// Push a task into the queue, replacing any queued task with the same ID
     pub fn push(&mut self, task: Task) {
-        self.heap.push(PrioritizedTask { task });
+        // A task queued again keeps what its policy knows of it
+        self.take(&task.id);
+        let lane = self.named.get(&task.name).copied().unwrap_or(0);
+        let key = QueueKey {
+            key: self.lanes[lane].policy.sort_key(&task, Utc::now()),
+            id: task.id.clone(),
+        };
+        self.keys.insert(task.id.clone(), (lane, key.clone()));
+        self.lanes[lane].tasks.insert(key, task);
     }
 
     /// Pop the highest priority task from the queue
     pub fn pop(&mut self) -> Option<Task> {
-        self.heap.pop().map(|prioritized| prioritized.task)
+        let lane = self.first_lane()?;
+        let lane = &mut self.lanes[lane];
+        let (key, task) = lane.tasks.pop_first()?;
+        self.keys.remove(&key.id);
+        lane.policy.dispatched(&task, &key.key);
+        Some(task)
+    }
+
+    /// Pop the task that would run last
+    pub fn pop_last(&mut self) -> Option<Task> {
+        let lane = self.last_lane()?;
+        let lane = &mut self.lanes[lane];
+        let (key, task) = lane.tasks.pop_last()?;
+        self.keys.remove(&key.id);
+        lane.policy.removed(&task);
+        Some(task)
     }
 
     /// Peek at the highest priority task without removing it
     pub fn peek(&self) -> Option<&Task> {
-        self.heap.peek().map(|prioritized| &prioritized.task)
+        self.lanes[self.first_lane()?].tasks.values().next()
+    }
+
+    /// Peek at the task that would run last without removing it
+    pub fn peek_last(&self) -> Option<&Task> {
+        self.lanes[self.last_lane()?].tasks.values().next_back()
+    }
+
+    /// Iterate over the queued tasks from the first to run to the last
+    pub fn iter(&self) -> impl Iterator<Item = &Task> {
+        let mut lanes: Vec<_> = self
+            .lanes
+            .iter()
+            .map(|lane| lane.tasks.values().peekable())
+            .collect();
+        std::iter::from_fn(move || {
+            let (_, next) = lanes
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(i, lane)| lane.peek().map(|task| (across_lanes(task), i)))
+                .min()?;
+            lanes[next].next()
+        })
+    }
+
+    /// Iterate over the queued tasks from the last to run to the first
+    pub fn iter_rev(&self) -> impl Iterator<Item = &Task> {
+        let mut lanes: Vec<_> = self
+            .lanes
+            .iter()
+            .map(|lane| lane.tasks.values().rev().peekable())
+            .collect();
+        std::iter::from_fn(move || {
+            let (_, next) = lanes
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(i, lane)| lane.peek().map(|task| (across_lanes(task), i)))
+                .max()?;
+            lanes[next].next()
+        })
     }
 
     /// Check if the queue is empty
     pub fn is_empty(&self) -> bool {
-        self.heap.is_empty()
+        self.keys.is_empty()
     }
 
     /// Get the number of tasks in the queue
     pub fn len(&self) -> usize {
-        self.heap.len()
+        self.keys.len()
     }
 
     /// Clear all tasks from the queue
     pub fn clear(&mut self) {
-        self.heap.clear();
+        self.retain(|_| false);
+    }
+
+    /// Check whether a task is in the queue
+    pub fn contains(&self, id: &str) -> bool {
+        self.keys.contains_key(id)
+    }
+
+    /// Remove a task from the queue by ID
+    pub fn remove(&mut self, id: &str) -> Option<Task> {
+        let lane = self.keys.get(id)?.0;
+        let task = self.take(id)?;
+        self.lanes[lane].policy.removed(&task);
+        Some(task)
+    }
+
+    /// Keep only the tasks for which the predicate returns true
+    pub fn retain(&mut self, mut f: impl FnMut(&Task) -> bool) {
+        let keys = &mut self.keys;
+        for lane in &mut self.lanes {
+            let policy = &lane.policy;
+            lane.tasks.retain(|key, task| {
+                let keep = f(task);
+                if !keep {
+                    keys.remove(&key.id);
+                    policy.removed(task);
+                }
+                keep
+            });
+        }
+    }
+
+    /// Modify a queued task in place and restore its position in the queue.
+    /// Returns false if the task isn't queued.
+    pub fn update(&mut self, id: &str, f: impl FnOnce(&mut Task)) -> bool {
+        match self.take(id) {
+            Some(mut task) => {
+                f(&mut task);
+                self.push(task);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Modify every queued task matching the predicate and re-key the queue.
+    /// Returns the number of tasks modified.
+    pub fn update_where(
+        &mut self,
+        mut pred: impl FnMut(&Task) -> bool,
+        mut f: impl FnMut(&mut Task),
+    ) -> usize {
+        let ids: Vec<String> = self
+            .lanes
+            .iter()
+            .flat_map(|lane| lane.tasks.values())
+            .filter(|task| pred(task))
+            .map(|task| task.id.clone())
+            .collect();
+        for id in &ids {
+            self.update(id, &mut f);
+        }
+        ids.len()
+    }
+
+    // Take a task out to be queued again, without telling its policy it left
+    fn take(&mut self, id: &str) -> Option<Task> {
+        let (lane, key) = self.keys.remove(id)?;
+        self.lanes[lane].tasks.remove(&key)
+    }
+
+    // Lane holding the task to run first
+    fn first_lane(&self) -> Option<usize> {
+        if self.lanes.len() == 1 {
+            return (!self.is_empty()).then_some(0);
+        }
+        let (_, lane) = self
+            .lanes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, lane)| lane.tasks.values().next().map(|task| (across_lanes(task), i)))
+            .min()?;
+        Some(lane)
+    }
+
+    // Lane holding the task to run last
+    fn last_lane(&self) -> Option<usize> {
+        if self.lanes.len() == 1 {
+            return (!self.is_empty()).then_some(0);
+        }
+        let (_, lane) = self
+            .lanes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, lane)| {
+                lane.tasks.values().next_back().map(|task| (across_lanes(task), i))
+            })
+            .max()?;
+        Some(lane)
     }
 }
 
@@ -103,7 +283,10 @@ impl Default for PriorityQueue {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use chrono::{Duration, Utc};
+    use crate::models::TaskPriority;
+    use crate::config::{PrioritySlack, QueueOrdering};
+    use crate::queue::policy_for;
+    use chrono::Duration;
 
     #[test]
     fn test_priority_ordering() {
@@ -180,4 +363,106 @@ mod tests {
         assert_eq!(queue.pop().unwrap().id, task2.id);
         assert_eq!(queue.pop().unwrap().id, task3.id);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_remove_and_rekey() {
+        let mut queue = PriorityQueue::new();
+
+        let low = Task::new("low".to_string(), serde_json::json!({}))
+            .with_priority(TaskPriority::Low);
+        let medium = Task::new("medium".to_string(), serde_json::json!({}))
+            .with_priority(TaskPriority::Medium);
+        let high = Task::new("high".to_string(), serde_json::json!({}))
+            .with_priority(TaskPriority::High);
+
+        queue.push(low.clone());
+        queue.push(medium.clone());
+        queue.push(high.clone());
+
+        // Removing a task takes it out of the ordering
+        assert_eq!(queue.remove(&high.id).unwrap().id, high.id);
+        assert!(queue.remove(&high.id).is_none());
+        assert_eq!(queue.len(), 2);
+
+        // Raising a task's priority moves it to the front
+        assert!(queue.update(&low.id, |task| task.priority = TaskPriority::Critical));
+        assert_eq!(queue.peek().unwrap().id, low.id);
+
+        // Bulk updates re-key every matching task
+        let updated = queue.update_where(
+            |task| task.id == medium.id,
+            |task| task.priority = TaskPriority::Critical,
+        );
+        assert_eq!(updated, 1);
+        assert_eq!(queue.pop().unwrap().priority, TaskPriority::Critical);
+        assert_eq!(queue.pop().unwrap().priority, TaskPriority::Critical);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_deadline_orderings() {
+        let now = Utc::now();
+        let task = |name: &str, priority, deadline: Option<i64>| {
+            let mut task = Task::new(name.to_string(), serde_json::json!({})).with_priority(priority);
+            task.created_at = now;
+            task.deadline = deadline.map(|seconds| now + Duration::seconds(seconds));
+            task
+        };
+        let tasks = [
+            task("critical", TaskPriority::Critical, None),
+            task("low-soon", TaskPriority::Low, Some(30)),
+            task("high-later", TaskPriority::High, Some(600)),
+            task("medium", TaskPriority::Medium, None),
+        ];
+        let order = |ordering| {
+            let mut queue = PriorityQueue::with_policy(policy_for(&ordering));
+            for task in &tasks {
+                queue.push(task.clone());
+            }
+            std::iter::from_fn(|| queue.pop()).map(|task| task.name).collect::<Vec<_>>()
+        };
+
+        // Earliest deadline first, then the rest by priority
+        assert_eq!(
+            order(QueueOrdering::Deadline),
+            ["low-soon", "high-later", "critical", "medium"]
+        );
+        // Priority implies a deadline of its own, which an explicit one can bring forward
+        assert_eq!(
+            order(QueueOrdering::Weighted(PrioritySlack::default())),
+            ["critical", "low-soon", "high-later", "medium"]
+        );
+        assert_eq!(
+            order(QueueOrdering::PriorityAge),
+            ["critical", "high-later", "medium", "low-soon"]
+        );
+    }
+
+    #[test]
+    fn test_named_queues_keep_their_own_order() {
+        let now = Utc::now();
+        let task = |name: &str, priority, age: i64| {
+            let mut task = Task::new(name.to_string(), serde_json::json!({})).with_priority(priority);
+            task.created_at = now - Duration::seconds(age);
+            task
+        };
+        let old_report = task("report", TaskPriority::High, 30);
+        let new_report = task("report", TaskPriority::Low, 10);
+        let urgent = task("email", TaskPriority::Critical, 0);
+        let medium = task("email", TaskPriority::Medium, 20);
+
+        let named = HashMap::from([("report".to_string(), policy_for(&QueueOrdering::Lifo))]);
+        let mut queue = PriorityQueue::with_policies(Arc::new(ByPriority), named);
+        assert!(!queue.follows_storage_order());
+        for task in [&old_report, &new_report, &urgent, &medium] {
+            queue.push(task.clone());
+        }
+
+        // Reports run newest first whatever their priority, and the first one in line
+        // takes its turn against the other tasks by its own priority
+        let order: Vec<String> = queue.iter().map(|task| task.id.clone()).collect();
+        let ran: Vec<String> = std::iter::from_fn(|| queue.pop()).map(|task| task.id).collect();
+        assert_eq!(ran, [urgent.id, medium.id, new_report.id, old_report.id]);
+        assert_eq!(order, ran);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::models::{TaskEvent, TaskProgress};
+use crate::storage::Database;
+use chrono::Utc;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tracing::warn;
+
+/// Handed to a task's handler to report how far along it is. Every report is
+/// published as an event right away, but storage is only written at most once
+/// per interval; whatever is still unsaved is written by [`ProgressReporter::flush`].
+#[derive(Clone)]
+pub struct ProgressReporter {
+    db: Arc<dyn Database>,
+    events: broadcast::Sender<TaskEvent>,
+    task_id: String,
+    interval: Duration,
+    state: Arc<Mutex<ProgressState>>,
+}
+
+#[derive(Default)]
+struct ProgressState {
+    last_persisted: Option<Instant>,
+    unsaved: Option<TaskProgress>,
+    unsaved_checkpoint: Option<serde_json::Value>,
+}
+
+impl ProgressReporter {
+    pub fn new(
+        db: Arc<dyn Database>,
+        events: broadcast::Sender<TaskEvent>,
+        task_id: String,
+        interval: Duration,
+    ) -> Self {
+        Self {
+            db,
+            events,
+            task_id,
+            interval,
+            state: Arc::new(Mutex::new(ProgressState::default())),
+        }
+    }
+
+    /// Report progress as a percentage between 0 and 100, with an optional message
+    pub async fn report(&self, percent: f64, message: Option<String>) {
+        self.update(percent, message, None).await
+    }
+
+    /// Report progress along with data the handler needs to resume from this
+    /// point if the attempt fails. It is handed back as `Task::checkpoint` on retry.
+    pub async fn checkpoint(&self, percent: f64, message: Option<String>, data: serde_json::Value) {
+        self.update(percent, message, Some(data)).await
+    }
+
+    /// Write any progress not yet saved because of throttling
+    pub async fn flush(&self) {
+        let pending = {
+            let mut state = self.state.lock();
+            state.last_persisted = Some(Instant::now());
+            state
+                .unsaved
+                .take()
+                .map(|progress| (progress, state.unsaved_checkpoint.take()))
+        };
+
+        if let Some((progress, checkpoint)) = pending {
+            self.persist(&progress, checkpoint.as_ref()).await;
+        }
+    }
+
+    async fn update(&self, percent: f64, message: Option<String>, checkpoint: Option<serde_json::Value>) {
+        let progress = TaskProgress {
+            percent: percent.clamp(0.0, 100.0),
+            message,
+            updated_at: Utc::now(),
+        };
+
+        // Nobody listening is fine
+        let _ = self.events.send(TaskEvent::Progress {
+            task_id: self.task_id.clone(),
+            progress: progress.clone(),
+        });
+
+        let due = {
+            let mut state = self.state.lock();
+            state.unsaved = Some(progress);
+            if checkpoint.is_some() {
+                state.unsaved_checkpoint = checkpoint;
+            }
+            state
+                .last_persisted
+                .is_none_or(|at| at.elapsed() >= self.interval)
+        };
+
+        if due {
+            self.flush().await;
+        }
+    }
+
+    async fn persist(&self, progress: &TaskProgress, checkpoint: Option<&serde_json::Value>) {
+        if let Err(e) = self.db.update_task_progress(&self.task_id, progress, checkpoint).await {
+            warn!(task_id = %self.task_id, "Failed to store task progress: {}", e);
+        }
+    }
+}
@@ -0,0 +1,432 @@
+use crate::config::{PrioritySlack, PriorityWeights, QueueOrdering};
+use crate::models::{Task, TaskPriority};
+use chrono::{DateTime, Duration, Utc};
+use parking_lot::Mutex;
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::Arc;
+
+// Tags whose turn has passed are forgotten once a fair share policy tracks this many
+const MAX_TRACKED_TAGS: usize = 1024;
+
+/// Where a policy places a task in the queue. Tasks with smaller keys run first,
+/// compared part by part.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SortKey(pub [i64; 3]);
+
+/// Decides the order in which ready tasks run
+pub trait SchedulingPolicy: Send + Sync {
+    /// Key for a task queued at `now`. The task keeps it while it stays queued.
+    fn sort_key(&self, task: &Task, now: DateTime<Utc>) -> SortKey;
+
+    /// Note that the task with the given key is leaving the queue to run
+    fn dispatched(&self, _task: &Task, _key: &SortKey) {}
+
+    /// Note that the task is leaving the queue without running, e.g. once cancelled
+    fn removed(&self, _task: &Task) {}
+
+    /// Whether tasks run in the order storage hands them out, by priority and then
+    /// age, so that only the first of them need to be held in memory
+    fn follows_storage_order(&self) -> bool {
+        false
+    }
+}
+
+/// Create the policy for a configured ordering
+pub fn policy_for(ordering: &QueueOrdering) -> Arc<dyn SchedulingPolicy> {
+    match ordering {
+        QueueOrdering::PriorityAge => Arc::new(ByPriority),
+        QueueOrdering::Deadline => Arc::new(EarliestDeadline),
+        QueueOrdering::Weighted(slack) => Arc::new(PriorityDeadline::new(*slack)),
+        QueueOrdering::Fifo => Arc::new(Fifo),
+        QueueOrdering::Lifo => Arc::new(Lifo),
+        QueueOrdering::WeightedRandom(weights) => Arc::new(WeightedRandom::new(*weights)),
+        QueueOrdering::FairShare => Arc::new(FairShare::new()),
+    }
+}
+
+fn micros(time: DateTime<Utc>) -> i64 {
+    time.timestamp_micros()
+}
+
+/// By priority, oldest first within a priority
+pub struct ByPriority;
+
+impl SchedulingPolicy for ByPriority {
+    fn sort_key(&self, task: &Task, _now: DateTime<Utc>) -> SortKey {
+        SortKey([task.priority.rank() as i64, micros(task.created_at), 0])
+    }
+
+    fn follows_storage_order(&self) -> bool {
+        true
+    }
+}
+
+/// Oldest first
+pub struct Fifo;
+
+impl SchedulingPolicy for Fifo {
+    fn sort_key(&self, task: &Task, _now: DateTime<Utc>) -> SortKey {
+        SortKey([micros(task.created_at), 0, 0])
+    }
+}
+
+/// Newest first
+pub struct Lifo;
+
+impl SchedulingPolicy for Lifo {
+    fn sort_key(&self, task: &Task, _now: DateTime<Utc>) -> SortKey {
+        SortKey([-micros(task.created_at), 0, 0])
+    }
+}
+
+/// Earliest deadline first, then tasks without one by priority and age
+pub struct EarliestDeadline;
+
+impl SchedulingPolicy for EarliestDeadline {
+    fn sort_key(&self, task: &Task, _now: DateTime<Utc>) -> SortKey {
+        let due = task.deadline.map_or(i64::MAX, micros);
+        SortKey([due, task.priority.rank() as i64, micros(task.created_at)])
+    }
+}
+
+/// Earliest deadline first, where every task is due some time after it was created,
+/// sooner for higher priorities, unless its own deadline is sooner still
+pub struct PriorityDeadline {
+    slack: PrioritySlack,
+}
+
+impl PriorityDeadline {
+    pub fn new(slack: PrioritySlack) -> Self {
+        Self { slack }
+    }
+
+    fn slack_for(&self, priority: &TaskPriority) -> Duration {
+        let seconds = match priority {
+            TaskPriority::Critical => self.slack.critical,
+            TaskPriority::High => self.slack.high,
+            TaskPriority::Medium => self.slack.medium,
+            TaskPriority::Low => self.slack.low,
+        };
+        Duration::seconds(seconds as i64)
+    }
+}
+
+impl SchedulingPolicy for PriorityDeadline {
+    fn sort_key(&self, task: &Task, _now: DateTime<Utc>) -> SortKey {
+        let implied = task.created_at + self.slack_for(&task.priority);
+        let due = task.deadline.map_or(implied, |deadline| deadline.min(implied));
+        SortKey([micros(due), task.priority.rank() as i64, micros(task.created_at)])
+    }
+}
+
+/// At random, where the chance of a task running before the others is in
+/// proportion to the weight of its priority
+pub struct WeightedRandom {
+    weights: PriorityWeights,
+    seed: u64,
+}
+
+impl WeightedRandom {
+    pub fn new(weights: PriorityWeights) -> Self {
+        Self::with_seed(weights, RandomState::new().hash_one(0u64))
+    }
+
+    /// Create the policy with draws fixed by the seed, so that the same tasks always
+    /// run in the same order
+    pub fn with_seed(weights: PriorityWeights, seed: u64) -> Self {
+        Self { weights, seed }
+    }
+
+    fn weight_of(&self, priority: &TaskPriority) -> u32 {
+        match priority {
+            TaskPriority::Critical => self.weights.critical,
+            TaskPriority::High => self.weights.high,
+            TaskPriority::Medium => self.weights.medium,
+            TaskPriority::Low => self.weights.low,
+        }
+    }
+}
+
+impl SchedulingPolicy for WeightedRandom {
+    fn sort_key(&self, task: &Task, _now: DateTime<Utc>) -> SortKey {
+        // Each task draws a number uniformly from (0, 1) and sorts by -ln(draw) / weight,
+        // which puts it first with a chance in proportion to its weight. The draw comes
+        // from the task's ID so the task keeps its place when queued again.
+        let mut hasher = DefaultHasher::new();
+        (self.seed, &task.id).hash(&mut hasher);
+        let hash = hasher.finish();
+        let draw = ((hash >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+        let weight = self.weight_of(&task.priority).max(1) as f64;
+        let wait = -draw.ln() / weight;
+        SortKey([(wait * 1e12) as i64, micros(task.created_at), 0])
+    }
+}
+
+/// Takes turns between tags, by each task's first tag, with untagged tasks sharing
+/// a turn. Within a tag, tasks run in the order they were queued. A task keeps its
+/// turn while it stays queued, even when queued again after a change.
+pub struct FairShare {
+    state: Mutex<FairShareState>,
+}
+
+struct FairShareState {
+    /// Turn of the task that last left the queue
+    current: i64,
+    /// Turn given to the last task queued under each tag
+    last: HashMap<String, i64>,
+    /// Turn of every queued task by ID
+    turns: HashMap<String, i64>,
+}
+
+impl FairShare {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(FairShareState {
+                current: 0,
+                last: HashMap::new(),
+                turns: HashMap::new(),
+            }),
+        }
+    }
+}
+
+impl Default for FairShare {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SchedulingPolicy for FairShare {
+    fn sort_key(&self, task: &Task, _now: DateTime<Utc>) -> SortKey {
+        let mut state = self.state.lock();
+        let turn = match state.turns.get(&task.id) {
+            Some(turn) => *turn,
+            None => {
+                let tag = task.tags.first().map(String::as_str).unwrap_or_default();
+                let current = state.current;
+                // A tag that sat idle starts at the current turn rather than catching up
+                let turn = state
+                    .last
+                    .get(tag)
+                    .map_or(current, |last| (last + 1).max(current));
+                state.last.insert(tag.to_string(), turn);
+                state.turns.insert(task.id.clone(), turn);
+                turn
+            }
+        };
+
+        SortKey([turn, task.priority.rank() as i64, micros(task.created_at)])
+    }
+
+    fn dispatched(&self, task: &Task, key: &SortKey) {
+        let mut state = self.state.lock();
+        state.turns.remove(&task.id);
+        state.current = state.current.max(key.0[0]);
+        if state.last.len() > MAX_TRACKED_TAGS {
+            let current = state.current;
+            state.last.retain(|_, last| *last >= current);
+        }
+    }
+
+    fn removed(&self, task: &Task) {
+        self.state.lock().turns.remove(&task.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::PriorityQueue;
+    use proptest::prelude::*;
+    use std::collections::HashSet;
+
+    const PRIORITIES: [TaskPriority; 4] = [
+        TaskPriority::Critical,
+        TaskPriority::High,
+        TaskPriority::Medium,
+        TaskPriority::Low,
+    ];
+
+    // Tasks with random priorities, ages, deadlines and a tag out of three
+    fn tasks() -> impl Strategy<Value = Vec<Task>> {
+        let task = (0..4usize, 0..3600i64, proptest::option::of(-600..3600i64), 0..4usize);
+        proptest::collection::vec(task, 0..50).prop_map(|specs| {
+            let now = Utc::now();
+            specs
+                .into_iter()
+                .map(|(priority, age, deadline, tag)| {
+                    let mut task = Task::new("work".to_string(), serde_json::json!({}))
+                        .with_priority(PRIORITIES[priority].clone());
+                    task.created_at = now - Duration::seconds(age);
+                    task.deadline = deadline.map(|seconds| now + Duration::seconds(seconds));
+                    if tag > 0 {
+                        task.tags = vec![format!("tag{}", tag)];
+                    }
+                    task
+                })
+                .collect()
+        })
+    }
+
+    fn run_all(policy: Arc<dyn SchedulingPolicy>, tasks: &[Task]) -> Vec<Task> {
+        let mut queue = PriorityQueue::with_policy(policy);
+        for task in tasks {
+            queue.push(task.clone());
+        }
+        std::iter::from_fn(|| queue.pop()).collect()
+    }
+
+    fn assert_runs_each_once(tasks: &[Task], ran: &[Task]) {
+        let queued: HashSet<&str> = tasks.iter().map(|task| task.id.as_str()).collect();
+        let popped: HashSet<&str> = ran.iter().map(|task| task.id.as_str()).collect();
+        assert_eq!(ran.len(), tasks.len());
+        assert_eq!(popped, queued);
+    }
+
+    #[test]
+    fn weighted_random_runs_priorities_first_in_proportion_to_their_weights() {
+        let weights = PriorityWeights::default();
+        let policy = WeightedRandom::with_seed(weights, 42);
+        let now = Utc::now();
+        let task = |id: String, priority: TaskPriority| {
+            let mut task = Task::new("work".to_string(), serde_json::json!({})).with_priority(priority);
+            task.id = id;
+            task.created_at = now;
+            task
+        };
+
+        // Against a low priority task, one with weight w runs first w / (w + 1) of the time
+        const DRAWS: usize = 10_000;
+        for (priority, weight) in [
+            (TaskPriority::Critical, weights.critical),
+            (TaskPriority::High, weights.high),
+            (TaskPriority::Medium, weights.medium),
+            (TaskPriority::Low, weights.low),
+        ] {
+            let first = (0..DRAWS)
+                .filter(|i| {
+                    let ahead = task(format!("ahead-{}", i), priority.clone());
+                    let low = task(format!("low-{}", i), TaskPriority::Low);
+                    policy.sort_key(&ahead, now) < policy.sort_key(&low, now)
+                })
+                .count();
+            let expected = weight as f64 / (weight + weights.low) as f64;
+            let share = first as f64 / DRAWS as f64;
+            assert!(
+                (share - expected).abs() < 0.02,
+                "{:?} ran first {} of the time, expected {}",
+                priority,
+                share,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn fair_share_keeps_turns_when_tasks_are_queued_again() {
+        let task = |tag: &str| {
+            let mut task = Task::new("work".to_string(), serde_json::json!({}));
+            task.tags = vec![tag.to_string()];
+            task
+        };
+        let (a1, a2, b1) = (task("a"), task("a"), task("b"));
+        let mut queue = PriorityQueue::with_policy(Arc::new(FairShare::new()));
+        queue.push(a1.clone());
+        queue.push(a2.clone());
+        queue.push(b1.clone());
+
+        // Changes and window refills queue a task again, which mustn't cost it its turn
+        for _ in 0..3 {
+            assert!(queue.update(&a1.id, |task| task.max_attempts += 1));
+            queue.push(a1.clone());
+        }
+
+        let ran: Vec<String> = std::iter::from_fn(|| queue.pop()).map(|task| task.id).collect();
+        assert_eq!(ran, [a1.id, b1.id, a2.id]);
+    }
+
+    proptest! {
+        #[test]
+        fn fifo_runs_oldest_first(tasks in tasks()) {
+            let ran = run_all(Arc::new(Fifo), &tasks);
+            assert_runs_each_once(&tasks, &ran);
+            prop_assert!(ran.windows(2).all(|pair| pair[0].created_at <= pair[1].created_at));
+        }
+
+        #[test]
+        fn lifo_runs_newest_first(tasks in tasks()) {
+            let ran = run_all(Arc::new(Lifo), &tasks);
+            assert_runs_each_once(&tasks, &ran);
+            prop_assert!(ran.windows(2).all(|pair| pair[0].created_at >= pair[1].created_at));
+        }
+
+        #[test]
+        fn by_priority_runs_by_priority_then_age(tasks in tasks()) {
+            let ran = run_all(Arc::new(ByPriority), &tasks);
+            assert_runs_each_once(&tasks, &ran);
+            let order = |task: &Task| (task.priority.rank(), task.created_at);
+            prop_assert!(ran.windows(2).all(|pair| order(&pair[0]) <= order(&pair[1])));
+        }
+
+        #[test]
+        fn earliest_deadline_runs_by_deadline(tasks in tasks()) {
+            let ran = run_all(Arc::new(EarliestDeadline), &tasks);
+            assert_runs_each_once(&tasks, &ran);
+            let due = |task: &Task| task.deadline.unwrap_or(DateTime::<Utc>::MAX_UTC);
+            prop_assert!(ran.windows(2).all(|pair| due(&pair[0]) <= due(&pair[1])));
+        }
+
+        #[test]
+        fn priority_deadline_never_runs_a_task_after_its_due_time(tasks in tasks()) {
+            let policy = PriorityDeadline::new(PrioritySlack::default());
+            let due = |task: &Task| {
+                let implied = task.created_at + policy.slack_for(&task.priority);
+                task.deadline.map_or(implied, |deadline| deadline.min(implied))
+            };
+            let ran = run_all(Arc::new(PriorityDeadline::new(PrioritySlack::default())), &tasks);
+            assert_runs_each_once(&tasks, &ran);
+            prop_assert!(ran.windows(2).all(|pair| due(&pair[0]) <= due(&pair[1])));
+        }
+
+        #[test]
+        fn weighted_random_favours_higher_priorities(tasks in tasks()) {
+            let policy = WeightedRandom::new(PriorityWeights::default());
+            let now = Utc::now();
+            for task in &tasks {
+                // A task keeps its draw, and moves forward when its priority is raised
+                let key = policy.sort_key(task, now);
+                prop_assert_eq!(policy.sort_key(task, now), key);
+                let raised = task.clone().with_priority(TaskPriority::Critical);
+                prop_assert!(policy.sort_key(&raised, now) <= key);
+            }
+            let ran = run_all(Arc::new(policy), &tasks);
+            assert_runs_each_once(&tasks, &ran);
+        }
+
+        #[test]
+        fn fair_share_takes_turns_between_tags(tasks in tasks()) {
+            let ran = run_all(Arc::new(FairShare::new()), &tasks);
+            assert_runs_each_once(&tasks, &ran);
+
+            let tag = |task: &Task| task.tags.first().cloned().unwrap_or_default();
+            let mut left: HashMap<String, usize> = HashMap::new();
+            for task in &tasks {
+                *left.entry(tag(task)).or_default() += 1;
+            }
+            // No tag gets more than one turn ahead of a tag still waiting
+            let mut taken: HashMap<String, usize> = HashMap::new();
+            for task in &ran {
+                let tag = tag(task);
+                *taken.entry(tag.clone()).or_default() += 1;
+                *left.get_mut(&tag).unwrap() -= 1;
+                let most = taken.values().copied().max().unwrap_or(0);
+                for (waiting, _) in left.iter().filter(|(_, left)| **left > 0) {
+                    let had = taken.get(waiting).copied().unwrap_or(0);
+                    prop_assert!(most <= had + 1);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use crate::models::LogLevel;
+use crate::storage::Database;
+use chrono::Utc;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use tracing::warn;
+
+// Longest single line kept; anything beyond is cut off
+const MAX_LINE_BYTES: usize = 8 * 1024;
+
+/// Logger handed to a task's handler. Every line is stored in the task's log,
+/// up to a size cap per attempt after which further lines are dropped.
+#[derive(Clone)]
+pub struct TaskLogger {
+    db: Arc<dyn Database>,
+    task_id: String,
+    attempt: u32,
+    max_bytes: usize,
+    budget: Arc<Mutex<LogBudget>>,
+}
+
+#[derive(Default)]
+struct LogBudget {
+    written: usize,
+    truncated: bool,
+}
+
+impl TaskLogger {
+    pub fn new(db: Arc<dyn Database>, task_id: String, attempt: u32, max_bytes: usize) -> Self {
+        Self {
+            db,
+            task_id,
+            attempt,
+            max_bytes,
+            budget: Arc::new(Mutex::new(LogBudget::default())),
+        }
+    }
+
+    pub async fn debug(&self, message: impl Into<String>) {
+        self.log(LogLevel::Debug, message).await
+    }
+
+    pub async fn info(&self, message: impl Into<String>) {
+        self.log(LogLevel::Info, message).await
+    }
+
+    pub async fn warn(&self, message: impl Into<String>) {
+        self.log(LogLevel::Warn, message).await
+    }
+
+    pub async fn error(&self, message: impl Into<String>) {
+        self.log(LogLevel::Error, message).await
+    }
+
+    /// Store a line in the task's log. Storage errors are reported but never fail the task.
+    pub async fn log(&self, level: LogLevel, message: impl Into<String>) {
+        let mut message = message.into();
+        truncate_at_char_boundary(&mut message, MAX_LINE_BYTES);
+
+        let (level, message) = {
+            let mut budget = self.budget.lock();
+            if budget.truncated {
+                return;
+            }
+            if budget.written + message.len() > self.max_bytes {
+                budget.truncated = true;
+                (
+                    LogLevel::Warn,
+                    format!("Log truncated after {} bytes", budget.written),
+                )
+            } else {
+                budget.written += message.len();
+                (level, message)
+            }
+        };
+
+        if let Err(e) = self
+            .db
+            .append_task_log(&self.task_id, self.attempt, level, &message, Utc::now())
+            .await
+        {
+            warn!(task_id = %self.task_id, "Failed to store task log line: {}", e);
+        }
+    }
+}
+
+fn truncate_at_char_boundary(s: &mut String, max_len: usize) {
+    if s.len() > max_len {
+        let mut end = max_len;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        s.truncate(end);
+    }
+}
@@ -1,34 +1,73 @@
-use crate::config::QueueConfig;
+use crate::config::{QueueConfig, UnfinishedTasks};
 use crate::error::{AppError, AppResult};
-use crate::models::Task;
-use crate::storage::Database;
+use crate::metrics::Metrics;
+use crate::models::{
+    BulkAction, QueuePosition, Task, TaskEvent, TaskFilter, TaskLogEntry, TaskPriority,
+    TaskState, UpdateTaskRequest,
+};
+use crate::storage::{ChangeEvent, Database, TaskChange};
 use chrono::Utc;
-use crossbeam_channel::{bounded, Receiver, Sender};
-use log::{debug, error, info, warn};
+use tracing::{debug, error, info, info_span, instrument, warn, Instrument};
 use parking_lot::Mutex;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use std::thread;
 use std::time::Duration;
+use tokio::sync::{broadcast, watch, Notify, OwnedSemaphorePermit};
+use tokio::time::Instant;
 use uuid::Uuid;
 
-use super::PriorityQueue;
+use super::{
+    policy_for, Admission, DelayQueue, Dispatcher, ProgressReporter, TaskLogger, Window,
+};
+
+// Events kept for subscribers that haven't caught up yet
+const EVENT_BUFFER_SIZE: usize = 1024;
+
+// Task changes from other instances handled together, loading their tasks in one query
+const CHANGE_BATCH_SIZE: usize = 500;
+
+// How long interrupted tasks get to record their outcome at shutdown
+const INTERRUPT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How often the window is checked for room to load more ready tasks when the
+// dispatcher hasn't asked, e.g. after tasks were removed from it, and how long
+// to wait after loading failed
+const REFILL_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How a task execution ended
+enum Outcome {
+    Finished(serde_json::Value),
+    /// Wrapped up when told its time was up
+    TimedOut,
+    /// Still running when the grace period after its timeout ran out
+    Aborted,
+    /// Stopped by shutdown before it finished
+    Interrupted,
+}
 
 pub struct TaskQueue {
     /// Database connection
     db: Arc<dyn Database>,
     /// Queue configuration
     config: QueueConfig,
-    /// In-memory priority queue for pending tasks
-    pending_queue: Arc<Mutex<PriorityQueue>>,
+    /// Hands ready tasks to workers in priority order
+    dispatcher: Dispatcher,
+    /// Turns away submissions while too many tasks are pending
+    admission: Arc<Admission>,
     /// Currently processing tasks
     processing: Arc<Mutex<HashMap<String, Task>>>,
-    /// Channel for submitting tasks
-    task_sender: Sender<Task>,
-    /// Channel for receiving tasks
-    task_receiver: Receiver<Task>,
     /// Worker ID for this queue instance
     worker_id: String,
+    /// Prometheus metrics
+    metrics: Arc<Metrics>,
+    /// Publishes events about running tasks to any subscribers
+    events: broadcast::Sender<TaskEvent>,
+    /// Scheduled tasks due within the lookahead window, waiting for their time
+    delayed: Arc<Mutex<DelayQueue>>,
+    /// Interrupts the scheduler's sleep when a task due sooner is delayed
+    scheduler_wake: Arc<Notify>,
+    /// Set at shutdown once running tasks have had their grace period
+    interrupt: Arc<watch::Sender<bool>>,
 }
 
 impl Clone for TaskQueue {
@@ -36,71 +75,207 @@ impl Clone for TaskQueue {
         Self {
             db: self.db.clone(),
             config: self.config.clone(),
-            pending_queue: self.pending_queue.clone(),
+            dispatcher: self.dispatcher.clone(),
+            admission: self.admission.clone(),
             processing: self.processing.clone(),
-            task_sender: self.task_sender.clone(),
-            task_receiver: self.task_receiver.clone(),
             worker_id: self.worker_id.clone(),
+            metrics: self.metrics.clone(),
+            events: self.events.clone(),
+            delayed: self.delayed.clone(),
+            scheduler_wake: self.scheduler_wake.clone(),
+            interrupt: self.interrupt.clone(),
         }
     }
 }
 
 impl TaskQueue {
     /// Create a new task queue
-    pub fn new(db: Arc<dyn Database>, config: QueueConfig) -> Self {
-        let (task_sender, task_receiver) = bounded(config.max_concurrent_tasks * 2);
+    pub fn new(db: Arc<dyn Database>, config: QueueConfig, metrics: Arc<Metrics>) -> Self {
+        let orderings = config
+            .orderings
+            .iter()
+            .map(|(name, ordering)| (name.clone(), policy_for(ordering)))
+            .collect();
+        let window = Window::with_policies(
+            config.prefetch_window_size,
+            policy_for(&config.ordering),
+            orderings,
+        );
+        let dispatcher = Dispatcher::with_window(config.max_concurrent_tasks, window);
+        let admission = Admission::new(
+            config.max_pending_tasks,
+            config.max_pending_tasks_per_tenant,
+            metrics.clone(),
+        );
         let worker_id = Uuid::new_v4().to_string();
+        let (events, _) = broadcast::channel(EVENT_BUFFER_SIZE);
         
         Self {
             db,
             config,
-            pending_queue: Arc::new(Mutex::new(PriorityQueue::new())),
+            dispatcher,
+            admission: Arc::new(admission),
             processing: Arc::new(Mutex::new(HashMap::new())),
-            task_sender,
-            task_receiver,
             worker_id,
+            metrics,
+            events,
+            delayed: Arc::new(Mutex::new(DelayQueue::new())),
+            scheduler_wake: Arc::new(Notify::new()),
+            interrupt: Arc::new(watch::Sender::new(false)),
+        }
+    }
+
+    /// Get the queue configuration
+    pub fn config(&self) -> &QueueConfig {
+        &self.config
+    }
+
+    /// Get the metrics shared by the queue and storage
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Subscribe to events about running tasks. Subscribers that fall too far
+    /// behind miss the oldest events.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<TaskEvent> {
+        self.events.subscribe()
+    }
+
+    /// Refresh the gauges describing the queue's state and render all metrics
+    pub async fn render_metrics(&self) -> AppResult<String> {
+        // Waiting tasks are counted in storage, since only the first of them are in memory
+        let at_risk_by =
+            Utc::now() + chrono::Duration::seconds(self.config.deadline_risk_seconds as i64);
+        let mut at_risk = 0;
+        for state in [TaskState::Pending, TaskState::Scheduled] {
+            let filter = TaskFilter {
+                deadline_before: Some(at_risk_by),
+                ..TaskFilter::default()
+            };
+            at_risk += self.db.count_tasks(&filter.with_state(state.to_string())).await?;
         }
+        self.metrics.tasks_at_deadline_risk.set(at_risk);
+
+        self.metrics
+            .pending_queue_depth
+            .set(self.dispatcher.window().lock().len() as i64);
+        self.metrics.processing_tasks.set(self.processing.lock().len() as i64);
+        self.metrics
+            .channel_occupancy
+            .set(self.dispatcher.channel_occupancy() as i64);
+        self.metrics
+            .channel_capacity
+            .set(self.dispatcher.channel_capacity() as i64);
+        self.metrics.render()
     }
 
     /// Start the queue processing loop
     pub async fn start(&self) -> AppResult<()> {
         info!("Starting task queue with worker ID: {}", self.worker_id);
+
+        // Load the first ready tasks from the database, then more as they run
+        self.dispatcher.window().lock().reload();
+        let loaded = self.refill_window().await?;
+        info!("Loaded {} ready tasks into memory", loaded);
+        self.start_refiller();
         
-        // Load any existing pending and scheduled tasks from the database
-        self.load_existing_tasks().await?;
-        
-        // Start the scheduler loop in a separate thread
+        // Start the scheduler loop in a separate task
         self.start_scheduler();
         
+        // Pick up tasks submitted or changed by other instances as they happen
+        self.start_change_listener().await;
+        
         // Start the retry loop in a separate thread
         self.start_retry_handler();
         
+        // Expire tasks that waited too long, wherever they are waiting
+        self.start_expiry_sweep();
+        
         // Start the task processing loop
         self.process_tasks().await?;
         
         Ok(())
     }
 
+    /// Stop claiming tasks and wait up to `grace` for running ones to finish. Tasks
+    /// still running after that are interrupted and requeued or failed as configured.
+    /// Returns true if every running task finished within the grace period.
+    pub async fn shutdown(&self, grace: Duration) -> bool {
+        info!("Stopping task dispatch, waiting up to {:?} for running tasks", grace);
+        self.dispatcher.stop();
+        
+        if self.dispatcher.drain(grace).await {
+            info!("All running tasks finished");
+            return true;
+        }
+        
+        warn!(
+            "{} tasks still running after the grace period, interrupting them",
+            self.processing.lock().len()
+        );
+        self.interrupt.send_replace(true);
+        
+        // Interrupted executions only have to record their outcome
+        if !self.dispatcher.drain(INTERRUPT_TIMEOUT).await {
+            error!("Some interrupted tasks didn't record their outcome in time");
+        }
+        false
+    }
+
     /// Submit a new task to the queue
+    #[instrument(
+        name = "submit",
+        skip_all,
+        fields(task_id = %task.id, name = %task.name, trace_id = task.trace_id.as_deref())
+    )]
     pub async fn submit_task(&self, task: Task) -> AppResult<()> {
         debug!("Submitting task: {} ({})", task.name, task.id);
         
+        // Turn the task away before storing it if too many are pending
+        self.admission.admit(self.db.as_ref(), std::slice::from_ref(&task)).await?;
+        
         // Save the task to the database first
         self.db.create_task(&task).await?;
+        self.metrics.record_submitted(&task);
         
-        // If the task is scheduled for the future, don't add it to the in-memory queue
-        if let Some(scheduled_at) = task.scheduled_at {
-            if scheduled_at > Utc::now() {
-                return Ok(());
-            }
+        // If the task is scheduled for the future, the scheduler queues it when it is due
+        if task.scheduled_at.is_some_and(|at| at > Utc::now()) {
+            self.delay(task);
+            return Ok(());
         }
         
         // Add to the in-memory queue
-        if self.task_sender.send(task).is_err() {
-            return Err(AppError::QueueFull);
+        self.enqueue_all(vec![task])
+    }
+
+    /// Submit many tasks at once. All of them are stored in a single transaction
+    /// before any is queued.
+    #[instrument(name = "submit_batch", skip_all, fields(count = tasks.len()))]
+    pub async fn submit_tasks(&self, tasks: Vec<Task>) -> AppResult<()> {
+        debug!("Submitting batch of {} tasks", tasks.len());
+        
+        self.admission.admit(self.db.as_ref(), &tasks).await?;
+        self.db.create_tasks(&tasks).await?;
+        for task in &tasks {
+            self.metrics.record_submitted(task);
         }
         
-        Ok(())
+        let now = Utc::now();
+        let mut due = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            match task.scheduled_at {
+                // Scheduled tasks are picked up by the scheduler when they are due
+                Some(at) if at > now => self.delay(task),
+                _ => due.push(task),
+            }
+        }
+        
+        self.enqueue_all(due)
+    }
+
+    /// Queue tasks that are already stored and ready to run
+    fn enqueue_all(&self, tasks: Vec<Task>) -> AppResult<()> {
+        self.dispatcher.enqueue(tasks)
     }
 
     /// Cancel a task by ID
@@ -108,7 +283,7 @@ impl TaskQueue {
         let mut task = self.db.get_task(task_id).await?;
         
         // Can only cancel tasks that are not yet completed
-        if matches!(task.state, crate::models::TaskState::Completed) {
+        if matches!(task.state, TaskState::Completed) {
             return Err(AppError::InvalidStateTransition { 
                 from: task.state.to_string(), 
                 to: "cancelled".to_string() 
@@ -117,6 +292,10 @@ impl TaskQueue {
         
         task.mark_cancelled();
         self.db.update_task(&task).await?;
+        self.metrics.record_cancelled(&task);
+        
+        self.dispatcher.window().lock().remove(task_id);
+        self.delayed.lock().remove(task_id);
         
         // If the task is currently processing, we need to remove it
         {
@@ -127,79 +306,498 @@ impl TaskQueue {
         Ok(())
     }
 
-    /// Get a task by ID
-    pub async fn get_task(&self, task_id: &str) -> AppResult<Task> {
-        self.db.get_task(task_id).await
+    /// Requeue a failed or cancelled task, optionally resetting its attempt count
+    pub async fn retry_task(&self, task_id: &str, reset_attempts: bool) -> AppResult<Task> {
+        let filter = TaskFilter::default().with_id(task_id);
+        
+        match self.db.retry_tasks(&filter, reset_attempts).await?.pop() {
+            Some(task) => {
+                self.enqueue_all(vec![task.clone()])?;
+                Ok(task)
+            }
+            None => {
+                let task = self.db.get_task(task_id).await?;
+                Err(AppError::InvalidStateTransition {
+                    from: task.state.to_string(),
+                    to: TaskState::Pending.to_string(),
+                })
+            }
+        }
     }
 
-    /// Load existing pending and scheduled tasks from the database
-    async fn load_existing_tasks(&self) -> AppResult<()> {
-        info!("Loading existing tasks from database...");
+    /// Change the priority, schedule, attempt limit or tags of a task that isn't running
+    pub async fn update_task(&self, task_id: &str, changes: UpdateTaskRequest) -> AppResult<Task> {
+        let mut task = self.db.get_task(task_id).await?;
+        if task.state == TaskState::Running {
+            return Err(AppError::TaskRunning(task_id.to_string()));
+        }
         
-        // Load pending tasks
-        let tasks = self.db.get_tasks(Some("pending"), None, None, None).await?;
-        let mut pending_queue = self.pending_queue.lock();
+        let previous_state = task.state.clone();
+        changes.apply(&mut task)?;
         
-        for task in tasks {
-            debug!("Loading pending task: {} ({})", task.name, task.id);
-            pending_queue.push(task);
+        // The dispatcher may claim the task between the read and the write
+        if !self.db.update_task_if_state(&task, &previous_state).await? {
+            let current = self.db.get_task(task_id).await?;
+            return Err(match current.state {
+                TaskState::Running => AppError::TaskRunning(task_id.to_string()),
+                _ => AppError::InvalidRequest(format!(
+                    "Task {} was modified concurrently, please retry", task_id
+                )),
+            });
         }
         
-        // Load scheduled tasks that are due now
-        let now = Utc::now();
-        let scheduled_tasks = self.db.get_scheduled_tasks(now).await?;
-        
-        for task in scheduled_tasks {
-            debug!("Loading scheduled task: {} ({})", task.name, task.id);
-            pending_queue.push(task);
+        // Keep the in-memory queues in line with the stored task
+        if task.is_ready_to_run() {
+            self.delayed.lock().remove(task_id);
+            // The task moves to its new place in line, which may be back in storage
+            let mut window = self.dispatcher.window().lock();
+            window.remove(task_id);
+            window.push(task.clone());
+        } else {
+            self.dispatcher.window().lock().remove(task_id);
+            if task.state == TaskState::Scheduled {
+                self.delay(task.clone());
+            } else {
+                self.delayed.lock().remove(task_id);
+            }
         }
         
-        info!("Loaded {} tasks into memory", pending_queue.len());
+        Ok(task)
+    }
+
+    /// Delete a task that isn't running
+    pub async fn delete_task(&self, task_id: &str) -> AppResult<()> {
+        let filter = TaskFilter::default().with_id(task_id);
+        
+        if self.db.delete_tasks(&filter).await?.is_empty() {
+            // Either the task doesn't exist, which get_task reports, or it is running
+            self.db.get_task(task_id).await?;
+            return Err(AppError::TaskRunning(task_id.to_string()));
+        }
         
+        self.dispatcher.window().lock().remove(task_id);
+        self.delayed.lock().remove(task_id);
         Ok(())
     }
 
-    /// Start the scheduler loop to check for scheduled tasks
+    /// Cancel every pending or scheduled task matching the filter
+    pub async fn cancel_tasks(&self, filter: &TaskFilter) -> AppResult<Vec<String>> {
+        self.apply_bulk_action(BulkAction::Cancel, filter, None, false).await
+    }
+
+    /// Change the priority of every not-yet-started task matching the filter
+    pub async fn reprioritize_tasks(
+        &self,
+        filter: &TaskFilter,
+        priority: TaskPriority,
+    ) -> AppResult<Vec<String>> {
+        self.apply_bulk_action(BulkAction::Reprioritize, filter, Some(priority), false).await
+    }
+
+    /// Apply a bulk action to every eligible task matching the filter and return the IDs
+    /// of the tasks it changed. `priority` is required for reprioritization and
+    /// `reset_attempts` only affects retries.
+    pub async fn apply_bulk_action(
+        &self,
+        action: BulkAction,
+        filter: &TaskFilter,
+        priority: Option<TaskPriority>,
+        reset_attempts: bool,
+    ) -> AppResult<Vec<String>> {
+        let ids = match action {
+            BulkAction::Cancel => {
+                let tasks = self.db.cancel_tasks(filter).await?;
+                for task in &tasks {
+                    self.metrics.record_cancelled(task);
+                }
+                let ids: Vec<String> = tasks.into_iter().map(|task| task.id).collect();
+                self.remove_from_pending(&ids);
+                ids
+            }
+            BulkAction::Delete => {
+                let ids = self.db.delete_tasks(filter).await?;
+                self.remove_from_pending(&ids);
+                ids
+            }
+            BulkAction::Reprioritize => {
+                let priority = priority.ok_or_else(|| {
+                    AppError::InvalidRequest("priority is required to reprioritize tasks".to_string())
+                })?;
+                let ids = self.db.reprioritize_tasks(filter, &priority).await?;
+                let changed: HashSet<&str> = ids.iter().map(String::as_str).collect();
+                let mut window = self.dispatcher.window().lock();
+                let updated = window.update_where(
+                    |task| changed.contains(task.id.as_str()),
+                    |task| task.priority = priority.clone(),
+                );
+                // Tasks still in storage may now come before some in the window
+                if updated < ids.len() {
+                    window.moved_up(QueuePosition {
+                        priority_rank: priority.rank(),
+                        created_second: i64::MIN,
+                    });
+                }
+                drop(window);
+                ids
+            }
+            BulkAction::Retry => {
+                let tasks = self.db.retry_tasks(filter, reset_attempts).await?;
+                let ids = tasks.iter().map(|task| task.id.clone()).collect();
+                self.enqueue_all(tasks)?;
+                ids
+            }
+        };
+        
+        info!("Bulk {} affected {} tasks", action, ids.len());
+        Ok(ids)
+    }
+
+    /// Drop tasks from the in-memory queues, e.g. after they were cancelled or deleted
+    fn remove_from_pending(&self, ids: &[String]) {
+        let removed: HashSet<&str> = ids.iter().map(String::as_str).collect();
+        self.dispatcher.window().lock().retain(|task| !removed.contains(task.id.as_str()));
+        
+        let mut delayed = self.delayed.lock();
+        for id in ids {
+            delayed.remove(id);
+        }
+    }
+
+    /// Move the tasks matching the filter that expired while waiting to run to
+    /// expired, and drop them from the in-memory queues. Returns how many expired.
+    async fn expire(&self, filter: &TaskFilter) -> AppResult<usize> {
+        let tasks = self.db.expire_tasks(filter, Utc::now()).await?;
+        for task in &tasks {
+            debug!("Task expired before it could run: {} ({})", task.name, task.id);
+            self.metrics.record_expired(task);
+        }
+        let ids: Vec<String> = tasks.into_iter().map(|task| task.id).collect();
+        self.remove_from_pending(&ids);
+        Ok(ids.len())
+    }
+
+    /// Start the loop expiring tasks whose time passed while they waited, e.g.
+    /// tasks scheduled too late or held up behind others
+    fn start_expiry_sweep(&self) {
+        let queue = self.clone();
+        let interval = Duration::from_secs(self.config.expiry_sweep_interval_seconds.max(1));
+        
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                match queue.expire(&TaskFilter::default()).await {
+                    Ok(0) => {}
+                    Ok(count) => info!("Expired {} tasks that waited too long", count),
+                    Err(e) => error!("Error expiring tasks: {}", e),
+                }
+            }
+        });
+    }
+
+    /// Count the tasks a bulk action would affect without changing anything
+    pub async fn count_bulk_action_matches(
+        &self,
+        action: BulkAction,
+        filter: &TaskFilter,
+    ) -> AppResult<i64> {
+        let eligible = action.eligible_states();
+        
+        match &filter.state {
+            Some(state) => {
+                if eligible.iter().any(|s| s.to_string() == *state) {
+                    self.db.count_tasks(filter).await
+                } else {
+                    Ok(0)
+                }
+            }
+            None => {
+                let mut total = 0;
+                for state in eligible {
+                    total += self.db.count_tasks(&filter.clone().with_state(state.to_string())).await?;
+                }
+                Ok(total)
+            }
+        }
+    }
+
+    /// Get a task by ID
+    pub async fn get_task(&self, task_id: &str) -> AppResult<Task> {
+        self.db.get_task(task_id).await
+    }
+
+    /// Get a task's log lines written after `after_seq`
+    pub async fn get_task_logs(
+        &self,
+        task_id: &str,
+        after_seq: Option<i64>,
+        limit: u32,
+    ) -> AppResult<Vec<TaskLogEntry>> {
+        self.db.get_task_logs(task_id, after_seq, limit).await
+    }
+
+    /// Load ready tasks from the database into the dispatcher's window, if it has
+    /// drained far enough. Returns the number of tasks loaded.
+    async fn refill_window(&self) -> AppResult<usize> {
+        let Some(request) = self.dispatcher.window().lock().begin_refill() else {
+            return Ok(0);
+        };
+        
+        match self.db.get_ready_tasks(request.from, Utc::now(), request.limit).await {
+            Ok(tasks) => {
+                let loaded = tasks.len();
+                debug!("Loaded {} ready tasks into memory", loaded);
+                self.dispatcher.window().lock().finish_refill(tasks);
+                self.dispatcher.refilled();
+                Ok(loaded)
+            }
+            Err(e) => {
+                self.dispatcher.window().lock().abort_refill();
+                Err(e)
+            }
+        }
+    }
+
+    /// Start the loop refilling the dispatcher's window from the database whenever
+    /// it drains
+    fn start_refiller(&self) {
+        let queue = self.clone();
+        
+        tokio::spawn(async move {
+            loop {
+                match queue.refill_window().await {
+                    // More may be wanted straight away
+                    Ok(loaded) if loaded > 0 => continue,
+                    Ok(_) => {
+                        let _ = tokio::time::timeout(
+                            REFILL_CHECK_INTERVAL,
+                            queue.dispatcher.refill_wanted(),
+                        )
+                        .await;
+                    }
+                    Err(e) => {
+                        error!("Error loading ready tasks: {}", e);
+                        tokio::time::sleep(REFILL_CHECK_INTERVAL).await;
+                    }
+                }
+            }
+        });
+    }
+
+    /// Hold a scheduled task until it is due, if it is due within the lookahead
+    /// window. Later tasks are loaded by the scheduler as the window moves.
+    fn delay(&self, task: Task) {
+        let horizon = Utc::now() + self.lookahead();
+        if task.scheduled_at.is_some_and(|at| at > horizon) {
+            self.delayed.lock().remove(&task.id);
+            return;
+        }
+        
+        if self.delayed.lock().insert(task) {
+            self.scheduler_wake.notify_one();
+        }
+    }
+
+    /// Seconds the task may run before it is told to wrap up: its own timeout, else
+    /// the one configured for its name, else the default
+    fn timeout_for(&self, task: &Task) -> u64 {
+        task.timeout_seconds
+            .or_else(|| self.config.task_timeouts.get(&task.name).copied())
+            .unwrap_or(self.config.task_timeout_seconds)
+    }
+
+    /// How far ahead the scheduler loads scheduled tasks. It is at least the poll
+    /// interval, so every task is loaded before it is due.
+    fn lookahead(&self) -> chrono::Duration {
+        let seconds = self
+            .config
+            .scheduler_lookahead_seconds
+            .max(self.config.scheduler_poll_interval_seconds);
+        chrono::Duration::seconds(seconds as i64)
+    }
+
+    /// Start the scheduler loop. It reloads the scheduled tasks due within the
+    /// lookahead window every poll interval and queues each one the moment it is due.
+    /// It also reloads the ready tasks, to run those no announcement told it about.
     fn start_scheduler(&self) {
-        let db = self.db.clone();
-        let task_sender = self.task_sender.clone();
+        let queue = self.clone();
+        let poll_interval = Duration::from_secs(self.config.scheduler_poll_interval_seconds);
         
-        thread::spawn(move || {
+        tokio::spawn(async move {
+            let mut next_refresh = Instant::now();
+            // The ready tasks were just loaded as the queue started
+            let mut reload = false;
+            
             loop {
-                thread::sleep(Duration::from_secs(15));
-                
-                // Check for scheduled tasks that are due
-                let now = Utc::now();
+                if Instant::now() >= next_refresh {
+                    if reload {
+                        queue.reload_window().await;
+                    }
+                    reload = true;
+                    queue.refresh_delayed().await;
+                    next_refresh = Instant::now() + poll_interval;
+                }
                 
-                match tokio::runtime::Runtime::new() {
-                    Ok(rt) => {
-                        match rt.block_on(db.get_scheduled_tasks(now)) {
-                            Ok(tasks) => {
-                                for task in tasks {
-                                    debug!("Scheduling due task: {} ({})", task.name, task.id);
-                                    
-                                    if task_sender.send(task).is_err() {
-                                        error!("Failed to schedule task: Queue is full");
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                error!("Error fetching scheduled tasks: {}", e);
-                            }
-                        }
+                let due = queue.delayed.lock().pop_due(Utc::now());
+                if !due.is_empty() {
+                    for task in &due {
+                        debug!("Scheduling due task: {} ({})", task.name, task.id);
                     }
-                    Err(e) => {
-                        error!("Failed to create tokio runtime for scheduler: {}", e);
+                    if let Err(e) = queue.enqueue_all(due) {
+                        error!("Failed to schedule tasks: {}", e);
                     }
                 }
+                
+                // Sleep until the next task is due or the window has to be reloaded
+                let until_refresh = next_refresh.saturating_duration_since(Instant::now());
+                let next_due = queue.delayed.lock().next_due();
+                let wait = next_due.map_or(until_refresh, |at| {
+                    (at - Utc::now()).to_std().unwrap_or_default().min(until_refresh)
+                });
+                
+                tokio::select! {
+                    _ = tokio::time::sleep(wait) => {}
+                    // A task due sooner was delayed
+                    _ = queue.scheduler_wake.notified() => {}
+                }
             }
         });
     }
 
+    /// Load the ready tasks from storage again, picking up those stored without this
+    /// instance hearing of it, e.g. by another instance while the announcement was
+    /// missed, or left behind by an instance that stopped
+    async fn reload_window(&self) {
+        self.dispatcher.window().lock().reload();
+        if let Err(e) = self.refill_window().await {
+            error!("Error reloading ready tasks: {}", e);
+        }
+    }
+
+    /// Load the scheduled tasks due within the lookahead window into the delay queue
+    async fn refresh_delayed(&self) {
+        let horizon = Utc::now() + self.lookahead();
+        
+        match self.db.get_scheduled_tasks(horizon).await {
+            Ok(tasks) => {
+                let mut delayed = self.delayed.lock();
+                for task in tasks {
+                    delayed.insert(task);
+                }
+                debug!("{} scheduled tasks due within the lookahead window", delayed.len());
+            }
+            Err(e) => {
+                error!("Error fetching scheduled tasks: {}", e);
+            }
+        }
+    }
+
+    /// Listen for tasks inserted or changed by other instances, if the storage
+    /// announces them, and reload them when announcements were missed. Otherwise
+    /// the queue only learns about them by polling.
+    async fn start_change_listener(&self) {
+        let mut changes = match self.db.watch_changes().await {
+            Ok(Some(changes)) => changes,
+            Ok(None) => {
+                debug!("Storage doesn't announce task changes, relying on polling");
+                return;
+            }
+            Err(e) => {
+                warn!("Failed to listen for task changes, relying on polling: {}", e);
+                return;
+            }
+        };
+        
+        info!("Listening for task changes from other instances");
+        let queue = self.clone();
+        tokio::spawn(async move {
+            let mut batch = Vec::with_capacity(CHANGE_BATCH_SIZE);
+            while changes.recv_many(&mut batch, CHANGE_BATCH_SIZE).await > 0 {
+                let mut missed = false;
+                let changed = batch
+                    .drain(..)
+                    .filter_map(|event| match event {
+                        ChangeEvent::Changed(change) => Some(change),
+                        ChangeEvent::Missed => {
+                            missed = true;
+                            None
+                        }
+                    })
+                    .collect();
+                queue.apply_changes(changed).await;
+                if missed {
+                    debug!("Task changes were missed, reloading ready tasks");
+                    queue.reload_window().await;
+                    queue.refresh_delayed().await;
+                }
+            }
+        });
+    }
+
+    /// Bring the in-memory queues in line with changes made by other instances
+    async fn apply_changes(&self, changes: Vec<TaskChange>) {
+        let horizon = Utc::now() + self.lookahead();
+        let mut ready = Vec::new();
+        let mut delayed = Vec::new();
+        let mut gone = Vec::new();
+        
+        for change in changes {
+            match change.state {
+                TaskState::Pending => ready.push(change.id),
+                TaskState::Scheduled if change.scheduled_at.is_none_or(|at| at <= horizon) => {
+                    delayed.push(change.id)
+                }
+                // Scheduled beyond the window, claimed, finished or cancelled elsewhere
+                _ => gone.push(change.id),
+            }
+        }
+        
+        self.remove_from_pending(&gone);
+        
+        {
+            let window = self.dispatcher.window().lock();
+            ready.retain(|id| !window.contains(id));
+        }
+        
+        // Another instance may claim the tasks before they get here, in which case
+        // claiming them fails and they are skipped
+        let ready = self.load_changed(ready, TaskState::Pending).await;
+        if let Err(e) = self.enqueue_all(ready) {
+            error!("Failed to queue tasks submitted elsewhere: {}", e);
+        }
+        for task in self.load_changed(delayed, TaskState::Scheduled).await {
+            self.delay(task);
+        }
+    }
+
+    /// Load the tasks with the given IDs that are still in the given state
+    async fn load_changed(&self, ids: Vec<String>, state: TaskState) -> Vec<Task> {
+        if ids.is_empty() {
+            return Vec::new();
+        }
+        
+        let filter = TaskFilter {
+            ids,
+            state: Some(state.to_string()),
+            ..Default::default()
+        };
+        match self.db.get_tasks(&filter, None, None).await {
+            Ok(tasks) => {
+                debug!("Loaded {} {} tasks changed elsewhere", tasks.len(), state);
+                tasks
+            }
+            Err(e) => {
+                error!("Error loading tasks changed elsewhere: {}", e);
+                Vec::new()
+            }
+        }
+    }
+
     /// Start the retry handler loop to check for failed tasks that need to be retried
     fn start_retry_handler(&self) {
         let db = self.db.clone();
-        let task_sender = self.task_sender.clone();
+        let dispatcher = self.dispatcher.clone();
+        let metrics = self.metrics.clone();
         let initial_interval = self.config.retry_initial_interval_ms;
         
         tokio::spawn(async move {
@@ -218,7 +816,43 @@ impl TaskQueue {
                             info!("Found {} failed tasks to retry", tasks.len());
                             
                             for mut task in tasks {
-                                // Process task code...
+                                // A task that expired while waiting for its retry won't run again
+                                let now = Utc::now();
+                                if task.is_expired(now) {
+                                    let filter = TaskFilter::default().with_id(&task.id);
+                                    match db.expire_tasks(&filter, now).await {
+                                        Ok(expired) => {
+                                            for task in &expired {
+                                                debug!("Not retrying expired task: {} ({})", task.name, task.id);
+                                                metrics.record_expired(task);
+                                            }
+                                        }
+                                        Err(e) => error!("Failed to expire task {}: {}", task.id, e),
+                                    }
+                                    continue;
+                                }
+                                
+                                // Put the task back in line, keeping its attempt count. It may have
+                                // been cancelled, deleted or retried since it was read.
+                                task.state = TaskState::Pending;
+                                task.updated_at = Utc::now();
+                                
+                                match db.update_task_if_state(&task, &TaskState::Failed).await {
+                                    Ok(true) => {}
+                                    Ok(false) => {
+                                        debug!("Not retrying task that is no longer failed: {}", task.id);
+                                        continue;
+                                    }
+                                    Err(e) => {
+                                        error!("Failed to requeue task {}: {}", task.id, e);
+                                        continue;
+                                    }
+                                }
+                                
+                                debug!("Retrying task: {} ({})", task.name, task.id);
+                                if dispatcher.enqueue([task]).is_err() {
+                                    error!("Failed to requeue task: Queue is full");
+                                }
                             }
                         }
                         // Reset interval on success
@@ -247,55 +881,46 @@ impl TaskQueue {
     async fn process_tasks(&self) -> AppResult<()> {
         info!("Starting task processing loop");
         
-        loop {
-            // Process all tasks in the channel
-            while let Ok(task) = self.task_receiver.try_recv() {
-                self.process_task(task).await?;
-            }
-            
-            // Check if we can process more tasks
-            {
-                let processing = self.processing.lock();
-                if processing.len() >= self.config.max_concurrent_tasks {
-                    // We're at capacity, wait a bit before checking again
-                    tokio::time::sleep(Duration::from_millis(100)).await;
-                    continue;
-                }
-            }
-            
-            // Try to get the next task from the priority queue
-            let task = {
-                let mut pending_queue = self.pending_queue.lock();
-                pending_queue.pop()
-            };
-            
-            if let Some(task) = task {
-                self.process_task(task).await?;
-            } else {
-                // No tasks in the queue, wait for tasks to be submitted
-                match self.task_receiver.recv() {
-                    Ok(task) => {
-                        self.process_task(task).await?;
-                    }
-                    Err(e) => {
-                        error!("Channel error: {}", e);
-                        // Sleep a bit before retrying
-                        tokio::time::sleep(Duration::from_millis(1000)).await;
-                    }
-                }
-            }
-        }
+        self.dispatcher
+            .run(|task, permit| self.process_task(task, permit))
+            .await
     }
 
-    /// Process a single task
-    async fn process_task(&self, mut task: Task) -> AppResult<()> {
+    /// Process a single task, holding the worker permit until it finishes
+    async fn process_task(&self, task: Task, permit: OwnedSemaphorePermit) -> AppResult<()> {
         debug!("Processing task: {} ({})", task.name, task.id);
         
-        // Mark the task as running
-        task.mark_running(self.worker_id.clone());
+        // A task past its expiry time is never started
+        if task.is_expired(Utc::now()) {
+            if let Err(e) = self.expire(&TaskFilter::default().with_id(&task.id)).await {
+                error!("Failed to expire task {}: {}", task.id, e);
+            }
+            return Ok(());
+        }
         
-        // Update the task in the database
-        self.db.update_task(&task).await?;
+        // Claim the task in the database, which marks it as running. The in-memory
+        // copy may be stale, e.g. if the task was cancelled or reprioritized since it was queued.
+        let claim_span = info_span!(
+            "claim",
+            task_id = %task.id,
+            name = %task.name,
+            worker_id = %self.worker_id,
+            trace_id = task.trace_id.as_deref(),
+        );
+        let claimed = self
+            .db
+            .claim_task(&task.id, &self.worker_id)
+            .instrument(claim_span)
+            .await?;
+        let task = match claimed {
+            Some(task) => task,
+            None => {
+                debug!("Skipping task that is no longer waiting to run: {}", task.id);
+                return Ok(());
+            }
+        };
+        self.metrics.record_claimed(&task);
+        self.admission.record_started();
         
         // Add to processing list
         {
@@ -303,60 +928,173 @@ impl TaskQueue {
             processing.insert(task.id.clone(), task.clone());
         }
         
+        // The execution span carries the trace ID of the request that created the task,
+        // so its logs can be correlated with that request even though it runs detached
+        let execute_span = info_span!(
+            parent: None,
+            "execute",
+            task_id = %task.id,
+            name = %task.name,
+            attempt = task.attempts,
+            worker_id = %self.worker_id,
+            trace_id = task.trace_id.as_deref(),
+        );
+
         // Simulate task execution (in a real system, this would be replaced with actual task handling)
         tokio::spawn({
             let task_id = task.id.clone();
             let db = self.db.clone();
             let processing = self.processing.clone();
-            let timeout = self.config.task_timeout_seconds;
+            let timeout = self.timeout_for(&task);
+            let grace = self.config.task_timeout_grace_seconds;
+            let unfinished = self.config.shutdown_unfinished;
+            let mut interrupt = self.interrupt.subscribe();
+            let metrics = self.metrics.clone();
+            // Failed attempts are counted when they finish, so this one is attempts + 1
+            let logger = TaskLogger::new(
+                self.db.clone(),
+                task.id.clone(),
+                task.attempts + 1,
+                self.config.max_task_log_bytes,
+            );
+            let progress = ProgressReporter::new(
+                self.db.clone(),
+                self.events.clone(),
+                task.id.clone(),
+                Duration::from_millis(self.config.progress_persist_interval_ms),
+            );
             
             async move {
-                debug!("Executing task: {} ({})", task.name, task.id);
+                debug!("Executing task");
                 
                 // In a real system, this is where you'd execute the actual task logic
                 // For now, we'll just simulate task execution with a delay
-                let success = tokio::time::timeout(
-                    Duration::from_secs(timeout),
-                    simulate_task_execution(&task)
-                ).await;
-                
-                // Update the task based on the execution result
-                let mut task = match db.get_task(&task.id).await {
-                    Ok(t) => t,
-                    Err(e) => {
-                        error!("Failed to get task for completion: {}", e);
-                        return;
-                    }
+                let started = Instant::now();
+                let (wrap_up, wrap_up_signal) = watch::channel(false);
+                let work = simulate_task_execution(&task, &logger, &progress, wrap_up_signal);
+                let execution = run_with_timeout(work, timeout, grace, wrap_up, &logger);
+                let outcome = tokio::select! {
+                    outcome = execution => outcome,
+                    Ok(_) = interrupt.wait_for(|interrupted| *interrupted) => Outcome::Interrupted,
                 };
+                progress.flush().await;
+                let timeout_error = match outcome {
+                    Outcome::TimedOut => Some(AppError::TaskTimeout(timeout)),
+                    Outcome::Aborted => Some(AppError::TaskAborted(timeout.saturating_add(grace))),
+                    _ => None,
+                };
+                if let Some(e) = &timeout_error {
+                    logger.error(e.to_string()).await;
+                }
+                if matches!(outcome, Outcome::Interrupted) {
+                    logger.warn("Interrupted by shutdown").await;
+                }
+                let elapsed = started.elapsed();
                 
-                match success {
-                    Ok(result) => {
-                        debug!("Task completed successfully: {} ({})", task.name, task.id);
-                        task.mark_completed(Some(result));
+                // Update the task based on the execution result
+                async {
+                    let mut task = match db.get_task(&task.id).await {
+                        Ok(t) => t,
+                        Err(e) => {
+                            error!("Failed to get task for completion: {}", e);
+                            return;
+                        }
+                    };
+
+                    match outcome {
+                        Outcome::Finished(result) => {
+                            debug!(elapsed_ms = elapsed.as_millis() as u64, "Task completed successfully");
+                            task.mark_completed(Some(result));
+                            metrics.record_completed(&task, elapsed);
+                        }
+                        Outcome::TimedOut | Outcome::Aborted => {
+                            let aborted = matches!(outcome, Outcome::Aborted);
+                            warn!(timeout_seconds = timeout, aborted, "Task timed out");
+                            if let Some(e) = timeout_error {
+                                task.mark_failed(e.to_string());
+                            }
+                            metrics.record_timeout(&task, aborted);
+                            metrics.record_failed(&task, elapsed);
+                        }
+                        Outcome::Interrupted => {
+                            warn!(action = ?unfinished, "Task interrupted by shutdown");
+                            match unfinished {
+                                UnfinishedTasks::Requeue => {
+                                    task.state = TaskState::Pending;
+                                    task.worker_id = None;
+                                    task.updated_at = Utc::now();
+                                }
+                                UnfinishedTasks::Fail => {
+                                    task.mark_failed("Interrupted by shutdown".to_string());
+                                    metrics.record_failed(&task, elapsed);
+                                }
+                            }
+                            // Leave the task alone if it was cancelled while running
+                            if let Err(e) = db.update_task_if_state(&task, &TaskState::Running).await {
+                                error!("Failed to update interrupted task: {}", e);
+                            }
+                            return;
+                        }
                     }
-                    Err(_) => {
-                        warn!("Task timed out: {} ({})", task.name, task.id);
-                        task.mark_failed(format!("Task timed out after {} seconds", timeout));
+
+                    // Update the task in the database, unless it was cancelled while running
+                    match db.update_task_if_state(&task, &TaskState::Running).await {
+                        Ok(true) => {}
+                        Ok(false) => debug!("Task is no longer running, leaving its outcome unrecorded"),
+                        Err(e) => error!("Failed to update task after execution: {}", e),
                     }
                 }
+                .instrument(info_span!("persist"))
+                .await;
                 
-                // Update the task in the database
-                if let Err(e) = db.update_task(&task).await {
-                    error!("Failed to update task after execution: {}", e);
-                }
-                
-                // Remove from processing list
-                let mut processing_guard = processing.lock();
-                processing_guard.remove(&task_id);
+                // Remove from processing list and free the worker for the next task
+                processing.lock().remove(&task_id);
+                drop(permit);
             }
+            .instrument(execute_span)
         });
         
         Ok(())
     }
 }
 
+/// Run a task's work for up to `timeout` seconds, then tell it to wrap up through
+/// `wrap_up` and abort it if it is still running `grace` seconds later. The work
+/// returns `None` if it wrapped up before finishing.
+async fn run_with_timeout(
+    work: impl std::future::Future<Output = Option<serde_json::Value>>,
+    timeout: u64,
+    grace: u64,
+    wrap_up: watch::Sender<bool>,
+    logger: &TaskLogger,
+) -> Outcome {
+    tokio::pin!(work);
+    tokio::select! {
+        // Work that finishes just as its time runs out counts as finished
+        biased;
+        result = &mut work => return result.map_or(Outcome::TimedOut, Outcome::Finished),
+        _ = tokio::time::sleep(Duration::from_secs(timeout)) => {}
+    }
+    
+    // Out of time: tell the handler to wrap up, and abort it if it doesn't
+    logger
+        .warn(format!("Timed out after {} seconds, wrapping up", timeout))
+        .await;
+    wrap_up.send_replace(true);
+    match tokio::time::timeout(Duration::from_secs(grace), work).await {
+        Ok(result) => result.map_or(Outcome::TimedOut, Outcome::Finished),
+        Err(_) => Outcome::Aborted,
+    }
+}
+
 // Simulate task execution (replace with actual task handling in a real system)
-async fn simulate_task_execution(task: &Task) -> serde_json::Value {
+/// Returns `None` if the work was cut short because `wrap_up` was set
+async fn simulate_task_execution(
+    task: &Task,
+    logger: &TaskLogger,
+    progress: &ProgressReporter,
+    wrap_up: watch::Receiver<bool>,
+) -> Option<serde_json::Value> {
     // Simulate different processing times based on priority
     let delay = match task.priority {
         crate::models::TaskPriority::Critical => 1,
@@ -365,13 +1103,208 @@ async fn simulate_task_execution(task: &Task) -> serde_json::Value {
         crate::models::TaskPriority::Low => 5,
     };
     
-    tokio::time::sleep(Duration::from_secs(delay)).await;
+    // Resume from the last step a previous attempt got through
+    let first_step = task
+        .checkpoint
+        .as_ref()
+        .and_then(|c| c.get("step"))
+        .and_then(|s| s.as_u64())
+        .unwrap_or(0);
+
+    logger.info(format!("Simulating {} seconds of work from step {}", delay, first_step)).await;
+    for step in first_step..delay {
+        // Stop between steps once told to wrap up; the checkpoint lets a retry resume
+        if *wrap_up.borrow() {
+            logger.warn(format!("Wrapping up after step {} of {}", step, delay)).await;
+            return None;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let percent = (step + 1) as f64 * 100.0 / delay as f64;
+        progress
+            .checkpoint(
+                percent,
+                Some(format!("Step {} of {}", step + 1, delay)),
+                serde_json::json!({ "step": step + 1 }),
+            )
+            .await;
+    }
+    logger.info("Work finished").await;
     
     // Return a simulated result
-    serde_json::json!({
+    Some(serde_json::json!({
         "task_id": task.id,
         "execution_time_seconds": delay,
         "result": format!("Task {} completed successfully", task.name),
         "timestamp": Utc::now().to_rfc3339()
-    })
-}
\ No newline at end of file
+    }))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryDatabase;
+    use tokio::sync::Semaphore;
+
+    fn queue(config: QueueConfig) -> (TaskQueue, Arc<dyn Database>) {
+        let db: Arc<dyn Database> = Arc::new(MemoryDatabase::new());
+        let queue = TaskQueue::new(db.clone(), config, Arc::new(Metrics::new().unwrap()));
+        (queue, db)
+    }
+
+    /// Store the task and run it on a single worker, returning the worker pool
+    async fn start(queue: &TaskQueue, db: &Arc<dyn Database>, task: &Task) -> Arc<Semaphore> {
+        db.create_task(task).await.unwrap();
+        let workers = Arc::new(Semaphore::new(1));
+        let permit = workers.clone().acquire_owned().await.unwrap();
+        queue.process_task(task.clone(), permit).await.unwrap();
+        workers
+    }
+
+    /// Wait until the worker running the task has recorded its outcome and been freed
+    async fn finished(workers: &Semaphore) {
+        drop(workers.acquire().await.unwrap());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_task_cancelled_while_running_stays_cancelled() {
+        let (queue, db) = queue(QueueConfig::for_tests());
+        let task = Task::new("work".to_string(), serde_json::json!({})).with_priority(TaskPriority::Low);
+        let workers = start(&queue, &db, &task).await;
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        queue.cancel_task(&task.id).await.unwrap();
+        finished(&workers).await;
+
+        assert_eq!(db.get_task(&task.id).await.unwrap().state, TaskState::Cancelled);
+    }
+
+    fn logger(db: &Arc<dyn Database>) -> TaskLogger {
+        TaskLogger::new(db.clone(), "task".to_string(), 1, 1024)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_work_that_wraps_up_times_out_and_work_that_doesnt_is_aborted() {
+        let db: Arc<dyn Database> = Arc::new(MemoryDatabase::new());
+
+        // Stops as soon as it is told to
+        let (wrap_up, mut signal) = watch::channel(false);
+        let cooperative = async move {
+            let _ = signal.wait_for(|wrap_up| *wrap_up).await;
+            None
+        };
+        let outcome = run_with_timeout(cooperative, 5, 10, wrap_up, &logger(&db)).await;
+        assert!(matches!(outcome, Outcome::TimedOut));
+
+        // Ignores the signal and keeps going
+        let (wrap_up, _signal) = watch::channel(false);
+        let started = Instant::now();
+        let stubborn = std::future::pending();
+        let outcome = run_with_timeout(stubborn, 5, 10, wrap_up, &logger(&db)).await;
+        assert!(matches!(outcome, Outcome::Aborted));
+        assert_eq!(started.elapsed(), Duration::from_secs(15));
+
+        // Finishes during the grace period
+        let (wrap_up, _signal) = watch::channel(false);
+        let slow = async {
+            tokio::time::sleep(Duration::from_secs(7)).await;
+            Some(serde_json::json!({}))
+        };
+        let outcome = run_with_timeout(slow, 5, 10, wrap_up, &logger(&db)).await;
+        assert!(matches!(outcome, Outcome::Finished(_)));
+    }
+
+    #[test]
+    fn test_timeout_comes_from_task_then_name_then_default() {
+        let mut config = QueueConfig::for_tests();
+        config.task_timeouts.insert("report".to_string(), 120);
+        let (queue, _) = queue(config);
+
+        let report = Task::new("report".to_string(), serde_json::json!({}));
+        assert_eq!(queue.timeout_for(&report), 120);
+        assert_eq!(queue.timeout_for(&report.clone().with_timeout(5)), 5);
+        let other = Task::new("other".to_string(), serde_json::json!({}));
+        assert_eq!(queue.timeout_for(&other), 300);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_task_wraps_up_when_its_name_times_out() {
+        let mut config = QueueConfig::for_tests();
+        config.task_timeouts.insert("slow".to_string(), 2);
+        let (queue, db) = queue(config);
+        let task = Task::new("slow".to_string(), serde_json::json!({})).with_priority(TaskPriority::Low);
+        let workers = start(&queue, &db, &task).await;
+        finished(&workers).await;
+
+        // The handler stopped at the next step after being told to wrap up
+        let stored = db.get_task(&task.id).await.unwrap();
+        assert_eq!(stored.state, TaskState::Failed);
+        assert_eq!(stored.last_error, Some(AppError::TaskTimeout(2).to_string()));
+        assert_eq!(stored.checkpoint, Some(serde_json::json!({ "step": 3 })));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_task_is_aborted_when_it_doesnt_wrap_up_in_time() {
+        let config = QueueConfig {
+            task_timeout_grace_seconds: 0,
+            ..QueueConfig::for_tests()
+        };
+        let (queue, db) = queue(config);
+        let task = Task::new("work".to_string(), serde_json::json!({}))
+            .with_priority(TaskPriority::Low)
+            .with_timeout(2);
+        let workers = start(&queue, &db, &task).await;
+        finished(&workers).await;
+
+        let stored = db.get_task(&task.id).await.unwrap();
+        assert_eq!(stored.state, TaskState::Failed);
+        assert_eq!(stored.last_error, Some(AppError::TaskAborted(2).to_string()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_task_finishing_within_its_timeout_completes() {
+        let (queue, db) = queue(QueueConfig::for_tests());
+        let task = Task::new("work".to_string(), serde_json::json!({})).with_timeout(10);
+        let workers = start(&queue, &db, &task).await;
+        finished(&workers).await;
+
+        let stored = db.get_task(&task.id).await.unwrap();
+        assert_eq!(stored.state, TaskState::Completed);
+        assert_eq!(stored.last_error, None);
+    }
+
+    #[tokio::test]
+    async fn test_tasks_at_deadline_risk_are_counted_beyond_the_window() {
+        let config = QueueConfig {
+            prefetch_window_size: 1,
+            ..QueueConfig::for_tests()
+        };
+        let (queue, db) = queue(config);
+        let soon = Utc::now() + chrono::Duration::seconds(10);
+        let later = Utc::now() + chrono::Duration::hours(1);
+        for deadline in [soon, soon, soon, later] {
+            let task = Task::new("work".to_string(), serde_json::json!({})).with_deadline(deadline);
+            db.create_task(&task).await.unwrap();
+        }
+        queue.dispatcher.window().lock().reload();
+        queue.refill_window().await.unwrap();
+        assert_eq!(queue.dispatcher.window().lock().len(), 1);
+
+        queue.render_metrics().await.unwrap();
+        assert_eq!(queue.metrics.tasks_at_deadline_risk.get(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_tasks_stored_behind_the_queues_back_are_polled_and_run() {
+        let (queue, db) = queue(QueueConfig::for_tests());
+        let running = queue.clone();
+        tokio::spawn(async move { running.start().await });
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        // Another instance stores a task without the announcement reaching this one
+        let task = Task::new("work".to_string(), serde_json::json!({}));
+        db.create_task(&task).await.unwrap();
+
+        let poll = Duration::from_secs(queue.config.scheduler_poll_interval_seconds);
+        tokio::time::sleep(poll * 4).await;
+        assert_eq!(db.get_task(&task.id).await.unwrap().state, TaskState::Completed);
+    }
+}
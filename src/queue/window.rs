@@ -0,0 +1,322 @@
+use crate::models::{QueuePosition, Task};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::{ByPriority, PriorityQueue, SchedulingPolicy};
+
+/// The tasks ready to run that are held in memory: the first `capacity` of them in
+/// queue order. The rest stay in storage and are loaded as the window drains.
+///
+/// A cutoff position separates the two. Every ready task outside the window is at
+/// or after it, and every task in the window is at or before it, so the window
+/// always runs the tasks storage would have put first. Tasks sharing the cutoff's
+/// position, created within the same second, may run slightly out of order.
+///
+/// Storage only hands out tasks by priority and age, so when the queue runs them
+/// in another order the window holds every ready task. The configuration is
+/// rejected unless `max_pending_tasks` bounds how many that can be.
+pub struct Window {
+    queue: PriorityQueue,
+    capacity: usize,
+    /// `None` once the window holds every ready task
+    cutoff: Option<QueuePosition>,
+    /// Set while tasks are being loaded from storage
+    refill: Option<Refill>,
+}
+
+struct Refill {
+    limit: u32,
+    /// Earliest position of a task left out of the window since the refill began,
+    /// which the loaded tasks may not include
+    left_out: Option<QueuePosition>,
+}
+
+/// Tasks to load from storage to refill the window
+pub struct RefillRequest {
+    /// Position of the first task to load, if not the very first
+    pub from: Option<QueuePosition>,
+    pub limit: u32,
+}
+
+impl Window {
+    /// Create an empty window holding up to `capacity` tasks, with no ready tasks
+    /// in storage yet
+    pub fn new(capacity: usize) -> Self {
+        Self::with_policy(capacity, Arc::new(ByPriority))
+    }
+
+    /// Create an empty window running its tasks in the order the policy decides
+    pub fn with_policy(capacity: usize, policy: Arc<dyn SchedulingPolicy>) -> Self {
+        Self::with_policies(capacity, policy, HashMap::new())
+    }
+
+    /// Create an empty window running the tasks of each named queue in the order its
+    /// policy decides, and all other tasks in the order the default one does
+    pub fn with_policies(
+        capacity: usize,
+        default: Arc<dyn SchedulingPolicy>,
+        named: HashMap<String, Arc<dyn SchedulingPolicy>>,
+    ) -> Self {
+        let queue = PriorityQueue::with_policies(default, named);
+        let capacity = if queue.follows_storage_order() {
+            capacity.max(1)
+        } else {
+            usize::MAX
+        };
+        Self {
+            queue,
+            capacity,
+            cutoff: None,
+            refill: None,
+        }
+    }
+
+    /// Add a ready task, unless it falls after the cutoff, in which case it stays in
+    /// storage until the window reaches it. Returns whether it was added.
+    pub fn push(&mut self, task: Task) -> bool {
+        let position = QueuePosition::of(&task);
+        if self.cutoff.is_some_and(|cutoff| position > cutoff) {
+            self.leave_out(position);
+            return false;
+        }
+
+        self.queue.push(task);
+        self.shrink();
+        true
+    }
+
+    /// Take the first task in queue order
+    pub fn pop(&mut self) -> Option<Task> {
+        self.queue.pop()
+    }
+
+    /// Number of tasks in the window
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Check if the window is empty
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Iterate over the tasks in the window from the first to run to the last
+    pub fn iter(&self) -> impl Iterator<Item = &Task> {
+        self.queue.iter()
+    }
+
+    /// Check whether a task is in the window
+    pub fn contains(&self, id: &str) -> bool {
+        self.queue.contains(id)
+    }
+
+    /// Remove a task from the window by ID
+    pub fn remove(&mut self, id: &str) -> Option<Task> {
+        self.queue.remove(id)
+    }
+
+    /// Keep only the tasks for which the predicate returns true
+    pub fn retain(&mut self, f: impl FnMut(&Task) -> bool) {
+        self.queue.retain(f);
+    }
+
+    /// Modify every task in the window matching the predicate. Returns the number
+    /// of tasks modified.
+    pub fn update_where(
+        &mut self,
+        pred: impl FnMut(&Task) -> bool,
+        f: impl FnMut(&mut Task),
+    ) -> usize {
+        let updated = self.queue.update_where(pred, f);
+        // Tasks moved past the cutoff go back to waiting in storage
+        self.trim();
+        updated
+    }
+
+    /// Forget which ready tasks storage holds, so the window is loaded again from
+    /// the start, e.g. when the queue starts on existing storage or may have missed
+    /// tasks stored since
+    pub fn reload(&mut self) {
+        self.cutoff = Some(QueuePosition::FIRST);
+        self.leave_out(QueuePosition::FIRST);
+        self.trim();
+    }
+
+    /// Note that tasks in storage may have moved forward as far as `position`, e.g.
+    /// after their priority was raised. Tasks in the window after it go back to
+    /// waiting in storage, to be loaded again in order.
+    pub fn moved_up(&mut self, position: QueuePosition) {
+        let Some(cutoff) = self.cutoff else {
+            // Every ready task is already in the window
+            return;
+        };
+        self.cutoff = Some(cutoff.min(position));
+        self.leave_out(position);
+        self.trim();
+    }
+
+    /// Whether the window has drained far enough to be refilled from storage
+    pub fn wants_refill(&self) -> bool {
+        self.cutoff.is_some() && self.refill.is_none() && self.queue.len() <= self.capacity / 2
+    }
+
+    /// Start a refill if the window is at most half full and storage holds more
+    /// ready tasks. The loaded tasks must be handed to [`Window::finish_refill`].
+    pub fn begin_refill(&mut self) -> Option<RefillRequest> {
+        if !self.wants_refill() {
+            return None;
+        }
+        let cutoff = self.cutoff?;
+
+        // Tasks at the cutoff's position may already be in the window, and will
+        // be loaded again along with the ones that aren't
+        let overlap = self
+            .queue
+            .iter_rev()
+            .take_while(|task| QueuePosition::of(task) == cutoff)
+            .count();
+        let limit = (self.capacity - self.queue.len() + overlap).min(u32::MAX as usize) as u32;
+
+        self.refill = Some(Refill { limit, left_out: None });
+        Some(RefillRequest {
+            from: (cutoff != QueuePosition::FIRST).then_some(cutoff),
+            limit,
+        })
+    }
+
+    /// Add the tasks loaded for a refill, given in queue order
+    pub fn finish_refill(&mut self, tasks: Vec<Task>) {
+        let Some(refill) = self.refill.take() else {
+            return;
+        };
+
+        // Fewer tasks than asked for means storage has no more
+        let mut cutoff = if tasks.len() < refill.limit as usize {
+            None
+        } else {
+            tasks.last().map(QueuePosition::of)
+        };
+        // Tasks left out meanwhile may have been stored too late to be loaded
+        if let Some(left_out) = refill.left_out {
+            cutoff = Some(cutoff.map_or(left_out, |cutoff| cutoff.min(left_out)));
+        }
+
+        for task in tasks {
+            self.queue.push(task);
+        }
+        self.cutoff = cutoff;
+        self.trim();
+        self.shrink();
+    }
+
+    /// Give up on a refill that failed, keeping what is known about storage
+    pub fn abort_refill(&mut self) {
+        if let Some(left_out) = self.refill.take().and_then(|refill| refill.left_out) {
+            self.cutoff = self.cutoff.map(|cutoff| cutoff.min(left_out));
+            self.trim();
+        }
+    }
+
+    // Note a task staying in storage, which a refill in progress may not load
+    fn leave_out(&mut self, position: QueuePosition) {
+        if let Some(refill) = &mut self.refill {
+            refill.left_out = Some(refill.left_out.map_or(position, |left| left.min(position)));
+        }
+    }
+
+    // Drop the tasks after the cutoff, which storage still holds
+    fn trim(&mut self) {
+        let Some(cutoff) = self.cutoff else {
+            return;
+        };
+        if self.queue.follows_storage_order() {
+            while self
+                .queue
+                .peek_last()
+                .is_some_and(|task| QueuePosition::of(task) > cutoff)
+            {
+                self.queue.pop_last();
+            }
+        } else {
+            self.queue.retain(|task| QueuePosition::of(task) <= cutoff);
+        }
+    }
+
+    // Drop the last tasks while the window is over capacity, moving the cutoff in front of them
+    fn shrink(&mut self) {
+        while self.queue.len() > self.capacity {
+            let Some(task) = self.queue.pop_last() else {
+                break;
+            };
+            let position = QueuePosition::of(&task);
+            self.cutoff = Some(self.cutoff.map_or(position, |cutoff| cutoff.min(position)));
+            self.leave_out(position);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::TaskPriority;
+    use chrono::{Duration, Utc};
+
+    fn task(priority: TaskPriority, age_seconds: i64) -> Task {
+        let mut task = Task::new("work".to_string(), serde_json::json!({})).with_priority(priority);
+        task.created_at = Utc::now() - Duration::seconds(age_seconds);
+        task
+    }
+
+    #[test]
+    fn test_window_stays_ahead_of_storage() {
+        // Storage holds six tasks, in queue order, and the window takes four at a time
+        let mut stored = vec![
+            task(TaskPriority::High, 10),
+            task(TaskPriority::High, 5),
+            task(TaskPriority::Medium, 30),
+            task(TaskPriority::Medium, 20),
+            task(TaskPriority::Low, 50),
+            task(TaskPriority::Low, 40),
+        ];
+        let mut window = Window::new(4);
+        window.reload();
+
+        let request = window.begin_refill().unwrap();
+        assert!(request.from.is_none());
+        assert_eq!(request.limit, 4);
+        assert!(window.begin_refill().is_none());
+        window.finish_refill(stored[..4].to_vec());
+
+        // A low priority task sorts after the last loaded task, so it stays in storage
+        let late = task(TaskPriority::Low, 0);
+        assert!(!window.push(late.clone()));
+        stored.push(late);
+
+        // A critical one goes to the front, pushing the last task out of the window
+        let urgent = task(TaskPriority::Critical, 0);
+        assert!(window.push(urgent.clone()));
+        assert_eq!(window.len(), 4);
+        assert_eq!(window.pop().unwrap().id, urgent.id);
+        stored.insert(0, urgent);
+
+        let mut ran = vec![stored[0].id.clone()];
+        while let Some(task) = window.pop() {
+            ran.push(task.id);
+            if let Some(request) = window.begin_refill() {
+                // Load what storage holds from the requested position on, minus what ran
+                let loaded: Vec<Task> = stored
+                    .iter()
+                    .filter(|task| !ran.contains(&task.id))
+                    .filter(|task| request.from.is_none_or(|from| QueuePosition::of(task) >= from))
+                    .take(request.limit as usize)
+                    .cloned()
+                    .collect();
+                window.finish_refill(loaded);
+            }
+        }
+
+        let expected: Vec<String> = stored.iter().map(|task| task.id.clone()).collect();
+        assert_eq!(ran, expected);
+        assert!(window.begin_refill().is_none());
+    }
+}
@@ -0,0 +1,252 @@
+use crate::archive::TaskArchiver;
+use crate::config::{RetentionConfig, RetentionRule};
+use crate::error::{AppError, AppResult};
+use crate::metrics::Metrics;
+use crate::models::{TaskFilter, TaskState};
+use crate::storage::Database;
+use chrono::{Duration as ChronoDuration, Utc};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::{error, info};
+
+/// Tasks purged by one rule during a run
+#[derive(Debug, Clone, Serialize)]
+pub struct RulePurgeResult {
+    pub rule: RetentionRule,
+    pub purged: usize,
+}
+
+/// Outcome of a purge run
+#[derive(Debug, Clone, Serialize)]
+pub struct PurgeReport {
+    pub purged: usize,
+    pub rules: Vec<RulePurgeResult>,
+}
+
+/// Deletes finished tasks once the retention rules say they are old enough
+pub struct RetentionPurger {
+    db: Arc<dyn Database>,
+    config: RetentionConfig,
+    metrics: Arc<Metrics>,
+    /// Archives tasks before they are deleted, if configured
+    archiver: Option<Arc<TaskArchiver>>,
+    /// Held while purging so scheduled and manual runs don't overlap
+    running: Mutex<()>,
+}
+
+impl RetentionPurger {
+    /// Create a purger, rejecting rules that don't target a finished state
+    pub fn new(
+        db: Arc<dyn Database>,
+        config: RetentionConfig,
+        metrics: Arc<Metrics>,
+    ) -> AppResult<Self> {
+        for rule in &config.rules {
+            let finished = rule
+                .state
+                .parse::<TaskState>()
+                .map(|state| state.is_finished())
+                .unwrap_or(false);
+            if !finished {
+                return Err(AppError::ConfigError(format!(
+                    "Retention rules must target completed, failed, cancelled or expired tasks, not '{}'",
+                    rule.state
+                )));
+            }
+        }
+
+        if config.batch_size == 0 {
+            return Err(AppError::ConfigError(
+                "retention.batch_size must be at least 1".to_string(),
+            ));
+        }
+
+        let archiver = config
+            .archive_dir
+            .as_ref()
+            .map(|dir| TaskArchiver::new(dir, config.archive_max_tasks_per_file).map(Arc::new))
+            .transpose()?;
+
+        Ok(Self {
+            db,
+            config,
+            metrics,
+            archiver,
+            running: Mutex::new(()),
+        })
+    }
+
+    /// Purge periodically in the background, if enabled
+    pub fn start(self: &Arc<Self>) {
+        if !self.config.enabled || self.config.rules.is_empty() {
+            info!("Retention purging is disabled");
+            return;
+        }
+
+        let purger = self.clone();
+        tokio::spawn(async move {
+            let period = Duration::from_secs(purger.config.interval_seconds);
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                if let Err(e) = purger.purge().await {
+                    error!("Retention purge failed: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Apply every rule once, deleting matching tasks in batches until none are left
+    pub async fn purge(&self) -> AppResult<PurgeReport> {
+        let _running = self.running.lock().await;
+
+        let now = Utc::now();
+        let mut report = PurgeReport {
+            purged: 0,
+            rules: Vec::with_capacity(self.config.rules.len()),
+        };
+
+        for (index, rule) in self.config.rules.iter().enumerate() {
+            let mut purged = 0;
+
+            if let Some(max_age_days) = rule.max_age_days {
+                let filter = TaskFilter {
+                    updated_before: Some(now - ChronoDuration::days(max_age_days as i64)),
+                    ..rule_filter(rule)
+                };
+                // Earlier rules take precedence, so leave the tasks they match alone
+                let earlier: Vec<TaskFilter> =
+                    self.config.rules[..index].iter().map(rule_filter).collect();
+
+                loop {
+                    let (matched, deleted) = self.purge_batch(&filter, &earlier).await?;
+                    purged += deleted;
+                    self.metrics
+                        .tasks_purged
+                        .with_label_values(&[&rule.state])
+                        .inc_by(deleted as u64);
+
+                    if matched < self.config.batch_size as usize {
+                        break;
+                    }
+                }
+            }
+
+            report.purged += purged;
+            report.rules.push(RulePurgeResult {
+                rule: rule.clone(),
+                purged,
+            });
+        }
+
+        if report.purged > 0 {
+            info!("Retention purge deleted {} tasks", report.purged);
+        }
+
+        Ok(report)
+    }
+
+    // Purge one batch, archiving it first if configured. Returns how many tasks
+    // matched and how many were deleted; tasks that changed after being archived
+    // (e.g. were retried) are kept.
+    async fn purge_batch(
+        &self,
+        filter: &TaskFilter,
+        earlier: &[TaskFilter],
+    ) -> AppResult<(usize, usize)> {
+        let batch_size = self.config.batch_size;
+
+        let Some(archiver) = &self.archiver else {
+            let ids = self.db.purge_tasks(filter, earlier, batch_size).await?;
+            return Ok((ids.len(), ids.len()));
+        };
+
+        let tasks = self.db.find_purgeable_tasks(filter, earlier, batch_size).await?;
+        if tasks.is_empty() {
+            return Ok((0, 0));
+        }
+
+        let matched = tasks.len();
+        let archived = TaskFilter {
+            ids: tasks.iter().map(|task| task.id.clone()).collect(),
+            ..filter.clone()
+        };
+        archiver.archive(tasks).await?;
+        let ids = self.db.purge_tasks(&archived, earlier, batch_size).await?;
+
+        Ok((matched, ids.len()))
+    }
+}
+
+// Tasks a rule applies to, regardless of age
+fn rule_filter(rule: &RetentionRule) -> TaskFilter {
+    TaskFilter {
+        state: Some(rule.state.clone()),
+        name: rule.name.clone(),
+        tags: rule.tag.iter().cloned().collect(),
+        ..TaskFilter::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Task;
+    use crate::storage::create_database;
+
+    fn rule(state: &str, tag: Option<&str>, max_age_days: Option<u32>) -> RetentionRule {
+        RetentionRule {
+            state: state.to_string(),
+            name: None,
+            tag: tag.map(str::to_string),
+            max_age_days,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_first_matching_rule_wins() {
+        let path = std::env::temp_dir().join(format!("taskqueue-{}.db", uuid::Uuid::new_v4()));
+        let db = create_database(&format!("sqlite:{}?mode=rwc", path.display()))
+            .await
+            .unwrap();
+        db.setup().await.unwrap();
+
+        let old = Utc::now() - ChronoDuration::days(10);
+        let finished = |tags: Vec<String>| {
+            let mut task = Task::new("report".to_string(), serde_json::json!({})).with_tags(tags);
+            task.mark_completed(None);
+            task.updated_at = old;
+            task
+        };
+        let audited = finished(vec!["audit".to_string()]);
+        let first = finished(Vec::new());
+        let second = finished(Vec::new());
+        let recent = Task::new("report".to_string(), serde_json::json!({}));
+        db.create_tasks(&[audited.clone(), first.clone(), second.clone(), recent.clone()])
+            .await
+            .unwrap();
+
+        let config = RetentionConfig {
+            enabled: true,
+            interval_seconds: 3600,
+            batch_size: 1,
+            rules: vec![
+                rule("completed", Some("audit"), None),
+                rule("completed", None, Some(7)),
+            ],
+            archive_dir: None,
+            archive_max_tasks_per_file: 100,
+        };
+        let metrics = Arc::new(Metrics::new().unwrap());
+        let purger = RetentionPurger::new(db.clone(), config, metrics).unwrap();
+
+        let report = purger.purge().await.unwrap();
+        assert_eq!(report.purged, 2);
+        assert!(db.get_task(&first.id).await.is_err());
+        assert!(db.get_task(&second.id).await.is_err());
+        assert!(db.get_task(&audited.id).await.is_ok());
+        assert!(db.get_task(&recent.id).await.is_ok());
+    }
+}
@@ -0,0 +1,563 @@
+//! Behavior every `Database` backend must share. Each check runs against the
+//! in-memory, SQLite and redb backends. The PostgreSQL checks are ignored by
+//! default; run them with `cargo test -- --ignored` and `TEST_POSTGRES_URL`
+//! pointing at a server. Every Postgres test gets a schema of its own.
+
+use crate::error::AppError;
+use crate::models::{
+    LogLevel, QueuePosition, Task, TaskCursor, TaskFilter, TaskPriority, TaskProgress, TaskState,
+};
+use crate::storage::{create_database, Database};
+use chrono::{Duration as ChronoDuration, Utc};
+use std::sync::Arc;
+
+async fn memory_database() -> Arc<dyn Database> {
+    let db = create_database("memory:").await.unwrap();
+    db.setup().await.unwrap();
+    db
+}
+
+async fn sqlite_database() -> Arc<dyn Database> {
+    let path = std::env::temp_dir().join(format!("taskqueue-{}.db", uuid::Uuid::new_v4()));
+    let db = create_database(&format!("sqlite:{}?mode=rwc", path.display()))
+        .await
+        .unwrap();
+    db.setup().await.unwrap();
+    db
+}
+
+async fn redb_database() -> Arc<dyn Database> {
+    let path = std::env::temp_dir().join(format!("taskqueue-{}.redb", uuid::Uuid::new_v4()));
+    let db = create_database(&format!("redb:{}", path.display())).await.unwrap();
+    db.setup().await.unwrap();
+    db
+}
+
+/// URL of a fresh, empty schema on the `TEST_POSTGRES_URL` server. Panics when it
+/// isn't set, so an unconfigured run can't pass without testing anything.
+pub(super) async fn postgres_schema_url() -> String {
+    let url = std::env::var("TEST_POSTGRES_URL")
+        .expect("TEST_POSTGRES_URL must point at a PostgreSQL server to run the Postgres tests");
+
+    let schema = format!("conformance_{}", uuid::Uuid::new_v4().simple());
+    let pool = sqlx::PgPool::connect(&url).await.unwrap();
+    sqlx::query(&format!("CREATE SCHEMA {}", schema))
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{}{}options=-c%20search_path%3D{}", url, separator, schema)
+}
+
+async fn postgres_database() -> Arc<dyn Database> {
+    let db = create_database(&postgres_schema_url().await).await.unwrap();
+    db.setup().await.unwrap();
+    db
+}
+
+// Generate a test per backend for each check
+macro_rules! conformance_tests {
+    ($($check:ident),* $(,)?) => {
+        mod memory {
+            $(
+                #[tokio::test]
+                async fn $check() {
+                    super::$check(super::memory_database().await.as_ref()).await;
+                }
+            )*
+        }
+
+        mod sqlite {
+            $(
+                #[tokio::test]
+                async fn $check() {
+                    super::$check(super::sqlite_database().await.as_ref()).await;
+                }
+            )*
+        }
+
+        mod redb {
+            $(
+                #[tokio::test]
+                async fn $check() {
+                    super::$check(super::redb_database().await.as_ref()).await;
+                }
+            )*
+        }
+
+        mod postgres {
+            $(
+                #[tokio::test]
+                #[ignore = "needs a PostgreSQL server in TEST_POSTGRES_URL"]
+                async fn $check() {
+                    super::$check(super::postgres_database().await.as_ref()).await;
+                }
+            )*
+        }
+    };
+}
+
+conformance_tests!(
+    keyset_pagination,
+    tag_filters_and_bulk_operations,
+    tenants_are_kept_and_filtered,
+    create_tasks_is_atomic,
+    retry_and_delete_by_filter,
+    task_logs_are_paged_and_deleted_with_task,
+    checkpoint_survives_retry,
+    timing_stats_percentiles,
+    scheduled_and_retryable_tasks,
+    ready_tasks_in_queue_order,
+    expiry_stops_waiting_tasks,
+    timeouts_are_kept,
+    deadlines_are_kept,
+    purge_respects_exclusions_and_age_order,
+    conditional_updates,
+);
+
+async fn keyset_pagination(db: &dyn Database) {
+    let now = Utc::now();
+    for i in 0..5 {
+        let mut task = Task::new(format!("task{}", i), serde_json::json!({}));
+        task.created_at = now - ChronoDuration::minutes(i);
+        db.create_task(&task).await.unwrap();
+    }
+
+    let filter = TaskFilter::default();
+    let first = db.get_tasks(&filter, None, Some(2)).await.unwrap();
+    assert_eq!(
+        first.iter().map(|t| t.name.as_str()).collect::<Vec<_>>(),
+        vec!["task0", "task1"]
+    );
+
+    let cursor = TaskCursor::after(first.last().unwrap());
+    let second = db.get_tasks(&filter, Some(&cursor), Some(10)).await.unwrap();
+    assert_eq!(
+        second.iter().map(|t| t.name.as_str()).collect::<Vec<_>>(),
+        vec!["task2", "task3", "task4"]
+    );
+
+    assert_eq!(db.count_tasks(&filter).await.unwrap(), 5);
+    assert_eq!(
+        db.count_tasks(&TaskFilter::default().with_state("completed")).await.unwrap(),
+        0
+    );
+}
+
+async fn tag_filters_and_bulk_operations(db: &dyn Database) {
+    let tagged = Task::new("tagged".to_string(), serde_json::json!({}))
+        .with_tags(vec!["email".to_string(), "urgent".to_string()]);
+    let other = Task::new("other".to_string(), serde_json::json!({}))
+        .with_tags(vec!["email".to_string()]);
+    db.create_task(&tagged).await.unwrap();
+    db.create_task(&other).await.unwrap();
+
+    let urgent = TaskFilter::default().with_tag("urgent");
+    let found = db.get_tasks(&urgent, None, None).await.unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].id, tagged.id);
+
+    let email = TaskFilter::default().with_tag("email");
+    let ids = db.reprioritize_tasks(&email, &TaskPriority::High).await.unwrap();
+    assert_eq!(ids.len(), 2);
+
+    let cancelled = db.cancel_tasks(&urgent).await.unwrap();
+    assert_eq!(cancelled.len(), 1);
+    assert_eq!(cancelled[0].id, tagged.id);
+    assert!(db.claim_task(&tagged.id, "worker").await.unwrap().is_none());
+
+    let claimed = db.claim_task(&other.id, "worker").await.unwrap().unwrap();
+    assert_eq!(claimed.priority, TaskPriority::High);
+
+    let counts = db.count_tasks_by_tag().await.unwrap();
+    let email_counts: i64 = counts.iter().filter(|c| c.tag == "email").map(|c| c.count).sum();
+    assert_eq!(email_counts, 2);
+}
+
+async fn tenants_are_kept_and_filtered(db: &dyn Database) {
+    let acme = Task::new("acme".to_string(), serde_json::json!({})).with_tenant("acme");
+    let other = Task::new("other".to_string(), serde_json::json!({})).with_tenant("other");
+    let untenanted = Task::new("untenanted".to_string(), serde_json::json!({}));
+    db.create_tasks(&[acme.clone(), other.clone(), untenanted.clone()]).await.unwrap();
+
+    // Updates leave the tenant alone, like the other columns fixed at creation
+    let mut updated = acme.clone();
+    updated.tenant = None;
+    updated.priority = TaskPriority::High;
+    db.update_task(&updated).await.unwrap();
+    assert_eq!(db.get_task(&acme.id).await.unwrap().tenant.as_deref(), Some("acme"));
+
+    let pending_for_acme = TaskFilter::default().with_state("pending").with_tenant("acme");
+    assert_eq!(db.count_tasks(&pending_for_acme).await.unwrap(), 1);
+    let found = db.get_tasks(&pending_for_acme, None, None).await.unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].id, acme.id);
+
+    db.claim_task(&acme.id, "worker").await.unwrap().unwrap();
+    assert_eq!(db.count_tasks(&pending_for_acme).await.unwrap(), 0);
+}
+
+async fn create_tasks_is_atomic(db: &dyn Database) {
+    let tasks: Vec<Task> = (0..1200)
+        .map(|i| {
+            Task::new(format!("batch{}", i), serde_json::json!({ "i": i }))
+                .with_tags(vec!["batch".to_string()])
+        })
+        .collect();
+    db.create_tasks(&tasks).await.unwrap();
+
+    let batch = TaskFilter::default().with_tag("batch");
+    assert_eq!(db.count_tasks(&batch).await.unwrap(), 1200);
+
+    // A duplicate ID rolls back the whole batch
+    let duplicate = vec![
+        Task::new("fresh".to_string(), serde_json::json!({})),
+        tasks[0].clone(),
+    ];
+    assert!(db.create_tasks(&duplicate).await.is_err());
+    assert_eq!(db.count_tasks(&TaskFilter::default()).await.unwrap(), 1200);
+}
+
+async fn retry_and_delete_by_filter(db: &dyn Database) {
+    let mut failed = Task::new("send_email".to_string(), serde_json::json!({}));
+    failed.mark_failed("SMTP connection refused".to_string());
+    let mut other = Task::new("send_email".to_string(), serde_json::json!({}));
+    other.mark_failed("Invalid address".to_string());
+    let done = Task::new("report".to_string(), serde_json::json!({}))
+        .with_tags(vec!["nightly".to_string()]);
+    db.create_tasks(&[failed.clone(), other.clone(), done.clone()]).await.unwrap();
+
+    let filter = TaskFilter {
+        error_contains: Some("SMTP".to_string()),
+        ..TaskFilter::default()
+    };
+    let retried = db.retry_tasks(&filter, true).await.unwrap();
+    assert_eq!(retried.len(), 1);
+    assert_eq!(retried[0].id, failed.id);
+    assert_eq!(retried[0].state, TaskState::Pending);
+    assert_eq!(retried[0].attempts, 0);
+
+    let deleted = db.delete_tasks(&TaskFilter::default().with_name("report")).await.unwrap();
+    assert_eq!(deleted, vec![done.id.clone()]);
+    assert!(db.get_task(&done.id).await.is_err());
+    assert!(db.count_tasks_by_tag().await.unwrap().is_empty());
+}
+
+async fn task_logs_are_paged_and_deleted_with_task(db: &dyn Database) {
+    let task = Task::new("export".to_string(), serde_json::json!({}));
+    db.create_task(&task).await.unwrap();
+    for i in 0..3 {
+        db.append_task_log(&task.id, 1, LogLevel::Info, &format!("line {}", i), Utc::now())
+            .await
+            .unwrap();
+    }
+
+    let first = db.get_task_logs(&task.id, None, 2).await.unwrap();
+    assert_eq!(
+        first.iter().map(|l| l.message.as_str()).collect::<Vec<_>>(),
+        vec!["line 0", "line 1"]
+    );
+    let rest = db.get_task_logs(&task.id, Some(first[1].seq), 2).await.unwrap();
+    assert_eq!(rest.len(), 1);
+    assert_eq!(rest[0].message, "line 2");
+
+    db.delete_tasks(&TaskFilter::default().with_id(task.id.clone())).await.unwrap();
+    assert!(db.get_task_logs(&task.id, None, 10).await.unwrap().is_empty());
+}
+
+async fn checkpoint_survives_retry(db: &dyn Database) {
+    let task = Task::new("export".to_string(), serde_json::json!({}));
+    db.create_task(&task).await.unwrap();
+
+    let progress = TaskProgress {
+        percent: 40.0,
+        message: Some("Exported 400 rows".to_string()),
+        updated_at: Utc::now(),
+    };
+    let checkpoint = serde_json::json!({ "offset": 400 });
+
+    // Progress is only recorded while the task runs
+    assert!(!db.update_task_progress(&task.id, &progress, Some(&checkpoint)).await.unwrap());
+
+    db.claim_task(&task.id, "worker").await.unwrap().unwrap();
+    assert!(db.update_task_progress(&task.id, &progress, Some(&checkpoint)).await.unwrap());
+
+    let mut stored = db.get_task(&task.id).await.unwrap();
+    assert_eq!(stored.progress.as_ref().map(|p| p.percent), Some(40.0));
+    stored.mark_failed("Connection reset".to_string());
+    db.update_task(&stored).await.unwrap();
+
+    db.retry_tasks(&TaskFilter::default().with_id(task.id.clone()), false).await.unwrap();
+    let reclaimed = db.claim_task(&task.id, "worker").await.unwrap().unwrap();
+    assert_eq!(reclaimed.checkpoint, Some(checkpoint));
+    assert!(reclaimed.progress.is_none());
+}
+
+async fn timing_stats_percentiles(db: &dyn Database) {
+    // Run times of 1..=100 seconds, each after a 5 second wait
+    let now = Utc::now();
+    let tasks: Vec<Task> = (1..=100)
+        .map(|i| {
+            let mut task = Task::new("job".to_string(), serde_json::json!({}));
+            task.created_at = now - ChronoDuration::seconds(200);
+            task.started_at = Some(task.created_at + ChronoDuration::seconds(5));
+            task.completed_at = Some(task.created_at + ChronoDuration::seconds(5 + i));
+            task.state = TaskState::Completed;
+            task
+        })
+        .collect();
+    db.create_tasks(&tasks).await.unwrap();
+
+    let stats = db.task_timing_stats(now - ChronoDuration::hours(1)).await.unwrap();
+    assert_eq!(stats.run.count, 100);
+    assert_eq!(stats.run.p50, Some(50.0));
+    assert_eq!(stats.run.p95, Some(95.0));
+    assert_eq!(stats.run.p99, Some(99.0));
+    assert_eq!(stats.wait.p99, Some(5.0));
+
+    let empty = db.task_timing_stats(now + ChronoDuration::hours(1)).await.unwrap();
+    assert_eq!(empty.run.count, 0);
+    assert_eq!(empty.run.p50, None);
+
+    let groups = db.count_tasks_by_group().await.unwrap();
+    assert_eq!(groups.len(), 1);
+    assert_eq!(groups[0].count, 100);
+}
+
+async fn scheduled_and_retryable_tasks(db: &dyn Database) {
+    let now = Utc::now();
+    let due = Task::new("due".to_string(), serde_json::json!({}))
+        .with_scheduled_time(now - ChronoDuration::minutes(1));
+    let later = Task::new("later".to_string(), serde_json::json!({}))
+        .with_scheduled_time(now + ChronoDuration::hours(1));
+    let mut undated = Task::new("undated".to_string(), serde_json::json!({}));
+    undated.state = TaskState::Scheduled;
+    let mut retryable = Task::new("retryable".to_string(), serde_json::json!({}));
+    retryable.mark_failed("Timed out".to_string());
+    let mut exhausted = Task::new("exhausted".to_string(), serde_json::json!({})).with_max_attempts(1);
+    exhausted.mark_failed("Timed out".to_string());
+    let tasks = [due.clone(), later.clone(), undated.clone(), retryable.clone(), exhausted.clone()];
+    db.create_tasks(&tasks).await.unwrap();
+
+    let scheduled = db.get_scheduled_tasks(now).await.unwrap();
+    assert_eq!(scheduled.iter().map(|t| t.id.as_str()).collect::<Vec<_>>(), vec![due.id.as_str()]);
+
+    let failed = db.get_failed_tasks_for_retry().await.unwrap();
+    assert_eq!(failed.iter().map(|t| t.id.as_str()).collect::<Vec<_>>(), vec![retryable.id.as_str()]);
+
+    // Scheduled tasks can only be claimed once they are due, and never without a time
+    assert!(db.claim_task(&later.id, "worker").await.unwrap().is_none());
+    assert!(db.claim_task(&undated.id, "worker").await.unwrap().is_none());
+    let claimed = db.claim_task(&due.id, "worker").await.unwrap().unwrap();
+    assert_eq!(claimed.state, TaskState::Running);
+    assert_eq!(claimed.worker_id.as_deref(), Some("worker"));
+    assert!(db.claim_task(&due.id, "other").await.unwrap().is_none());
+
+    let by_state = db.count_tasks_by_state().await.unwrap();
+    let count = |state: &str| by_state.iter().find(|(s, _)| s == state).map_or(0, |(_, c)| *c);
+    assert_eq!(count("running"), 1);
+    assert_eq!(count("scheduled"), 2);
+    assert_eq!(count("failed"), 2);
+}
+
+async fn ready_tasks_in_queue_order(db: &dyn Database) {
+    let now = Utc::now();
+    let task = |name: &str, priority: TaskPriority, age_minutes: i64| {
+        let mut task = Task::new(name.to_string(), serde_json::json!({})).with_priority(priority);
+        task.created_at = now - ChronoDuration::minutes(age_minutes);
+        task
+    };
+    let old_low = task("old_low", TaskPriority::Low, 30);
+    let new_high = task("new_high", TaskPriority::High, 1);
+    let old_high = task("old_high", TaskPriority::High, 20);
+    let new_low = task("new_low", TaskPriority::Low, 2);
+    let due = task("due", TaskPriority::Medium, 10)
+        .with_scheduled_time(now - ChronoDuration::minutes(1));
+    let later = task("later", TaskPriority::Critical, 5)
+        .with_scheduled_time(now + ChronoDuration::hours(1));
+    let mut running = task("running", TaskPriority::Critical, 5);
+    running.state = TaskState::Running;
+    db.create_tasks(&[old_low, new_high, old_high, new_low, due, later, running])
+        .await
+        .unwrap();
+
+    let names = |tasks: Vec<Task>| tasks.into_iter().map(|t| t.name).collect::<Vec<_>>();
+    let all = db.get_ready_tasks(None, now, 10).await.unwrap();
+    assert_eq!(names(all), vec!["old_high", "new_high", "due", "old_low", "new_low"]);
+
+    let first = db.get_ready_tasks(None, now, 2).await.unwrap();
+    let from = QueuePosition::of(first.last().unwrap());
+    assert_eq!(names(first), vec!["old_high", "new_high"]);
+
+    // Resuming from a task's position includes the task itself
+    let rest = db.get_ready_tasks(Some(from), now, 10).await.unwrap();
+    assert_eq!(names(rest), vec!["new_high", "due", "old_low", "new_low"]);
+}
+
+async fn expiry_stops_waiting_tasks(db: &dyn Database) {
+    let now = Utc::now();
+    let expired_at = now - ChronoDuration::minutes(1);
+    let task = |name: &str| Task::new(name.to_string(), serde_json::json!({})).with_expiry(expired_at);
+    let pending = task("pending");
+    let scheduled = task("scheduled").with_scheduled_time(now - ChronoDuration::minutes(2));
+    let mut retryable = task("retryable");
+    retryable.mark_failed("Timed out".to_string());
+    let mut exhausted = task("exhausted").with_max_attempts(1);
+    exhausted.mark_failed("Timed out".to_string());
+    let mut running = task("running");
+    running.state = TaskState::Running;
+    let unexpired = Task::new("unexpired".to_string(), serde_json::json!({}))
+        .with_expiry(now + ChronoDuration::hours(1));
+    db.create_tasks(&[
+        pending.clone(),
+        scheduled.clone(),
+        retryable.clone(),
+        exhausted.clone(),
+        running.clone(),
+        unexpired.clone(),
+    ])
+    .await
+    .unwrap();
+    // SQLite keeps whole seconds
+    let stored = db.get_task(&unexpired.id).await.unwrap();
+    assert_eq!(
+        stored.expires_at.map(|at| at.timestamp()),
+        unexpired.expires_at.map(|at| at.timestamp())
+    );
+
+    let only_pending = TaskFilter::default().with_id(&pending.id);
+    let expired = db.expire_tasks(&only_pending, now).await.unwrap();
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].state, TaskState::Expired);
+
+    let mut expired: Vec<String> = db
+        .expire_tasks(&TaskFilter::default(), now)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|t| t.name)
+        .collect();
+    expired.sort();
+    assert_eq!(expired, vec!["retryable", "scheduled"]);
+
+    assert_eq!(db.count_tasks(&TaskFilter::default().with_state("expired")).await.unwrap(), 3);
+    assert!(db.claim_task(&pending.id, "worker").await.unwrap().is_none());
+    assert_eq!(db.get_task(&exhausted.id).await.unwrap().state, TaskState::Failed);
+    assert_eq!(db.get_task(&running.id).await.unwrap().state, TaskState::Running);
+    assert_eq!(db.get_task(&unexpired.id).await.unwrap().state, TaskState::Pending);
+}
+
+async fn timeouts_are_kept(db: &dyn Database) {
+    let task = Task::new("report".to_string(), serde_json::json!({})).with_timeout(3600);
+    let default = Task::new("ping".to_string(), serde_json::json!({}));
+    db.create_tasks(&[task.clone(), default.clone()]).await.unwrap();
+
+    // Updates leave the timeout alone, like the other columns fixed at creation
+    let mut updated = task.clone();
+    updated.timeout_seconds = None;
+    db.update_task(&updated).await.unwrap();
+    assert_eq!(db.get_task(&task.id).await.unwrap().timeout_seconds, Some(3600));
+    assert_eq!(db.get_task(&default.id).await.unwrap().timeout_seconds, None);
+}
+
+async fn deadlines_are_kept(db: &dyn Database) {
+    let deadline = Utc::now() + ChronoDuration::hours(1);
+    let task = Task::new("report".to_string(), serde_json::json!({})).with_deadline(deadline);
+    let batched = Task::new("digest".to_string(), serde_json::json!({})).with_deadline(deadline);
+    let default = Task::new("ping".to_string(), serde_json::json!({}));
+    db.create_task(&task).await.unwrap();
+    db.create_tasks(&[batched.clone(), default.clone()]).await.unwrap();
+
+    let mut updated = task.clone();
+    updated.deadline = None;
+    db.update_task(&updated).await.unwrap();
+    for id in [&task.id, &batched.id] {
+        let stored = db.get_task(id).await.unwrap().deadline.unwrap();
+        assert_eq!(stored.timestamp(), deadline.timestamp());
+    }
+    assert_eq!(db.get_task(&default.id).await.unwrap().deadline, None);
+
+    // Tasks without a deadline never match a deadline filter
+    let due_by = |at| TaskFilter {
+        deadline_before: Some(at),
+        ..TaskFilter::default()
+    };
+    assert_eq!(db.count_tasks(&due_by(deadline + ChronoDuration::minutes(1))).await.unwrap(), 2);
+    assert_eq!(db.count_tasks(&due_by(deadline - ChronoDuration::minutes(1))).await.unwrap(), 0);
+}
+
+async fn purge_respects_exclusions_and_age_order(db: &dyn Database) {
+    let now = Utc::now();
+    let finished = |minutes: i64, tags: Vec<String>| {
+        let mut task = Task::new("report".to_string(), serde_json::json!({})).with_tags(tags);
+        task.mark_completed(None);
+        task.updated_at = now - ChronoDuration::minutes(minutes);
+        task
+    };
+    // Oldest first, stored out of order and in every finished state, so that
+    // neither insertion order nor a per-state index scan matches age order
+    let purgeable: Vec<Task> = (0..7)
+        .map(|i| {
+            let mut task = finished(70 - i * 10, Vec::new());
+            match i % 3 {
+                0 => task.state = TaskState::Failed,
+                1 => task.state = TaskState::Cancelled,
+                _ => {}
+            }
+            task
+        })
+        .collect();
+    let kept = finished(100, vec!["audit".to_string()]);
+    let pending = Task::new("report".to_string(), serde_json::json!({}));
+    let mut stored: Vec<Task> = purgeable.iter().rev().cloned().collect();
+    stored.extend([kept.clone(), pending.clone()]);
+    db.create_tasks(&stored).await.unwrap();
+
+    let filter = TaskFilter::default().with_name("report");
+    let exclude = [TaskFilter::default().with_tag("audit")];
+    let ids = |tasks: &[Task]| tasks.iter().map(|t| t.id.clone()).collect::<Vec<_>>();
+
+    let found = db.find_purgeable_tasks(&filter, &exclude, 3).await.unwrap();
+    assert_eq!(ids(&found), ids(&purgeable[..3]));
+
+    // Batches are deleted oldest first, like find_purgeable_tasks returns them
+    let mut purged = db.purge_tasks(&filter, &exclude, 3).await.unwrap();
+    purged.sort();
+    let mut expected = ids(&purgeable[..3]);
+    expected.sort();
+    assert_eq!(purged, expected);
+
+    let mut purged = db.purge_tasks(&filter, &exclude, 3).await.unwrap();
+    purged.sort();
+    let mut expected = ids(&purgeable[3..6]);
+    expected.sort();
+    assert_eq!(purged, expected);
+
+    let purged = db.purge_tasks(&filter, &exclude, 10).await.unwrap();
+    assert_eq!(purged, ids(&purgeable[6..]));
+    assert!(db.get_task(&kept.id).await.is_ok());
+    assert!(db.get_task(&pending.id).await.is_ok());
+    assert_eq!(db.count_tasks(&TaskFilter::default()).await.unwrap(), 2);
+}
+
+async fn conditional_updates(db: &dyn Database) {
+    assert!(matches!(
+        db.get_task("missing").await,
+        Err(AppError::TaskNotFound(_))
+    ));
+
+    let task = Task::new("export".to_string(), serde_json::json!({})).with_trace_id("trace");
+    db.create_task(&task).await.unwrap();
+
+    let mut cancelled = task.clone();
+    cancelled.mark_cancelled();
+    cancelled.trace_id = None;
+    assert!(!db.update_task_if_state(&cancelled, &TaskState::Running).await.unwrap());
+    assert!(db.update_task_if_state(&cancelled, &TaskState::Pending).await.unwrap());
+
+    // The trace ID is kept from when the task was created
+    let stored = db.get_task(&task.id).await.unwrap();
+    assert_eq!(stored.state, TaskState::Cancelled);
+    assert_eq!(stored.trace_id.as_deref(), Some("trace"));
+}
@@ -1,8 +1,31 @@
 use crate::error::AppResult;
-use crate::models::Task;
+use crate::models::{
+    LogLevel, QueuePosition, TagStateCount, Task, TaskCursor, TaskFilter, TaskGroupCount,
+    TaskLogEntry, TaskPriority, TaskProgress, TaskState, TimingStats,
+};
+use crate::storage::migrate::{AppliedMigration, Migration};
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// A task inserted or changed state, as announced by another queue instance
+#[derive(Debug, Clone)]
+pub struct TaskChange {
+    pub id: String,
+    pub state: TaskState,
+    pub scheduled_at: Option<DateTime<Utc>>,
+}
+
+/// What a change listener announces
+#[derive(Debug, Clone)]
+pub enum ChangeEvent {
+    /// A task was inserted or changed state
+    Changed(TaskChange),
+    /// Changes may have been missed, e.g. while reconnecting, so storage has to be
+    /// read again to catch up
+    Missed,
+}
 
 // Trait defining the database operations
 #[async_trait]
@@ -10,27 +33,126 @@ pub trait Database: Send + Sync {
     /// Create a new task in the database
     async fn create_task(&self, task: &Task) -> AppResult<()>;
     
+    /// Create many tasks in a single transaction; either all of them are stored or none
+    async fn create_tasks(&self, tasks: &[Task]) -> AppResult<()>;
+    
     /// Get a task by ID
     async fn get_task(&self, id: &str) -> AppResult<Task>;
     
     /// Update an existing task
     async fn update_task(&self, task: &Task) -> AppResult<()>;
     
-    /// Delete a task by ID
+    /// Update a task only if its stored state still matches `expected`.
+    /// Returns false if the state changed concurrently (or the task no longer exists).
+    async fn update_task_if_state(&self, task: &Task, expected: &TaskState) -> AppResult<bool>;
+    
+    /// Delete a task by ID, along with its logs
     async fn delete_task(&self, id: &str) -> AppResult<()>;
     
-    /// Get all tasks with optional filtering
+    /// Get tasks matching the filter, newest first by `(created_at, id)`,
+    /// starting strictly after the cursor if one is given
     async fn get_tasks(
         &self,
-        state: Option<&str>,
-        priority: Option<&str>,
+        filter: &TaskFilter,
+        after: Option<&TaskCursor>,
         limit: Option<u32>,
-        offset: Option<u32>,
     ) -> AppResult<Vec<Task>>;
     
+    /// Count tasks matching the filter exactly
+    async fn count_tasks(&self, filter: &TaskFilter) -> AppResult<i64>;
+    
+    /// Estimate the number of tasks matching the filter.
+    /// Backends without a cheap estimate fall back to an exact count.
+    async fn estimate_task_count(&self, filter: &TaskFilter) -> AppResult<i64> {
+        self.count_tasks(filter).await
+    }
+    
+    /// Atomically move a pending task, or a scheduled task that is due, to running for the given worker.
+    /// Returns `None` if the task is no longer waiting to run (cancelled, claimed elsewhere, ...)
+    async fn claim_task(&self, id: &str, worker_id: &str) -> AppResult<Option<Task>>;
+    
+    /// Cancel every pending or scheduled task matching the filter, returning the cancelled tasks
+    async fn cancel_tasks(&self, filter: &TaskFilter) -> AppResult<Vec<Task>>;
+    
+    /// Move every task matching the filter that expired by `now` while still waiting to
+    /// run, pending, scheduled or failed with attempts left, to expired. Returns the
+    /// expired tasks.
+    async fn expire_tasks(&self, filter: &TaskFilter, now: DateTime<Utc>) -> AppResult<Vec<Task>>;
+    
+    /// Change the priority of every task matching the filter that hasn't started yet,
+    /// returning their IDs
+    async fn reprioritize_tasks(
+        &self,
+        filter: &TaskFilter,
+        priority: &TaskPriority,
+    ) -> AppResult<Vec<String>>;
+    
+    /// Requeue every failed or cancelled task matching the filter as pending, optionally
+    /// resetting its attempt count, and return the requeued tasks
+    async fn retry_tasks(&self, filter: &TaskFilter, reset_attempts: bool) -> AppResult<Vec<Task>>;
+    
+    /// Delete every task matching the filter that isn't running, along with their logs,
+    /// returning their IDs
+    async fn delete_tasks(&self, filter: &TaskFilter) -> AppResult<Vec<String>>;
+    
+    /// Record a running task's progress, and its checkpoint if one is given.
+    /// Returns false if the task is no longer running.
+    async fn update_task_progress(
+        &self,
+        id: &str,
+        progress: &TaskProgress,
+        checkpoint: Option<&serde_json::Value>,
+    ) -> AppResult<bool>;
+    
+    /// Append a line to a task's log
+    async fn append_task_log(
+        &self,
+        task_id: &str,
+        attempt: u32,
+        level: LogLevel,
+        message: &str,
+        logged_at: DateTime<Utc>,
+    ) -> AppResult<()>;
+    
+    /// Get a task's log lines in the order they were written, starting strictly after `after_seq`
+    async fn get_task_logs(
+        &self,
+        task_id: &str,
+        after_seq: Option<i64>,
+        limit: u32,
+    ) -> AppResult<Vec<TaskLogEntry>>;
+    
+    /// Get up to `limit` finished tasks matching the filter but none of the exclusions,
+    /// least recently changed first. These are the tasks `purge_tasks` would delete.
+    async fn find_purgeable_tasks(
+        &self,
+        filter: &TaskFilter,
+        exclude: &[TaskFilter],
+        limit: u32,
+    ) -> AppResult<Vec<Task>>;
+    
+    /// Delete up to `limit` finished tasks matching the filter but none of the exclusions,
+    /// along with their logs, returning their IDs. Used to purge old tasks in small batches.
+    async fn purge_tasks(
+        &self,
+        filter: &TaskFilter,
+        exclude: &[TaskFilter],
+        limit: u32,
+    ) -> AppResult<Vec<String>>;
+    
     /// Get tasks scheduled to run before the given time
     async fn get_scheduled_tasks(&self, before: DateTime<Utc>) -> AppResult<Vec<Task>>;
     
+    /// Get up to `limit` tasks ready to run, pending or scheduled and due by `now`, in
+    /// queue order: by priority, then oldest first. With `from`, tasks before that
+    /// position are skipped.
+    async fn get_ready_tasks(
+        &self,
+        from: Option<QueuePosition>,
+        now: DateTime<Utc>,
+        limit: u32,
+    ) -> AppResult<Vec<Task>>;
+    
     /// Get tasks that have failed and can be retried
     async fn get_failed_tasks_for_retry(&self) -> AppResult<Vec<Task>>;
     
@@ -40,13 +162,49 @@ pub trait Database: Send + Sync {
     /// Count tasks by priority
     async fn count_tasks_by_priority(&self) -> AppResult<Vec<(String, i64)>>;
     
-    /// Setup database (create tables, etc.)
+    /// Count tasks by state, priority and name
+    async fn count_tasks_by_group(&self) -> AppResult<Vec<TaskGroupCount>>;
+    
+    /// Wait and run time percentiles for tasks started or completed since the given time
+    async fn task_timing_stats(&self, since: DateTime<Utc>) -> AppResult<TimingStats>;
+    
+    /// Count tasks by tag and state
+    async fn count_tasks_by_tag(&self) -> AppResult<Vec<TagStateCount>>;
+    
+    /// Start listening for tasks inserted or changed by other queue instances.
+    /// Backends that can't announce changes return `None` and are only polled.
+    /// Changes known to be missed, e.g. while reconnecting, are announced as
+    /// [`ChangeEvent::Missed`]. Others may go unnoticed, so callers keep polling as well.
+    async fn watch_changes(&self) -> AppResult<Option<mpsc::Receiver<ChangeEvent>>> {
+        Ok(None)
+    }
+    
+    /// Schema migrations known to this backend, ordered by version
+    fn migrations(&self) -> &'static [Migration];
+    
+    /// Migrations recorded as applied; empty if the schema has never been migrated
+    async fn applied_migrations(&self) -> AppResult<Vec<AppliedMigration>>;
+    
+    /// Apply a migration and record it in one transaction. Returns false if it
+    /// had already been applied.
+    async fn apply_migration(&self, migration: &Migration) -> AppResult<bool>;
+    
+    /// Setup database by applying any pending migrations
     async fn setup(&self) -> AppResult<()>;
+    
+    /// Wait for outstanding writes and close connections. Called once at shutdown;
+    /// backends that commit every write synchronously have nothing to do.
+    async fn close(&self) {}
 }
 
 // Factory function to create a database instance based on URL
 pub async fn create_database(database_url: &str) -> AppResult<Arc<dyn Database>> {
-    if database_url.starts_with("sqlite:") {
+    if database_url.starts_with("memory:") {
+        Ok(Arc::new(super::memory::MemoryDatabase::new()))
+    } else if let Some(path) = database_url.strip_prefix("redb:") {
+        let db = super::redb::RedbDatabase::new(path.trim_start_matches("//"))?;
+        Ok(Arc::new(db))
+    } else if database_url.starts_with("sqlite:") {
         let db = super::sqlite::SqliteDatabase::new(database_url).await?;
         Ok(Arc::new(db))
     } else {
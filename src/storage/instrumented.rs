@@ -0,0 +1,228 @@
+use crate::error::AppResult;
+use crate::metrics::Metrics;
+use crate::models::{
+    LogLevel, QueuePosition, TagStateCount, Task, TaskCursor, TaskFilter, TaskGroupCount,
+    TaskLogEntry, TaskPriority, TaskProgress, TaskState, TimingStats,
+};
+use crate::storage::database::{ChangeEvent, Database};
+use crate::storage::migrate::{AppliedMigration, Migration};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// Database wrapper that records the latency of every call in the metrics
+pub struct InstrumentedDatabase {
+    inner: Arc<dyn Database>,
+    metrics: Arc<Metrics>,
+}
+
+impl InstrumentedDatabase {
+    pub fn new(inner: Arc<dyn Database>, metrics: Arc<Metrics>) -> Self {
+        Self { inner, metrics }
+    }
+
+    async fn timed<T>(&self, method: &str, call: impl Future<Output = AppResult<T>>) -> AppResult<T> {
+        let timer = self
+            .metrics
+            .db_query_seconds
+            .with_label_values(&[method])
+            .start_timer();
+        let result = call.await;
+        timer.observe_duration();
+        result
+    }
+}
+
+#[async_trait]
+impl Database for InstrumentedDatabase {
+    async fn create_task(&self, task: &Task) -> AppResult<()> {
+        self.timed("create_task", self.inner.create_task(task)).await
+    }
+
+    async fn create_tasks(&self, tasks: &[Task]) -> AppResult<()> {
+        self.timed("create_tasks", self.inner.create_tasks(tasks)).await
+    }
+
+    async fn get_task(&self, id: &str) -> AppResult<Task> {
+        self.timed("get_task", self.inner.get_task(id)).await
+    }
+
+    async fn update_task(&self, task: &Task) -> AppResult<()> {
+        self.timed("update_task", self.inner.update_task(task)).await
+    }
+
+    async fn update_task_if_state(&self, task: &Task, expected: &TaskState) -> AppResult<bool> {
+        self.timed("update_task_if_state", self.inner.update_task_if_state(task, expected)).await
+    }
+
+    async fn delete_task(&self, id: &str) -> AppResult<()> {
+        self.timed("delete_task", self.inner.delete_task(id)).await
+    }
+
+    async fn get_tasks(
+        &self,
+        filter: &TaskFilter,
+        after: Option<&TaskCursor>,
+        limit: Option<u32>,
+    ) -> AppResult<Vec<Task>> {
+        self.timed("get_tasks", self.inner.get_tasks(filter, after, limit)).await
+    }
+
+    async fn count_tasks(&self, filter: &TaskFilter) -> AppResult<i64> {
+        self.timed("count_tasks", self.inner.count_tasks(filter)).await
+    }
+
+    async fn estimate_task_count(&self, filter: &TaskFilter) -> AppResult<i64> {
+        self.timed("estimate_task_count", self.inner.estimate_task_count(filter)).await
+    }
+
+    async fn claim_task(&self, id: &str, worker_id: &str) -> AppResult<Option<Task>> {
+        self.timed("claim_task", self.inner.claim_task(id, worker_id)).await
+    }
+
+    async fn cancel_tasks(&self, filter: &TaskFilter) -> AppResult<Vec<Task>> {
+        self.timed("cancel_tasks", self.inner.cancel_tasks(filter)).await
+    }
+
+    async fn expire_tasks(&self, filter: &TaskFilter, now: DateTime<Utc>) -> AppResult<Vec<Task>> {
+        self.timed("expire_tasks", self.inner.expire_tasks(filter, now)).await
+    }
+
+    async fn reprioritize_tasks(
+        &self,
+        filter: &TaskFilter,
+        priority: &TaskPriority,
+    ) -> AppResult<Vec<String>> {
+        self.timed("reprioritize_tasks", self.inner.reprioritize_tasks(filter, priority)).await
+    }
+
+    async fn retry_tasks(&self, filter: &TaskFilter, reset_attempts: bool) -> AppResult<Vec<Task>> {
+        self.timed("retry_tasks", self.inner.retry_tasks(filter, reset_attempts)).await
+    }
+
+    async fn delete_tasks(&self, filter: &TaskFilter) -> AppResult<Vec<String>> {
+        self.timed("delete_tasks", self.inner.delete_tasks(filter)).await
+    }
+
+    async fn update_task_progress(
+        &self,
+        id: &str,
+        progress: &TaskProgress,
+        checkpoint: Option<&serde_json::Value>,
+    ) -> AppResult<bool> {
+        self.timed(
+            "update_task_progress",
+            self.inner.update_task_progress(id, progress, checkpoint),
+        )
+        .await
+    }
+
+    async fn append_task_log(
+        &self,
+        task_id: &str,
+        attempt: u32,
+        level: LogLevel,
+        message: &str,
+        logged_at: DateTime<Utc>,
+    ) -> AppResult<()> {
+        self.timed(
+            "append_task_log",
+            self.inner.append_task_log(task_id, attempt, level, message, logged_at),
+        )
+        .await
+    }
+
+    async fn get_task_logs(
+        &self,
+        task_id: &str,
+        after_seq: Option<i64>,
+        limit: u32,
+    ) -> AppResult<Vec<TaskLogEntry>> {
+        self.timed("get_task_logs", self.inner.get_task_logs(task_id, after_seq, limit)).await
+    }
+
+    async fn find_purgeable_tasks(
+        &self,
+        filter: &TaskFilter,
+        exclude: &[TaskFilter],
+        limit: u32,
+    ) -> AppResult<Vec<Task>> {
+        self.timed(
+            "find_purgeable_tasks",
+            self.inner.find_purgeable_tasks(filter, exclude, limit),
+        )
+        .await
+    }
+
+    async fn purge_tasks(
+        &self,
+        filter: &TaskFilter,
+        exclude: &[TaskFilter],
+        limit: u32,
+    ) -> AppResult<Vec<String>> {
+        self.timed("purge_tasks", self.inner.purge_tasks(filter, exclude, limit)).await
+    }
+
+    async fn get_scheduled_tasks(&self, before: DateTime<Utc>) -> AppResult<Vec<Task>> {
+        self.timed("get_scheduled_tasks", self.inner.get_scheduled_tasks(before)).await
+    }
+
+    async fn get_ready_tasks(
+        &self,
+        from: Option<QueuePosition>,
+        now: DateTime<Utc>,
+        limit: u32,
+    ) -> AppResult<Vec<Task>> {
+        self.timed("get_ready_tasks", self.inner.get_ready_tasks(from, now, limit)).await
+    }
+
+    async fn get_failed_tasks_for_retry(&self) -> AppResult<Vec<Task>> {
+        self.timed("get_failed_tasks_for_retry", self.inner.get_failed_tasks_for_retry()).await
+    }
+
+    async fn count_tasks_by_state(&self) -> AppResult<Vec<(String, i64)>> {
+        self.timed("count_tasks_by_state", self.inner.count_tasks_by_state()).await
+    }
+
+    async fn count_tasks_by_priority(&self) -> AppResult<Vec<(String, i64)>> {
+        self.timed("count_tasks_by_priority", self.inner.count_tasks_by_priority()).await
+    }
+
+    async fn count_tasks_by_group(&self) -> AppResult<Vec<TaskGroupCount>> {
+        self.timed("count_tasks_by_group", self.inner.count_tasks_by_group()).await
+    }
+
+    async fn task_timing_stats(&self, since: DateTime<Utc>) -> AppResult<TimingStats> {
+        self.timed("task_timing_stats", self.inner.task_timing_stats(since)).await
+    }
+
+    async fn count_tasks_by_tag(&self) -> AppResult<Vec<TagStateCount>> {
+        self.timed("count_tasks_by_tag", self.inner.count_tasks_by_tag()).await
+    }
+
+    async fn watch_changes(&self) -> AppResult<Option<mpsc::Receiver<ChangeEvent>>> {
+        self.inner.watch_changes().await
+    }
+
+    fn migrations(&self) -> &'static [Migration] {
+        self.inner.migrations()
+    }
+
+    async fn applied_migrations(&self) -> AppResult<Vec<AppliedMigration>> {
+        self.timed("applied_migrations", self.inner.applied_migrations()).await
+    }
+
+    async fn apply_migration(&self, migration: &Migration) -> AppResult<bool> {
+        self.timed("apply_migration", self.inner.apply_migration(migration)).await
+    }
+
+    async fn setup(&self) -> AppResult<()> {
+        self.timed("setup", self.inner.setup()).await
+    }
+
+    async fn close(&self) {
+        self.inner.close().await
+    }
+}
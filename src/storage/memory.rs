@@ -0,0 +1,569 @@
+use crate::error::{AppError, AppResult};
+use crate::models::{
+    DurationPercentiles, LogLevel, QueuePosition, TagStateCount, Task, TaskCursor, TaskFilter,
+    TaskGroupCount, TaskLogEntry, TaskPriority, TaskProgress, TaskState, TimingStats,
+};
+use crate::storage::database::Database;
+use crate::storage::migrate::{AppliedMigration, Migration};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use tracing::info;
+
+/// Database kept entirely in process memory, for tests and ephemeral
+/// deployments. Filtering and ordering follow the SQL backends; nothing
+/// survives a restart.
+#[derive(Default)]
+pub struct MemoryDatabase {
+    state: RwLock<MemoryState>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    tasks: HashMap<String, Task>,
+    logs: Vec<TaskLogEntry>,
+    next_log_seq: i64,
+}
+
+impl MemoryDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl MemoryState {
+    // Tasks matching the predicate, newest first like the SQL listings
+    fn select(&self, predicate: impl Fn(&Task) -> bool) -> Vec<&Task> {
+        let mut tasks: Vec<&Task> = self.tasks.values().filter(|task| predicate(task)).collect();
+        tasks.sort_by(|a, b| (&b.created_at, &b.id).cmp(&(&a.created_at, &a.id)));
+        tasks
+    }
+
+    // Update every task matching the predicate, returning the updated tasks
+    fn update(
+        &mut self,
+        predicate: impl Fn(&Task) -> bool,
+        change: impl Fn(&mut Task),
+    ) -> Vec<Task> {
+        let ids: Vec<String> = self.select(predicate).into_iter().map(|t| t.id.clone()).collect();
+        let mut updated = Vec::with_capacity(ids.len());
+        for id in ids {
+            if let Some(task) = self.tasks.get_mut(&id) {
+                change(task);
+                updated.push(task.clone());
+            }
+        }
+        updated
+    }
+
+    // Remove tasks and their logs
+    fn remove(&mut self, ids: &[String]) {
+        for id in ids {
+            self.tasks.remove(id);
+        }
+        let removed: HashSet<&String> = ids.iter().collect();
+        self.logs.retain(|entry| !removed.contains(&entry.task_id));
+    }
+
+    // Finished tasks matching the filter but none of the exclusions, least recently changed first
+    fn purgeable(&self, filter: &TaskFilter, exclude: &[TaskFilter], limit: u32) -> Vec<&Task> {
+        let mut tasks = self.select(|task| {
+            task.state.is_finished()
+                && filter.matches(task)
+                && !exclude.iter().any(|excluded| excluded.matches(task))
+        });
+        tasks.sort_by(|a, b| (a.updated_at, &a.id).cmp(&(b.updated_at, &b.id)));
+        tasks.truncate(limit as usize);
+        tasks
+    }
+}
+
+// Replace a stored task with an updated copy. Like the SQL backends' UPDATE,
+// this leaves the columns fixed at creation alone.
+pub(super) fn overwrite(stored: &mut Task, task: &Task) {
+    let created_at = stored.created_at;
+    let trace_id = stored.trace_id.take();
+    let tenant = stored.tenant.take();
+    let expires_at = stored.expires_at;
+    let timeout_seconds = stored.timeout_seconds;
+    let deadline = stored.deadline;
+    *stored = Task {
+        created_at,
+        trace_id,
+        tenant,
+        expires_at,
+        timeout_seconds,
+        deadline,
+        ..task.clone()
+    };
+}
+
+// Whether a task is ready to run at `now`, as the SQL backends' get_ready_tasks selects
+pub(super) fn is_ready(task: &Task, now: DateTime<Utc>) -> bool {
+    match task.state {
+        TaskState::Pending => true,
+        TaskState::Scheduled => task.scheduled_at.is_some_and(|at| at <= now),
+        _ => false,
+    }
+}
+
+// Whether a task expired by `now` while waiting to run, as the SQL backends'
+// expire_tasks selects
+pub(super) fn is_expiring(task: &Task, now: DateTime<Utc>) -> bool {
+    let waiting = match task.state {
+        TaskState::Pending | TaskState::Scheduled => true,
+        TaskState::Failed => task.attempts < task.max_attempts,
+        _ => false,
+    };
+    waiting && task.is_expired(now)
+}
+
+// The first `limit` ready tasks at or after `from` in queue order
+pub(super) fn in_queue_order(
+    tasks: impl IntoIterator<Item = Task>,
+    from: Option<QueuePosition>,
+    limit: u32,
+) -> Vec<Task> {
+    let mut tasks: Vec<Task> = tasks
+        .into_iter()
+        .filter(|task| from.is_none_or(|from| QueuePosition::of(task) >= from))
+        .collect();
+    tasks.sort_by(|a, b| {
+        (a.priority.rank(), a.created_at, &a.id).cmp(&(b.priority.rank(), b.created_at, &b.id))
+    });
+    tasks.truncate(limit as usize);
+    tasks
+}
+
+// The SQL backends order by the stored priority text, so do the same
+pub(super) fn priority_key(task: &Task) -> Reverse<String> {
+    Reverse(task.priority.to_string())
+}
+
+fn seconds_between(from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+    (to - from).num_microseconds().unwrap_or(i64::MAX) as f64 / 1_000_000.0
+}
+
+// Nearest-rank percentiles: the value at rank ceil(p * n)
+fn duration_percentiles(mut samples: Vec<f64>) -> DurationPercentiles {
+    samples.sort_by(f64::total_cmp);
+    let n = samples.len();
+    let percentile = |p: f64| {
+        let rank = ((p * n as f64).ceil() as usize).max(1);
+        samples.get(rank - 1).copied()
+    };
+
+    DurationPercentiles {
+        count: n as i64,
+        p50: percentile(0.50),
+        p95: percentile(0.95),
+        p99: percentile(0.99),
+    }
+}
+
+// Count tasks by a key, ordered by the key
+fn count_by<'a, K: Ord>(
+    tasks: impl IntoIterator<Item = &'a Task>,
+    key: impl Fn(&Task) -> K,
+) -> BTreeMap<K, i64> {
+    let mut counts = BTreeMap::new();
+    for task in tasks {
+        *counts.entry(key(task)).or_insert(0) += 1;
+    }
+    counts
+}
+
+// Tasks counted by state, priority and name, in that order
+pub(super) fn group_counts<'a>(tasks: impl IntoIterator<Item = &'a Task>) -> Vec<TaskGroupCount> {
+    count_by(tasks, |task| {
+        (task.state.to_string(), task.priority.to_string(), task.name.clone())
+    })
+    .into_iter()
+    .map(|((state, priority, name), count)| TaskGroupCount {
+        state,
+        priority,
+        name,
+        count,
+    })
+    .collect()
+}
+
+// Tasks counted by tag and state, in that order
+pub(super) fn tag_counts<'a>(tasks: impl IntoIterator<Item = &'a Task>) -> Vec<TagStateCount> {
+    let mut counts: BTreeMap<(String, String), i64> = BTreeMap::new();
+    for task in tasks {
+        let tags: HashSet<&String> = task.tags.iter().collect();
+        for tag in tags {
+            *counts.entry((tag.clone(), task.state.to_string())).or_insert(0) += 1;
+        }
+    }
+
+    counts
+        .into_iter()
+        .map(|((tag, state), count)| TagStateCount { tag, state, count })
+        .collect()
+}
+
+// Wait and run time percentiles computed the same way as the SQL backends
+pub(super) fn timing_stats<'a>(
+    tasks: impl IntoIterator<Item = &'a Task>,
+    since: DateTime<Utc>,
+) -> TimingStats {
+    let mut wait = Vec::new();
+    let mut run = Vec::new();
+
+    for task in tasks {
+        if let Some(started_at) = task.started_at.filter(|at| *at >= since) {
+            let runnable_at = task.created_at.max(task.scheduled_at.unwrap_or(task.created_at));
+            wait.push(seconds_between(runnable_at, started_at).max(0.0));
+        }
+
+        if task.state == TaskState::Completed {
+            if let (Some(started_at), Some(completed_at)) =
+                (task.started_at, task.completed_at.filter(|at| *at >= since))
+            {
+                run.push(seconds_between(started_at, completed_at));
+            }
+        }
+    }
+
+    TimingStats {
+        wait: duration_percentiles(wait),
+        run: duration_percentiles(run),
+    }
+}
+
+#[async_trait]
+impl Database for MemoryDatabase {
+    async fn create_task(&self, task: &Task) -> AppResult<()> {
+        self.create_tasks(std::slice::from_ref(task)).await
+    }
+
+    async fn create_tasks(&self, tasks: &[Task]) -> AppResult<()> {
+        let mut state = self.state.write();
+
+        let mut seen = HashSet::with_capacity(tasks.len());
+        for task in tasks {
+            if state.tasks.contains_key(&task.id) || !seen.insert(&task.id) {
+                return Err(AppError::TaskAlreadyExists(task.id.clone()));
+            }
+        }
+
+        for task in tasks {
+            state.tasks.insert(task.id.clone(), task.clone());
+        }
+        Ok(())
+    }
+
+    async fn get_task(&self, id: &str) -> AppResult<Task> {
+        self.state
+            .read()
+            .tasks
+            .get(id)
+            .cloned()
+            .ok_or_else(|| AppError::TaskNotFound(id.to_string()))
+    }
+
+    async fn update_task(&self, task: &Task) -> AppResult<()> {
+        let mut state = self.state.write();
+        if let Some(stored) = state.tasks.get_mut(&task.id) {
+            overwrite(stored, task);
+        }
+        Ok(())
+    }
+
+    async fn update_task_if_state(&self, task: &Task, expected: &TaskState) -> AppResult<bool> {
+        let mut state = self.state.write();
+        match state.tasks.get_mut(&task.id) {
+            Some(stored) if stored.state == *expected => {
+                overwrite(stored, task);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn delete_task(&self, id: &str) -> AppResult<()> {
+        self.state.write().remove(&[id.to_string()]);
+        Ok(())
+    }
+
+    async fn get_tasks(
+        &self,
+        filter: &TaskFilter,
+        after: Option<&TaskCursor>,
+        limit: Option<u32>,
+    ) -> AppResult<Vec<Task>> {
+        let state = self.state.read();
+        let tasks = state.select(|task| {
+            filter.matches(task)
+                && after.is_none_or(|cursor| {
+                    (task.created_at, &task.id) < (cursor.created_at, &cursor.id)
+                })
+        });
+
+        Ok(tasks
+            .into_iter()
+            .take(limit.map_or(usize::MAX, |limit| limit as usize))
+            .cloned()
+            .collect())
+    }
+
+    async fn count_tasks(&self, filter: &TaskFilter) -> AppResult<i64> {
+        let state = self.state.read();
+        Ok(state.tasks.values().filter(|task| filter.matches(task)).count() as i64)
+    }
+
+    async fn claim_task(&self, id: &str, worker_id: &str) -> AppResult<Option<Task>> {
+        let now = Utc::now();
+        let mut state = self.state.write();
+
+        let Some(task) = state.tasks.get_mut(id) else {
+            return Ok(None);
+        };
+        if !is_ready(task, now) {
+            return Ok(None);
+        }
+
+        task.state = TaskState::Running;
+        task.worker_id = Some(worker_id.to_string());
+        task.started_at = Some(now);
+        task.updated_at = now;
+        task.progress = None;
+        Ok(Some(task.clone()))
+    }
+
+    async fn cancel_tasks(&self, filter: &TaskFilter) -> AppResult<Vec<Task>> {
+        let now = Utc::now();
+        Ok(self.state.write().update(
+            |task| {
+                matches!(task.state, TaskState::Pending | TaskState::Scheduled)
+                    && filter.matches(task)
+            },
+            |task| {
+                task.state = TaskState::Cancelled;
+                task.updated_at = now;
+            },
+        ))
+    }
+
+    async fn expire_tasks(&self, filter: &TaskFilter, now: DateTime<Utc>) -> AppResult<Vec<Task>> {
+        Ok(self.state.write().update(
+            |task| is_expiring(task, now) && filter.matches(task),
+            |task| {
+                task.state = TaskState::Expired;
+                task.updated_at = now;
+            },
+        ))
+    }
+
+    async fn reprioritize_tasks(
+        &self,
+        filter: &TaskFilter,
+        priority: &TaskPriority,
+    ) -> AppResult<Vec<String>> {
+        let now = Utc::now();
+        let tasks = self.state.write().update(
+            |task| {
+                matches!(
+                    task.state,
+                    TaskState::Pending | TaskState::Scheduled | TaskState::Failed
+                ) && filter.matches(task)
+            },
+            |task| {
+                task.priority = priority.clone();
+                task.updated_at = now;
+            },
+        );
+        Ok(tasks.into_iter().map(|task| task.id).collect())
+    }
+
+    async fn retry_tasks(&self, filter: &TaskFilter, reset_attempts: bool) -> AppResult<Vec<Task>> {
+        let now = Utc::now();
+        Ok(self.state.write().update(
+            |task| {
+                matches!(task.state, TaskState::Failed | TaskState::Cancelled)
+                    && filter.matches(task)
+            },
+            |task| {
+                task.state = TaskState::Pending;
+                task.worker_id = None;
+                task.started_at = None;
+                task.completed_at = None;
+                task.updated_at = now;
+                if reset_attempts {
+                    task.attempts = 0;
+                }
+            },
+        ))
+    }
+
+    async fn delete_tasks(&self, filter: &TaskFilter) -> AppResult<Vec<String>> {
+        let mut state = self.state.write();
+        let ids: Vec<String> = state
+            .select(|task| task.state != TaskState::Running && filter.matches(task))
+            .into_iter()
+            .map(|task| task.id.clone())
+            .collect();
+        state.remove(&ids);
+        Ok(ids)
+    }
+
+    async fn find_purgeable_tasks(
+        &self,
+        filter: &TaskFilter,
+        exclude: &[TaskFilter],
+        limit: u32,
+    ) -> AppResult<Vec<Task>> {
+        let state = self.state.read();
+        Ok(state.purgeable(filter, exclude, limit).into_iter().cloned().collect())
+    }
+
+    async fn purge_tasks(
+        &self,
+        filter: &TaskFilter,
+        exclude: &[TaskFilter],
+        limit: u32,
+    ) -> AppResult<Vec<String>> {
+        let mut state = self.state.write();
+        let ids: Vec<String> = state
+            .purgeable(filter, exclude, limit)
+            .into_iter()
+            .map(|task| task.id.clone())
+            .collect();
+        state.remove(&ids);
+        Ok(ids)
+    }
+
+    async fn update_task_progress(
+        &self,
+        id: &str,
+        progress: &TaskProgress,
+        checkpoint: Option<&serde_json::Value>,
+    ) -> AppResult<bool> {
+        let mut state = self.state.write();
+        match state.tasks.get_mut(id) {
+            Some(task) if task.state == TaskState::Running => {
+                task.progress = Some(progress.clone());
+                if let Some(checkpoint) = checkpoint {
+                    task.checkpoint = Some(checkpoint.clone());
+                }
+                task.updated_at = progress.updated_at;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn append_task_log(
+        &self,
+        task_id: &str,
+        attempt: u32,
+        level: LogLevel,
+        message: &str,
+        logged_at: DateTime<Utc>,
+    ) -> AppResult<()> {
+        let mut state = self.state.write();
+        state.next_log_seq += 1;
+        let seq = state.next_log_seq;
+        state.logs.push(TaskLogEntry {
+            seq,
+            task_id: task_id.to_string(),
+            attempt,
+            level,
+            message: message.to_string(),
+            logged_at,
+        });
+        Ok(())
+    }
+
+    async fn get_task_logs(
+        &self,
+        task_id: &str,
+        after_seq: Option<i64>,
+        limit: u32,
+    ) -> AppResult<Vec<TaskLogEntry>> {
+        let after_seq = after_seq.unwrap_or(0);
+        let state = self.state.read();
+
+        // Entries are appended in sequence order
+        Ok(state
+            .logs
+            .iter()
+            .filter(|entry| entry.task_id == task_id && entry.seq > after_seq)
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_scheduled_tasks(&self, before: DateTime<Utc>) -> AppResult<Vec<Task>> {
+        let state = self.state.read();
+        let mut tasks = state.select(|task| {
+            task.state == TaskState::Scheduled && task.scheduled_at.is_some_and(|at| at <= before)
+        });
+        tasks.sort_by_key(|task| (priority_key(task), task.scheduled_at));
+        Ok(tasks.into_iter().cloned().collect())
+    }
+
+    async fn get_ready_tasks(
+        &self,
+        from: Option<QueuePosition>,
+        now: DateTime<Utc>,
+        limit: u32,
+    ) -> AppResult<Vec<Task>> {
+        let state = self.state.read();
+        let ready = state.select(|task| is_ready(task, now));
+        Ok(in_queue_order(ready.into_iter().cloned(), from, limit))
+    }
+
+    async fn get_failed_tasks_for_retry(&self) -> AppResult<Vec<Task>> {
+        let state = self.state.read();
+        let mut tasks = state.select(|task| {
+            task.state == TaskState::Failed && task.attempts < task.max_attempts
+        });
+        tasks.sort_by_key(|task| (priority_key(task), task.updated_at));
+        Ok(tasks.into_iter().cloned().collect())
+    }
+
+    async fn count_tasks_by_state(&self) -> AppResult<Vec<(String, i64)>> {
+        let state = self.state.read();
+        Ok(count_by(state.tasks.values(), |task| task.state.to_string()).into_iter().collect())
+    }
+
+    async fn count_tasks_by_priority(&self) -> AppResult<Vec<(String, i64)>> {
+        let state = self.state.read();
+        Ok(count_by(state.tasks.values(), |task| task.priority.to_string()).into_iter().collect())
+    }
+
+    async fn count_tasks_by_group(&self) -> AppResult<Vec<TaskGroupCount>> {
+        Ok(group_counts(self.state.read().tasks.values()))
+    }
+
+    async fn task_timing_stats(&self, since: DateTime<Utc>) -> AppResult<TimingStats> {
+        Ok(timing_stats(self.state.read().tasks.values(), since))
+    }
+
+    async fn count_tasks_by_tag(&self) -> AppResult<Vec<TagStateCount>> {
+        Ok(tag_counts(self.state.read().tasks.values()))
+    }
+
+    // There is no schema to migrate
+    fn migrations(&self) -> &'static [Migration] {
+        &[]
+    }
+
+    async fn applied_migrations(&self) -> AppResult<Vec<AppliedMigration>> {
+        Ok(Vec::new())
+    }
+
+    async fn apply_migration(&self, _migration: &Migration) -> AppResult<bool> {
+        Ok(false)
+    }
+
+    async fn setup(&self) -> AppResult<()> {
+        info!("Using in-memory database; tasks will not survive a restart");
+        Ok(())
+    }
+}
@@ -0,0 +1,221 @@
+use crate::error::{AppError, AppResult};
+use crate::storage::database::Database;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::info;
+
+/// A numbered schema change. Each backend keeps its own list, ordered by version.
+#[derive(Debug)]
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+/// A migration recorded in the `schema_migrations` table
+#[derive(Debug, Clone, Serialize)]
+pub struct AppliedMigration {
+    pub version: i64,
+    pub description: String,
+    pub applied_at: DateTime<Utc>,
+}
+
+/// A known migration and when it was applied, if it has been
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied_at: Option<DateTime<Utc>>,
+}
+
+pub const SQLITE_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        sql: include_str!("migrations/sqlite/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        description: "task tenants",
+        sql: include_str!("migrations/sqlite/0002_task_tenants.sql"),
+    },
+    Migration {
+        version: 3,
+        description: "ready task order",
+        sql: include_str!("migrations/sqlite/0003_ready_task_order.sql"),
+    },
+    Migration {
+        version: 4,
+        description: "task expiry",
+        sql: include_str!("migrations/sqlite/0004_task_expiry.sql"),
+    },
+    Migration {
+        version: 5,
+        description: "task timeouts",
+        sql: include_str!("migrations/sqlite/0005_task_timeouts.sql"),
+    },
+    Migration {
+        version: 6,
+        description: "task deadlines",
+        sql: include_str!("migrations/sqlite/0006_task_deadlines.sql"),
+    },
+    Migration {
+        version: 7,
+        description: "task deadline index",
+        sql: include_str!("migrations/sqlite/0007_task_deadline_index.sql"),
+    },
+];
+
+pub const POSTGRES_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        sql: include_str!("migrations/postgres/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        description: "task change notifications",
+        sql: include_str!("migrations/postgres/0002_task_change_notifications.sql"),
+    },
+    Migration {
+        version: 3,
+        description: "task tenants",
+        sql: include_str!("migrations/postgres/0003_task_tenants.sql"),
+    },
+    Migration {
+        version: 4,
+        description: "ready task order",
+        sql: include_str!("migrations/postgres/0004_ready_task_order.sql"),
+    },
+    Migration {
+        version: 5,
+        description: "task expiry",
+        sql: include_str!("migrations/postgres/0005_task_expiry.sql"),
+    },
+    Migration {
+        version: 6,
+        description: "task timeouts",
+        sql: include_str!("migrations/postgres/0006_task_timeouts.sql"),
+    },
+    Migration {
+        version: 7,
+        description: "task deadlines",
+        sql: include_str!("migrations/postgres/0007_task_deadlines.sql"),
+    },
+    Migration {
+        version: 8,
+        description: "task deadline index",
+        sql: include_str!("migrations/postgres/0008_task_deadline_index.sql"),
+    },
+];
+
+/// Every known migration with the time it was applied
+pub async fn status(db: &dyn Database) -> AppResult<Vec<MigrationStatus>> {
+    let applied = db.applied_migrations().await?;
+    check_not_newer(db, &applied)?;
+
+    Ok(db
+        .migrations()
+        .iter()
+        .map(|migration| MigrationStatus {
+            version: migration.version,
+            description: migration.description.to_string(),
+            applied_at: applied
+                .iter()
+                .find(|applied| applied.version == migration.version)
+                .map(|applied| applied.applied_at),
+        })
+        .collect())
+}
+
+/// Migrations that have not been applied yet, in order
+pub async fn pending(db: &dyn Database) -> AppResult<Vec<&'static Migration>> {
+    let applied = db.applied_migrations().await?;
+    check_not_newer(db, &applied)?;
+
+    Ok(db
+        .migrations()
+        .iter()
+        .filter(|migration| !applied.iter().any(|applied| applied.version == migration.version))
+        .collect())
+}
+
+/// Apply pending migrations in order, or only list them when `dry_run` is set.
+/// Returns the migrations that were (or would be) applied.
+pub async fn migrate(db: &dyn Database, dry_run: bool) -> AppResult<Vec<&'static Migration>> {
+    let pending = pending(db).await?;
+    if dry_run {
+        return Ok(pending);
+    }
+
+    let mut applied = Vec::with_capacity(pending.len());
+    for migration in pending {
+        // Another instance may have got there first
+        if db.apply_migration(migration).await? {
+            info!(
+                "Applied migration {} ({})",
+                migration.version, migration.description
+            );
+            applied.push(migration);
+        }
+    }
+    Ok(applied)
+}
+
+/// Fail unless the schema is exactly the one this build expects
+pub async fn verify(db: &dyn Database) -> AppResult<()> {
+    let pending = pending(db).await?;
+    if let Some(first) = pending.first() {
+        return Err(AppError::ConfigError(format!(
+            "Database schema is out of date: {} pending migration(s) starting at version {}; run `migrate up`",
+            pending.len(),
+            first.version
+        )));
+    }
+    Ok(())
+}
+
+// Refuse to touch a schema written by a newer build, which may have changed
+// tables in ways this one doesn't understand
+fn check_not_newer(db: &dyn Database, applied: &[AppliedMigration]) -> AppResult<()> {
+    let latest = db.migrations().last().map_or(0, |migration| migration.version);
+    match applied.iter().map(|applied| applied.version).max() {
+        Some(version) if version > latest => Err(AppError::ConfigError(format!(
+            "Database schema version {} is newer than the latest version {} known to this build",
+            version, latest
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::create_database;
+
+    #[tokio::test]
+    async fn test_migrations_apply_once_and_refuse_newer_schema() {
+        let path = std::env::temp_dir().join(format!("taskqueue-{}.db", uuid::Uuid::new_v4()));
+        let db = create_database(&format!("sqlite:{}?mode=rwc", path.display()))
+            .await
+            .unwrap();
+
+        assert_eq!(migrate(db.as_ref(), true).await.unwrap().len(), SQLITE_MIGRATIONS.len());
+        assert!(verify(db.as_ref()).await.is_err());
+
+        db.setup().await.unwrap();
+        db.setup().await.unwrap();
+        verify(db.as_ref()).await.unwrap();
+        assert!(migrate(db.as_ref(), false).await.unwrap().is_empty());
+        assert!(status(db.as_ref()).await.unwrap().iter().all(|m| m.applied_at.is_some()));
+
+        let newer = Migration {
+            version: SQLITE_MIGRATIONS.len() as i64 + 1,
+            description: "from the future",
+            sql: "SELECT 1",
+        };
+        assert!(db.apply_migration(&newer).await.unwrap());
+        assert!(db.setup().await.is_err());
+        assert!(status(db.as_ref()).await.is_err());
+    }
+}
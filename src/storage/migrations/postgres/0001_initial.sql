@@ -0,0 +1,58 @@
+-- Baseline schema. Databases created before migrations existed already have
+-- some of these objects, so every statement tolerates them being present.
+
+CREATE TABLE IF NOT EXISTS tasks (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    state TEXT NOT NULL,
+    priority TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL,
+    scheduled_at TIMESTAMPTZ,
+    started_at TIMESTAMPTZ,
+    completed_at TIMESTAMPTZ,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    max_attempts INTEGER NOT NULL DEFAULT 3,
+    last_error TEXT,
+    worker_id TEXT,
+    result JSONB,
+    tags TEXT[] NOT NULL DEFAULT '{}',
+    trace_id TEXT,
+    progress JSONB,
+    checkpoint JSONB
+);
+
+-- Tables created by older versions lack the newer columns
+ALTER TABLE tasks ADD COLUMN IF NOT EXISTS trace_id TEXT;
+ALTER TABLE tasks ADD COLUMN IF NOT EXISTS progress JSONB;
+ALTER TABLE tasks ADD COLUMN IF NOT EXISTS checkpoint JSONB;
+
+CREATE INDEX IF NOT EXISTS idx_tasks_state ON tasks (state);
+CREATE INDEX IF NOT EXISTS idx_tasks_priority ON tasks (priority);
+CREATE INDEX IF NOT EXISTS idx_tasks_scheduled_at ON tasks (scheduled_at);
+CREATE INDEX IF NOT EXISTS idx_tasks_created_at ON tasks (created_at);
+
+-- Lets retention purges find old finished tasks without a full scan
+CREATE INDEX IF NOT EXISTS idx_tasks_state_updated_at ON tasks (state, updated_at);
+
+-- Support the time windows of the queue statistics
+CREATE INDEX IF NOT EXISTS idx_tasks_started_at ON tasks (started_at);
+CREATE INDEX IF NOT EXISTS idx_tasks_completed_at ON tasks (completed_at);
+
+-- Supports keyset pagination ordered by (created_at, id)
+CREATE INDEX IF NOT EXISTS idx_tasks_created_at_id ON tasks (created_at, id);
+
+CREATE INDEX IF NOT EXISTS idx_tasks_tags ON tasks USING GIN (tags);
+
+-- Logs go away with their task, whichever way it is deleted
+CREATE TABLE IF NOT EXISTS task_logs (
+    seq BIGSERIAL PRIMARY KEY,
+    task_id TEXT NOT NULL REFERENCES tasks (id) ON DELETE CASCADE,
+    attempt INTEGER NOT NULL,
+    level TEXT NOT NULL,
+    message TEXT NOT NULL,
+    logged_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_task_logs_task_id_seq ON task_logs (task_id, seq);
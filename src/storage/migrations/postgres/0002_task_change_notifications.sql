@@ -0,0 +1,30 @@
+-- Tell listening queues about new tasks and state changes so they don't have
+-- to wait for their next poll. The origin is the application_name of the
+-- session making the change, which lets a queue skip its own changes.
+
+CREATE OR REPLACE FUNCTION notify_task_change() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify(
+        'task_changes',
+        json_build_object(
+            'id', NEW.id,
+            'state', NEW.state,
+            'scheduled_at', NEW.scheduled_at,
+            'origin', current_setting('application_name')
+        )::text
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS tasks_notify_insert ON tasks;
+CREATE TRIGGER tasks_notify_insert
+    AFTER INSERT ON tasks
+    FOR EACH ROW EXECUTE FUNCTION notify_task_change();
+
+DROP TRIGGER IF EXISTS tasks_notify_change ON tasks;
+CREATE TRIGGER tasks_notify_change
+    AFTER UPDATE OF state, scheduled_at ON tasks
+    FOR EACH ROW
+    WHEN (OLD.state IS DISTINCT FROM NEW.state OR OLD.scheduled_at IS DISTINCT FROM NEW.scheduled_at)
+    EXECUTE FUNCTION notify_task_change();
@@ -0,0 +1,6 @@
+-- Tenant each task was submitted for. Admission control counts a tenant's
+-- pending tasks on every submission, so the count has to come from an index.
+
+ALTER TABLE tasks ADD COLUMN tenant TEXT;
+
+CREATE INDEX idx_tasks_tenant_state ON tasks (tenant, state);
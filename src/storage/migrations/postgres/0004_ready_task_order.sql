@@ -0,0 +1,8 @@
+-- Lets the queue load the tasks ready to run in queue order a window at a time,
+-- however large the backlog. The expression must match PRIORITY_RANK.
+
+CREATE INDEX idx_tasks_ready_order ON tasks (
+    (CASE priority WHEN 'critical' THEN 0 WHEN 'high' THEN 1 WHEN 'medium' THEN 2 ELSE 3 END),
+    created_at,
+    id
+) WHERE state IN ('pending', 'scheduled');
@@ -0,0 +1,8 @@
+-- Time after which a task must not start. The expiry sweep looks for tasks
+-- still waiting to run whose time has passed, which the partial index keeps
+-- cheap while few tasks carry an expiry.
+
+ALTER TABLE tasks ADD COLUMN expires_at TIMESTAMPTZ;
+
+CREATE INDEX idx_tasks_expires_at ON tasks (expires_at)
+    WHERE expires_at IS NOT NULL AND state IN ('pending', 'scheduled', 'failed');
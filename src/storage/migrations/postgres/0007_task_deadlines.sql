@@ -0,0 +1,4 @@
+-- Time each task should have finished by, which deadline-aware orderings
+-- schedule by
+
+ALTER TABLE tasks ADD COLUMN deadline TIMESTAMPTZ;
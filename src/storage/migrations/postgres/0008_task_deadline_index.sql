@@ -0,0 +1,6 @@
+-- The deadline risk gauge counts the waiting tasks due soon on every metrics
+-- scrape. Only tasks with a deadline are indexed, which keeps the index small
+-- while few tasks carry one.
+
+CREATE INDEX idx_tasks_state_deadline ON tasks (state, deadline)
+    WHERE deadline IS NOT NULL;
@@ -0,0 +1,65 @@
+-- Baseline schema. Databases created before migrations existed already have
+-- some of these objects, so every statement tolerates them being present.
+
+CREATE TABLE IF NOT EXISTS tasks (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    state TEXT NOT NULL,
+    priority TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL,
+    scheduled_at INTEGER,
+    started_at INTEGER,
+    completed_at INTEGER,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    max_attempts INTEGER NOT NULL DEFAULT 3,
+    last_error TEXT,
+    worker_id TEXT,
+    result TEXT,
+    tags TEXT,
+    trace_id TEXT,
+    progress TEXT,
+    checkpoint TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_tasks_state ON tasks (state);
+CREATE INDEX IF NOT EXISTS idx_tasks_priority ON tasks (priority);
+CREATE INDEX IF NOT EXISTS idx_tasks_scheduled_at ON tasks (scheduled_at);
+CREATE INDEX IF NOT EXISTS idx_tasks_created_at ON tasks (created_at);
+
+-- Lets retention purges find old finished tasks without a full scan
+CREATE INDEX IF NOT EXISTS idx_tasks_state_updated_at ON tasks (state, updated_at);
+
+-- Support the time windows of the queue statistics
+CREATE INDEX IF NOT EXISTS idx_tasks_started_at ON tasks (started_at);
+CREATE INDEX IF NOT EXISTS idx_tasks_completed_at ON tasks (completed_at);
+
+-- Supports keyset pagination ordered by (created_at, id)
+CREATE INDEX IF NOT EXISTS idx_tasks_created_at_id ON tasks (created_at, id);
+
+-- Tags are kept normalized in their own table so they can be indexed
+CREATE TABLE IF NOT EXISTS task_tags (
+    task_id TEXT NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (task_id, tag)
+);
+
+CREATE INDEX IF NOT EXISTS idx_task_tags_tag ON task_tags (tag);
+
+-- Backfill from the JSON column for databases created before task_tags existed
+INSERT OR IGNORE INTO task_tags (task_id, tag)
+SELECT tasks.id, json_each.value
+FROM tasks, json_each(tasks.tags)
+WHERE json_valid(tasks.tags);
+
+CREATE TABLE IF NOT EXISTS task_logs (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    task_id TEXT NOT NULL,
+    attempt INTEGER NOT NULL,
+    level TEXT NOT NULL,
+    message TEXT NOT NULL,
+    logged_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_task_logs_task_id_seq ON task_logs (task_id, seq);
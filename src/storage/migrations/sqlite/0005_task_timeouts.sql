@@ -0,0 +1,4 @@
+-- Per-task execution timeout, overriding the configured timeout for the
+-- task's name
+
+ALTER TABLE tasks ADD COLUMN timeout_seconds INTEGER;
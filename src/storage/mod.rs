@@ -1,5 +1,16 @@
 pub mod database;
+pub mod instrumented;
+pub mod memory;
+pub mod migrate;
 pub mod postgres;
+pub mod redb;
 pub mod sqlite;
 
-pub use database::{create_database, Database};
\ No newline at end of file
+#[cfg(test)]
+mod conformance;
+
+pub use database::{create_database, ChangeEvent, Database, TaskChange};
+pub use instrumented::InstrumentedDatabase;
+pub use memory::MemoryDatabase;
+pub use migrate::{AppliedMigration, Migration, MigrationStatus};
+pub use self::redb::RedbDatabase;
@@ -1,30 +1,244 @@
 use crate::error::{AppError, AppResult};
-use crate::models::{Task, TaskPriority, TaskState};
-use crate::storage::database::Database;
+use crate::models::{
+    DurationPercentiles, LogLevel, QueuePosition, TagStateCount, Task, TaskCursor, TaskFilter,
+    TaskGroupCount, TaskLogEntry, TaskPriority, TaskProgress, TaskState, TimingStats,
+};
+use crate::storage::database::{ChangeEvent, Database, TaskChange};
+use crate::storage::migrate::{self, AppliedMigration, Migration, POSTGRES_MIGRATIONS};
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use log::{info, warn};
-use sqlx::{postgres::PgPoolOptions, PgPool, Row};
+use tracing::{info, warn};
+use serde::Deserialize;
+use sqlx::postgres::{PgConnectOptions, PgListener, PgPoolOptions, PgRow, Postgres};
+use sqlx::types::Json;
+use sqlx::{Executor, PgPool, QueryBuilder, Row};
 use std::time::Duration;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+// Rows per multi-row INSERT, kept well below Postgres' bind parameter limit
+const INSERT_CHUNK_SIZE: usize = 1000;
+
+// Advisory lock held while applying a migration
+const MIGRATION_LOCK_KEY: i64 = 0x7461_736b_7175_6575;
+
+// Channel the task change trigger notifies on
+const CHANGE_CHANNEL: &str = "task_changes";
+
+// Changes buffered for a queue that is busy handling earlier ones
+const CHANGE_BUFFER_SIZE: usize = 1024;
+
+// Place of a task's priority in queue order, as TaskPriority::rank gives it. The
+// ready task index is built on this exact expression.
+const PRIORITY_RANK: &str =
+    "CASE priority WHEN 'critical' THEN 0 WHEN 'high' THEN 1 WHEN 'medium' THEN 2 ELSE 3 END";
+
+const TASK_COLUMNS: &str = r#"
+    id, name, payload, state, priority,
+    created_at, updated_at, scheduled_at,
+    started_at, completed_at, attempts,
+    max_attempts, last_error, worker_id,
+    result, tags, trace_id,
+    progress, checkpoint, tenant,
+    expires_at, timeout_seconds, deadline
+"#;
 
 pub struct PostgresDatabase {
     pool: PgPool,
+    /// Application name of this instance's sessions, which the change trigger
+    /// reports as the origin of each change
+    instance_id: String,
+}
+
+/// Payload of a task change notification
+#[derive(Deserialize)]
+struct ChangeNotification {
+    id: String,
+    state: String,
+    scheduled_at: Option<DateTime<Utc>>,
+    origin: String,
 }
 
 impl PostgresDatabase {
     pub async fn new(database_url: &str) -> AppResult<Self> {
+        let instance_id = format!("taskqueue-{}", Uuid::new_v4());
+        let options: PgConnectOptions = database_url
+            .parse()
+            .map_err(AppError::DatabaseError)?;
+
         // Add connection timeout and retry logic
         let pool = PgPoolOptions::new()
             .max_connections(20)
             .acquire_timeout(Duration::from_secs(15))
-            .connect(database_url)
+            .connect_with(options.application_name(&instance_id))
             .await
             .map_err(|e| {
                 warn!("PostgreSQL connection error: {}", e);
                 AppError::DatabaseError(e)
             })?;
 
-        Ok(Self { pool })
+        Ok(Self { pool, instance_id })
+    }
+
+    // Nearest-rank percentiles of the durations `v` selected by `samples`, which
+    // takes the window start as its only parameter
+    async fn duration_percentiles(
+        &self,
+        samples: &str,
+        since: DateTime<Utc>,
+    ) -> AppResult<DurationPercentiles> {
+        let row = sqlx::query(&format!(
+            r#"
+            SELECT
+                COUNT(*) AS count,
+                percentile_disc(0.50) WITHIN GROUP (ORDER BY v) AS p50,
+                percentile_disc(0.95) WITHIN GROUP (ORDER BY v) AS p95,
+                percentile_disc(0.99) WITHIN GROUP (ORDER BY v) AS p99
+            FROM ({}) AS samples
+            "#,
+            samples
+        ))
+        .bind(since)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(AppError::DatabaseError)?;
+
+        Ok(DurationPercentiles {
+            count: row.try_get("count")?,
+            p50: row.try_get("p50")?,
+            p95: row.try_get("p95")?,
+            p99: row.try_get("p99")?,
+        })
+    }
+}
+
+// Convert a row selected with TASK_COLUMNS into a Task
+fn row_to_task(row: &PgRow) -> AppResult<Task> {
+    let state_str: String = row.try_get("state")?;
+    let priority_str: String = row.try_get("priority")?;
+    let attempts: i32 = row.try_get("attempts")?;
+    let max_attempts: i32 = row.try_get("max_attempts")?;
+    let tags: Option<Vec<String>> = row.try_get("tags")?;
+    let progress: Option<Json<TaskProgress>> = row.try_get("progress")?;
+    let timeout_seconds: Option<i64> = row.try_get("timeout_seconds")?;
+
+    Ok(Task {
+        id: row.try_get("id")?,
+        name: row.try_get("name")?,
+        payload: row.try_get("payload")?,
+        state: state_str.parse().unwrap_or_default(),
+        priority: priority_str.parse().unwrap_or_default(),
+        created_at: row.try_get("created_at")?,
+        updated_at: row.try_get("updated_at")?,
+        scheduled_at: row.try_get("scheduled_at")?,
+        started_at: row.try_get("started_at")?,
+        completed_at: row.try_get("completed_at")?,
+        attempts: attempts as u32,
+        max_attempts: max_attempts as u32,
+        last_error: row.try_get("last_error")?,
+        worker_id: row.try_get("worker_id")?,
+        result: row.try_get("result")?,
+        tags: tags.unwrap_or_default(),
+        trace_id: row.try_get("trace_id")?,
+        progress: progress.map(|p| p.0),
+        checkpoint: row.try_get("checkpoint")?,
+        tenant: row.try_get("tenant")?,
+        expires_at: row.try_get("expires_at")?,
+        timeout_seconds: timeout_seconds.map(|t| t as u64),
+        deadline: row.try_get("deadline")?,
+    })
+}
+
+// Append a WHERE clause selecting finished tasks that match the filter but none of the exclusions
+fn push_purge_conditions(
+    builder: &mut QueryBuilder<'_, Postgres>,
+    filter: &TaskFilter,
+    exclude: &[TaskFilter],
+) {
+    builder.push(" WHERE state IN ('completed', 'failed', 'cancelled', 'expired')");
+    push_filter(builder, filter);
+    for excluded in exclude {
+        builder.push(" AND NOT (1 = 1");
+        push_filter(builder, excluded);
+        builder.push(")");
+    }
+}
+
+// Connect a listener for the changes other instances announce
+async fn listen_for_changes(pool: &PgPool) -> Result<PgListener, sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(CHANGE_CHANNEL).await?;
+    Ok(listener)
+}
+
+// Convert a task_logs row into a TaskLogEntry
+fn row_to_log_entry(row: &PgRow) -> AppResult<TaskLogEntry> {
+    let attempt: i32 = row.try_get("attempt")?;
+    let level_str: String = row.try_get("level")?;
+
+    Ok(TaskLogEntry {
+        seq: row.try_get("seq")?,
+        task_id: row.try_get("task_id")?,
+        attempt: attempt as u32,
+        level: level_str.parse().unwrap_or_default(),
+        message: row.try_get("message")?,
+        logged_at: row.try_get("logged_at")?,
+    })
+}
+
+// Append the filter's conditions to a query that already has a WHERE clause
+fn push_filter(builder: &mut QueryBuilder<'_, Postgres>, filter: &TaskFilter) {
+    if !filter.ids.is_empty() {
+        builder.push(" AND id IN (");
+        let mut separated = builder.separated(", ");
+        for id in &filter.ids {
+            separated.push_bind(id.clone());
+        }
+        builder.push(")");
+    }
+
+    if let Some(state) = &filter.state {
+        builder.push(" AND state = ").push_bind(state.clone());
+    }
+
+    if let Some(priority) = &filter.priority {
+        builder.push(" AND priority = ").push_bind(priority.clone());
+    }
+
+    if let Some(name) = &filter.name {
+        builder.push(" AND name = ").push_bind(name.clone());
+    }
+
+    if let Some(created_after) = filter.created_after {
+        builder.push(" AND created_at >= ").push_bind(created_after);
+    }
+
+    if let Some(created_before) = filter.created_before {
+        builder.push(" AND created_at < ").push_bind(created_before);
+    }
+
+    if let Some(updated_before) = filter.updated_before {
+        builder.push(" AND updated_at < ").push_bind(updated_before);
+    }
+
+    if let Some(deadline_before) = filter.deadline_before {
+        builder.push(" AND deadline <= ").push_bind(deadline_before);
+    }
+
+    if let Some(error) = &filter.error_contains {
+        builder
+            .push(" AND strpos(last_error, ")
+            .push_bind(error.clone())
+            .push(") > 0");
+    }
+
+    if let Some(tenant) = &filter.tenant {
+        builder.push(" AND tenant = ").push_bind(tenant.clone());
+    }
+
+    // Containment can use the GIN index on tags
+    if !filter.tags.is_empty() {
+        builder.push(" AND tags @> ").push_bind(filter.tags.clone());
     }
 }
 
@@ -39,117 +253,164 @@ impl Database for PostgresDatabase {
                 created_at, updated_at, scheduled_at,
                 started_at, completed_at, attempts,
                 max_attempts, last_error, worker_id,
-                result, tags
+                result, tags, trace_id,
+                progress, checkpoint, tenant,
+                expires_at, timeout_seconds, deadline
             ) VALUES (
                 $1, $2, $3, $4, $5,
                 $6, $7, $8, $9, $10,
                 $11, $12, $13, $14, $15,
-                $16
+                $16, $17, $18, $19, $20,
+                $21, $22, $23
             )
             "#
         )
         .bind(&task.id)
         .bind(&task.name)
         .bind(&task.payload)
-        .bind(&task.state.to_string())
-        .bind(&task.priority.to_string())
-        .bind(&task.created_at)
-        .bind(&task.updated_at)
-        .bind(&task.scheduled_at)
-        .bind(&task.started_at)
-        .bind(&task.completed_at)
+        .bind(task.state.to_string())
+        .bind(task.priority.to_string())
+        .bind(task.created_at)
+        .bind(task.updated_at)
+        .bind(task.scheduled_at)
+        .bind(task.started_at)
+        .bind(task.completed_at)
         .bind(task.attempts as i32)
         .bind(task.max_attempts as i32)
         .bind(&task.last_error)
         .bind(&task.worker_id)
         .bind(&task.result)
         .bind(&task.tags)
+        .bind(&task.trace_id)
+        .bind(task.progress.as_ref().map(Json))
+        .bind(&task.checkpoint)
+        .bind(&task.tenant)
+        .bind(task.expires_at)
+        .bind(task.timeout_seconds.map(|t| t as i64))
+        .bind(task.deadline)
         .execute(&self.pool)
         .await
-        .map_err(|e| AppError::DatabaseError(e))?;
+        .map_err(AppError::DatabaseError)?;
+
+        Ok(())
+    }
+
+    async fn create_tasks(&self, tasks: &[Task]) -> AppResult<()> {
+        let mut tx = self.pool.begin().await.map_err(AppError::DatabaseError)?;
+
+        for chunk in tasks.chunks(INSERT_CHUNK_SIZE) {
+            let mut builder = QueryBuilder::<Postgres>::new(
+                r#"
+                INSERT INTO tasks (
+                    id, name, payload, state, priority,
+                    created_at, updated_at, scheduled_at,
+                    started_at, completed_at, attempts,
+                    max_attempts, last_error, worker_id,
+                    result, tags, trace_id,
+                    progress, checkpoint, tenant,
+                    expires_at, timeout_seconds, deadline
+                )
+                "#
+            );
+            builder.push_values(chunk, |mut row, task| {
+                row.push_bind(task.id.clone())
+                    .push_bind(task.name.clone())
+                    .push_bind(task.payload.clone())
+                    .push_bind(task.state.to_string())
+                    .push_bind(task.priority.to_string())
+                    .push_bind(task.created_at)
+                    .push_bind(task.updated_at)
+                    .push_bind(task.scheduled_at)
+                    .push_bind(task.started_at)
+                    .push_bind(task.completed_at)
+                    .push_bind(task.attempts as i32)
+                    .push_bind(task.max_attempts as i32)
+                    .push_bind(task.last_error.clone())
+                    .push_bind(task.worker_id.clone())
+                    .push_bind(task.result.clone())
+                    .push_bind(task.tags.clone())
+                    .push_bind(task.trace_id.clone())
+                    .push_bind(task.progress.clone().map(Json))
+                    .push_bind(task.checkpoint.clone())
+                    .push_bind(task.tenant.clone())
+                    .push_bind(task.expires_at)
+                    .push_bind(task.timeout_seconds.map(|t| t as i64))
+                    .push_bind(task.deadline);
+            });
+            builder
+                .build()
+                .execute(&mut *tx)
+                .await
+                .map_err(AppError::DatabaseError)?;
+        }
+
+        tx.commit().await.map_err(AppError::DatabaseError)?;
 
         Ok(())
     }
 
     async fn get_task(&self, id: &str) -> AppResult<Task> {
-        let row = sqlx::query(
+        let row = sqlx::query(&format!("SELECT {} FROM tasks WHERE id = $1", TASK_COLUMNS))
+            .bind(id)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => AppError::TaskNotFound(id.to_string()),
+                e => AppError::DatabaseError(e),
+            })?;
+
+        row_to_task(&row)
+    }
+
+    async fn update_task(&self, task: &Task) -> AppResult<()> {
+        sqlx::query(
             r#"
-            SELECT
-                id, name, payload, state, priority,
-                created_at, updated_at, scheduled_at,
-                started_at, completed_at, attempts,
-                max_attempts, last_error, worker_id,
-                result, tags
-            FROM tasks
-            WHERE id = $1
+            UPDATE tasks SET
+                name = $1,
+                payload = $2,
+                state = $3,
+                priority = $4,
+                updated_at = $5,
+                scheduled_at = $6,
+                started_at = $7,
+                completed_at = $8,
+                attempts = $9,
+                max_attempts = $10,
+                last_error = $11,
+                worker_id = $12,
+                result = $13,
+                tags = $14,
+                progress = $15,
+                checkpoint = $16
+            WHERE id = $17
             "#
         )
-        .bind(id)
-        .fetch_one(&self.pool)
+        .bind(&task.name)
+        .bind(&task.payload)
+        .bind(task.state.to_string())
+        .bind(task.priority.to_string())
+        .bind(task.updated_at)
+        .bind(task.scheduled_at)
+        .bind(task.started_at)
+        .bind(task.completed_at)
+        .bind(task.attempts as i32)
+        .bind(task.max_attempts as i32)
+        .bind(&task.last_error)
+        .bind(&task.worker_id)
+        .bind(&task.result)
+        .bind(&task.tags)
+        .bind(task.progress.as_ref().map(Json))
+        .bind(&task.checkpoint)
+        .bind(&task.id)
+        .execute(&self.pool)
         .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => AppError::TaskNotFound(id.to_string()),
-            e => AppError::DatabaseError(e),
-        })?;
-
-        // Extract values from row
-        let id: String = row.try_get("id")?;
-        let name: String = row.try_get("name")?;
-        let payload: serde_json::Value = row.try_get("payload")?;
-        let state_str: String = row.try_get("state")?;
-        let priority_str: String = row.try_get("priority")?;
-        let created_at: DateTime<Utc> = row.try_get("created_at")?;
-        let updated_at: DateTime<Utc> = row.try_get("updated_at")?;
-        let scheduled_at: Option<DateTime<Utc>> = row.try_get("scheduled_at")?;
-        let started_at: Option<DateTime<Utc>> = row.try_get("started_at")?;
-        let completed_at: Option<DateTime<Utc>> = row.try_get("completed_at")?;
-        let attempts: i32 = row.try_get("attempts")?;
-        let max_attempts: i32 = row.try_get("max_attempts")?;
-        let last_error: Option<String> = row.try_get("last_error")?;
-        let worker_id: Option<String> = row.try_get("worker_id")?;
-        let result: Option<serde_json::Value> = row.try_get("result")?;
-        let tags: Vec<String> = row.try_get("tags").unwrap_or_default();
-
-        let state = match state_str.as_str() {
-            "pending" => TaskState::Pending,
-            "scheduled" => TaskState::Scheduled,
-            "running" => TaskState::Running,
-            "completed" => TaskState::Completed,
-            "failed" => TaskState::Failed,
-            "cancelled" => TaskState::Cancelled,
-            _ => TaskState::Pending,
-        };
-
-        let priority = match priority_str.as_str() {
-            "low" => TaskPriority::Low,
-            "medium" => TaskPriority::Medium,
-            "high" => TaskPriority::High,
-            "critical" => TaskPriority::Critical,
-            _ => TaskPriority::Medium,
-        };
+        .map_err(AppError::DatabaseError)?;
 
-        Ok(Task {
-            id,
-            name,
-            payload,
-            state,
-            priority,
-            created_at,
-            updated_at,
-            scheduled_at,
-            started_at,
-            completed_at,
-            attempts: attempts as u32,
-            max_attempts: max_attempts as u32,
-            last_error,
-            worker_id,
-            result,
-            tags,
-        })
+        Ok(())
     }
 
-    async fn update_task(&self, task: &Task) -> AppResult<()> {
-        sqlx::query(
+    async fn update_task_if_state(&self, task: &Task, expected: &TaskState) -> AppResult<bool> {
+        let result = sqlx::query(
             r#"
             UPDATE tasks SET
                 name = $1,
@@ -165,30 +426,35 @@ impl Database for PostgresDatabase {
                 last_error = $11,
                 worker_id = $12,
                 result = $13,
-                tags = $14
-            WHERE id = $15
+                tags = $14,
+                progress = $15,
+                checkpoint = $16
+            WHERE id = $17 AND state = $18
             "#
         )
         .bind(&task.name)
         .bind(&task.payload)
-        .bind(&task.state.to_string())
-        .bind(&task.priority.to_string())
-        .bind(&task.updated_at)
-        .bind(&task.scheduled_at)
-        .bind(&task.started_at)
-        .bind(&task.completed_at)
+        .bind(task.state.to_string())
+        .bind(task.priority.to_string())
+        .bind(task.updated_at)
+        .bind(task.scheduled_at)
+        .bind(task.started_at)
+        .bind(task.completed_at)
         .bind(task.attempts as i32)
         .bind(task.max_attempts as i32)
         .bind(&task.last_error)
         .bind(&task.worker_id)
         .bind(&task.result)
         .bind(&task.tags)
+        .bind(task.progress.as_ref().map(Json))
+        .bind(&task.checkpoint)
         .bind(&task.id)
+        .bind(expected.to_string())
         .execute(&self.pool)
         .await
-        .map_err(|e| AppError::DatabaseError(e))?;
+        .map_err(AppError::DatabaseError)?;
 
-        Ok(())
+        Ok(result.rows_affected() > 0)
     }
 
     async fn delete_task(&self, id: &str) -> AppResult<()> {
@@ -196,107 +462,334 @@ impl Database for PostgresDatabase {
             .bind(id)
             .execute(&self.pool)
             .await
-            .map_err(|e| AppError::DatabaseError(e))?;
+            .map_err(AppError::DatabaseError)?;
 
         Ok(())
     }
 
     async fn get_tasks(
         &self,
-        state: Option<&str>,
-        priority: Option<&str>,
+        filter: &TaskFilter,
+        after: Option<&TaskCursor>,
         limit: Option<u32>,
-        offset: Option<u32>,
     ) -> AppResult<Vec<Task>> {
-        let mut query = "SELECT * FROM tasks".to_string();
-        let mut conditions = Vec::new();
+        let mut builder = QueryBuilder::<Postgres>::new(
+            format!("SELECT {} FROM tasks WHERE 1 = 1", TASK_COLUMNS)
+        );
+        push_filter(&mut builder, filter);
 
-        if let Some(state) = state {
-            conditions.push(format!("state = '{}'", state));
+        if let Some(cursor) = after {
+            builder
+                .push(" AND (created_at, id) < (")
+                .push_bind(cursor.created_at)
+                .push(", ")
+                .push_bind(cursor.id.clone())
+                .push(")");
         }
 
-        if let Some(priority) = priority {
-            conditions.push(format!("priority = '{}'", priority));
-        }
+        builder.push(" ORDER BY created_at DESC, id DESC");
 
-        if !conditions.is_empty() {
-            query.push_str(" WHERE ");
-            query.push_str(&conditions.join(" AND "));
+        if let Some(limit) = limit {
+            builder.push(" LIMIT ").push_bind(limit as i64);
         }
 
-        query.push_str(" ORDER BY created_at DESC");
+        let rows = builder
+            .build()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(AppError::DatabaseError)?;
 
-        if let Some(limit) = limit {
-            query.push_str(&format!(" LIMIT {}", limit));
+        rows.iter().map(row_to_task).collect()
+    }
+
+    async fn count_tasks(&self, filter: &TaskFilter) -> AppResult<i64> {
+        let mut builder = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM tasks WHERE 1 = 1");
+        push_filter(&mut builder, filter);
+
+        let count: i64 = builder
+            .build_query_scalar()
+            .fetch_one(&self.pool)
+            .await
+            .map_err(AppError::DatabaseError)?;
+
+        Ok(count)
+    }
+
+    async fn estimate_task_count(&self, filter: &TaskFilter) -> AppResult<i64> {
+        // Without conditions the table statistics are the cheapest source
+        if filter.is_empty() {
+            let estimate: f32 = sqlx::query_scalar(
+                "SELECT reltuples FROM pg_class WHERE relname = 'tasks'"
+            )
+            .fetch_one(&self.pool)
+            .await
+            .map_err(AppError::DatabaseError)?;
+
+            // reltuples is -1 until the table has been vacuumed or analyzed
+            if estimate >= 0.0 {
+                return Ok(estimate as i64);
+            }
+            return self.count_tasks(filter).await;
         }
 
-        if let Some(offset) = offset {
-            query.push_str(&format!(" OFFSET {}", offset));
+        // Otherwise ask the planner how many rows it expects the filter to match
+        let mut builder = QueryBuilder::<Postgres>::new(
+            "EXPLAIN (FORMAT JSON) SELECT 1 FROM tasks WHERE 1 = 1"
+        );
+        push_filter(&mut builder, filter);
+
+        let plan: serde_json::Value = builder
+            .build_query_scalar()
+            .fetch_one(&self.pool)
+            .await
+            .map_err(AppError::DatabaseError)?;
+
+        match plan[0]["Plan"]["Plan Rows"].as_f64() {
+            Some(rows) => Ok(rows as i64),
+            None => self.count_tasks(filter).await,
         }
+    }
 
-        let rows = sqlx::query(&query)
+    async fn claim_task(&self, id: &str, worker_id: &str) -> AppResult<Option<Task>> {
+        // Due by the clock the scheduler's timers run on, which may be slightly
+        // ahead of the database server's
+        // Read every row, so the update runs to completion and commits before
+        // another connection looks
+        let rows = sqlx::query(&format!(
+            r#"
+            UPDATE tasks SET
+                state = 'running',
+                worker_id = $1,
+                started_at = NOW(),
+                updated_at = NOW(),
+                progress = NULL
+            WHERE id = $2
+                AND (state = 'pending'
+                    OR (state = 'scheduled' AND scheduled_at <= $3))
+            RETURNING {}
+            "#,
+            TASK_COLUMNS
+        ))
+        .bind(worker_id)
+        .bind(id)
+        .bind(Utc::now())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(AppError::DatabaseError)?;
+
+        rows.first().map(row_to_task).transpose()
+    }
+
+    async fn cancel_tasks(&self, filter: &TaskFilter) -> AppResult<Vec<Task>> {
+        let mut builder = QueryBuilder::<Postgres>::new(
+            "UPDATE tasks SET state = 'cancelled', updated_at = NOW() WHERE state IN ('pending', 'scheduled')"
+        );
+        push_filter(&mut builder, filter);
+        builder.push(format!(" RETURNING {}", TASK_COLUMNS));
+
+        let rows = builder
+            .build()
             .fetch_all(&self.pool)
             .await
-            .map_err(|e| AppError::DatabaseError(e))?;
+            .map_err(AppError::DatabaseError)?;
 
-        let mut tasks = Vec::new();
-        for row in rows {
-            let id: String = row.try_get("id")?;
-            let name: String = row.try_get("name")?;
-            let payload: serde_json::Value = row.try_get("payload")?;
-            let state_str: String = row.try_get("state")?;
-            let priority_str: String = row.try_get("priority")?;
-            let created_at: DateTime<Utc> = row.try_get("created_at")?;
-            let updated_at: DateTime<Utc> = row.try_get("updated_at")?;
-            let scheduled_at: Option<DateTime<Utc>> = row.try_get("scheduled_at")?;
-            let started_at: Option<DateTime<Utc>> = row.try_get("started_at")?;
-            let completed_at: Option<DateTime<Utc>> = row.try_get("completed_at")?;
-            let attempts: i32 = row.try_get("attempts")?;
-            let max_attempts: i32 = row.try_get("max_attempts")?;
-            let last_error: Option<String> = row.try_get("last_error")?;
-            let worker_id: Option<String> = row.try_get("worker_id")?;
-            let result: Option<serde_json::Value> = row.try_get("result")?;
-            let tags: Option<Vec<String>> = row.try_get("tags")?;
-
-            let state = match state_str.as_str() {
-                "pending" => TaskState::Pending,
-                "scheduled" => TaskState::Scheduled,
-                "running" => TaskState::Running,
-                "completed" => TaskState::Completed,
-                "failed" => TaskState::Failed,
-                "cancelled" => TaskState::Cancelled,
-                _ => TaskState::Pending,
-            };
-
-            let priority = match priority_str.as_str() {
-                "low" => TaskPriority::Low,
-                "medium" => TaskPriority::Medium,
-                "high" => TaskPriority::High,
-                "critical" => TaskPriority::Critical,
-                _ => TaskPriority::Medium,
-            };
-
-            tasks.push(Task {
-                id,
-                name,
-                payload,
-                state,
-                priority,
-                created_at,
-                updated_at,
-                scheduled_at,
-                started_at,
-                completed_at,
-                attempts: attempts as u32,
-                max_attempts: max_attempts as u32,
-                last_error,
-                worker_id,
-                result,
-                tags: tags.unwrap_or_default(),
-            });
+        rows.iter().map(row_to_task).collect()
+    }
+
+    async fn expire_tasks(&self, filter: &TaskFilter, now: DateTime<Utc>) -> AppResult<Vec<Task>> {
+        let mut builder = QueryBuilder::<Postgres>::new("UPDATE tasks SET state = 'expired', updated_at = ");
+        builder
+            .push_bind(now)
+            .push(
+                " WHERE (state IN ('pending', 'scheduled') \
+                 OR (state = 'failed' AND attempts < max_attempts)) AND expires_at <= ",
+            )
+            .push_bind(now);
+        push_filter(&mut builder, filter);
+        builder.push(format!(" RETURNING {}", TASK_COLUMNS));
+
+        let rows = builder
+            .build()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(AppError::DatabaseError)?;
+
+        rows.iter().map(row_to_task).collect()
+    }
+
+    async fn reprioritize_tasks(
+        &self,
+        filter: &TaskFilter,
+        priority: &TaskPriority,
+    ) -> AppResult<Vec<String>> {
+        let mut builder = QueryBuilder::<Postgres>::new("UPDATE tasks SET priority = ");
+        builder
+            .push_bind(priority.to_string())
+            .push(", updated_at = NOW() WHERE state IN ('pending', 'scheduled', 'failed')");
+        push_filter(&mut builder, filter);
+        builder.push(" RETURNING id");
+
+        let ids: Vec<String> = builder
+            .build_query_scalar()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(AppError::DatabaseError)?;
+
+        Ok(ids)
+    }
+
+    async fn retry_tasks(&self, filter: &TaskFilter, reset_attempts: bool) -> AppResult<Vec<Task>> {
+        let mut builder = QueryBuilder::<Postgres>::new(
+            "UPDATE tasks SET state = 'pending', worker_id = NULL, started_at = NULL, completed_at = NULL, updated_at = NOW()"
+        );
+        if reset_attempts {
+            builder.push(", attempts = 0");
         }
+        builder.push(" WHERE state IN ('failed', 'cancelled')");
+        push_filter(&mut builder, filter);
+        builder.push(format!(" RETURNING {}", TASK_COLUMNS));
+
+        let rows = builder
+            .build()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(AppError::DatabaseError)?;
+
+        rows.iter().map(row_to_task).collect()
+    }
+
+    async fn delete_tasks(&self, filter: &TaskFilter) -> AppResult<Vec<String>> {
+        let mut builder = QueryBuilder::<Postgres>::new("DELETE FROM tasks WHERE state != 'running'");
+        push_filter(&mut builder, filter);
+        builder.push(" RETURNING id");
 
-        Ok(tasks)
+        let ids: Vec<String> = builder
+            .build_query_scalar()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(AppError::DatabaseError)?;
+
+        Ok(ids)
+    }
+
+    async fn update_task_progress(
+        &self,
+        id: &str,
+        progress: &TaskProgress,
+        checkpoint: Option<&serde_json::Value>,
+    ) -> AppResult<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE tasks SET
+                progress = $1,
+                checkpoint = COALESCE($2, checkpoint),
+                updated_at = $3
+            WHERE id = $4 AND state = 'running'
+            "#
+        )
+        .bind(Json(progress))
+        .bind(checkpoint)
+        .bind(progress.updated_at)
+        .bind(id)
+        .execute(&self.pool)
+        .await
+        .map_err(AppError::DatabaseError)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn append_task_log(
+        &self,
+        task_id: &str,
+        attempt: u32,
+        level: LogLevel,
+        message: &str,
+        logged_at: DateTime<Utc>,
+    ) -> AppResult<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO task_logs (task_id, attempt, level, message, logged_at)
+            VALUES ($1, $2, $3, $4, $5)
+            "#
+        )
+        .bind(task_id)
+        .bind(attempt as i32)
+        .bind(level.to_string())
+        .bind(message)
+        .bind(logged_at)
+        .execute(&self.pool)
+        .await
+        .map_err(AppError::DatabaseError)?;
+
+        Ok(())
+    }
+
+    async fn get_task_logs(
+        &self,
+        task_id: &str,
+        after_seq: Option<i64>,
+        limit: u32,
+    ) -> AppResult<Vec<TaskLogEntry>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT seq, task_id, attempt, level, message, logged_at
+            FROM task_logs
+            WHERE task_id = $1 AND seq > $2
+            ORDER BY seq
+            LIMIT $3
+            "#
+        )
+        .bind(task_id)
+        .bind(after_seq.unwrap_or(0))
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(AppError::DatabaseError)?;
+
+        rows.iter().map(row_to_log_entry).collect()
+    }
+
+    async fn find_purgeable_tasks(
+        &self,
+        filter: &TaskFilter,
+        exclude: &[TaskFilter],
+        limit: u32,
+    ) -> AppResult<Vec<Task>> {
+        let mut builder = QueryBuilder::<Postgres>::new(format!("SELECT {} FROM tasks", TASK_COLUMNS));
+        push_purge_conditions(&mut builder, filter, exclude);
+        builder.push(" ORDER BY updated_at, id LIMIT ").push_bind(limit as i64);
+
+        let rows = builder
+            .build()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(AppError::DatabaseError)?;
+
+        rows.iter().map(row_to_task).collect()
+    }
+
+    async fn purge_tasks(
+        &self,
+        filter: &TaskFilter,
+        exclude: &[TaskFilter],
+        limit: u32,
+    ) -> AppResult<Vec<String>> {
+        // Logs are removed by the foreign key cascade. SKIP LOCKED keeps concurrent
+        // purges from waiting on each other's batches.
+        let mut builder = QueryBuilder::<Postgres>::new("DELETE FROM tasks WHERE id IN (SELECT id FROM tasks");
+        push_purge_conditions(&mut builder, filter, exclude);
+        builder
+            .push(" ORDER BY updated_at, id LIMIT ")
+            .push_bind(limit as i64)
+            .push(" FOR UPDATE SKIP LOCKED) RETURNING id");
+
+        let ids: Vec<String> = builder
+            .build_query_scalar()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(AppError::DatabaseError)?;
+
+        Ok(ids)
     }
 
     async fn get_scheduled_tasks(&self, before: DateTime<Utc>) -> AppResult<Vec<Task>> {
@@ -307,78 +800,21 @@ impl Database for PostgresDatabase {
         match tokio::time::timeout(
             Duration::from_secs(TIMEOUT_SECONDS),
             async {
-                let rows = sqlx::query(
+                let rows = sqlx::query(&format!(
                     r#"
-                    SELECT
-                        id, name, payload, state, priority,
-                        created_at, updated_at, scheduled_at,
-                        started_at, completed_at, attempts,
-                        max_attempts, last_error, worker_id,
-                        result, tags
+                    SELECT {}
                     FROM tasks
                     WHERE state = 'scheduled' AND scheduled_at <= $1
                     ORDER BY priority DESC, scheduled_at ASC
-                    "#
-                )
-                .bind(&before)
+                    "#,
+                    TASK_COLUMNS
+                ))
+                .bind(before)
                 .fetch_all(&self.pool)
                 .await
-                .map_err(|e| AppError::DatabaseError(e))?;
-    
-                // Process the results and convert to Task objects
-                let mut tasks = Vec::new();
-                for row in rows {
-                    // [Extract task data from row - existing code]
-                    let id: String = row.try_get("id")?;
-                    let name: String = row.try_get("name")?;
-                    let payload: serde_json::Value = row.try_get("payload")?;
-                    let state_str: String = row.try_get("state")?;
-                    let priority_str: String = row.try_get("priority")?;
-                    // [Rest of your existing field extraction]
-                    
-                    // [Create Task object - existing code]
-                    let state = match state_str.as_str() {
-                        // [Your existing state mapping code]
-                        "pending" => TaskState::Pending,
-                        "scheduled" => TaskState::Scheduled,
-                        "running" => TaskState::Running,
-                        "completed" => TaskState::Completed,
-                        "failed" => TaskState::Failed,
-                        "cancelled" => TaskState::Cancelled,
-                        _ => TaskState::Pending,
-                    };
+                .map_err(AppError::DatabaseError)?;
     
-                    let priority = match priority_str.as_str() {
-                        // [Your existing priority mapping code]
-                        "low" => TaskPriority::Low,
-                        "medium" => TaskPriority::Medium,
-                        "high" => TaskPriority::High,
-                        "critical" => TaskPriority::Critical,
-                        _ => TaskPriority::Medium,
-                    };
-    
-                    tasks.push(Task {
-                        id,
-                        name,
-                        payload,
-                        state,
-                        priority,
-                        // [Rest of your Task construction]
-                        created_at: row.try_get("created_at")?,
-                        updated_at: row.try_get("updated_at")?,
-                        scheduled_at: row.try_get("scheduled_at")?,
-                        started_at: row.try_get("started_at")?,
-                        completed_at: row.try_get("completed_at")?,
-                        attempts: row.try_get::<i32, _>("attempts")? as u32,
-                        max_attempts: row.try_get::<i32, _>("max_attempts")? as u32,
-                        last_error: row.try_get("last_error")?,
-                        worker_id: row.try_get("worker_id")?,
-                        result: row.try_get("result")?,
-                        tags: row.try_get("tags").unwrap_or_default(),
-                    });
-                }
-    
-                Ok(tasks)
+                rows.iter().map(row_to_task).collect()
             }
         ).await {
             // Timeout wrapper result handling
@@ -391,72 +827,56 @@ impl Database for PostgresDatabase {
         }
     }
     
+    async fn get_ready_tasks(
+        &self,
+        from: Option<QueuePosition>,
+        now: DateTime<Utc>,
+        limit: u32,
+    ) -> AppResult<Vec<Task>> {
+        let mut builder = QueryBuilder::<Postgres>::new(format!(
+            "SELECT {} FROM tasks WHERE state IN ('pending', 'scheduled') \
+             AND (state = 'pending' OR scheduled_at <= ",
+            TASK_COLUMNS
+        ));
+        builder.push_bind(now).push(")");
+
+        if let Some(from) = from {
+            builder
+                .push(format!(" AND ({}, created_at) >= (", PRIORITY_RANK))
+                .push_bind(from.priority_rank as i32)
+                .push(", ")
+                .push_bind(from.created_from())
+                .push(")");
+        }
+
+        builder
+            .push(format!(" ORDER BY {}, created_at, id LIMIT ", PRIORITY_RANK))
+            .push_bind(limit as i64);
+
+        let rows = builder
+            .build()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(AppError::DatabaseError)?;
+
+        rows.iter().map(row_to_task).collect()
+    }
+    
     async fn get_failed_tasks_for_retry(&self) -> AppResult<Vec<Task>> {
-        let rows = sqlx::query(
+        let rows = sqlx::query(&format!(
             r#"
-            SELECT
-                id, name, payload, state, priority,
-                created_at, updated_at, scheduled_at,
-                started_at, completed_at, attempts,
-                max_attempts, last_error, worker_id,
-                result, tags
+            SELECT {}
             FROM tasks
             WHERE state = 'failed' AND attempts < max_attempts
             ORDER BY priority DESC, updated_at ASC
-            "#
-        )
+            "#,
+            TASK_COLUMNS
+        ))
         .fetch_all(&self.pool)
         .await
-        .map_err(|e| AppError::DatabaseError(e))?;
-
-        let mut tasks = Vec::new();
-        for row in rows {
-            let id: String = row.try_get("id")?;
-            let name: String = row.try_get("name")?;
-            let payload: serde_json::Value = row.try_get("payload")?;
-            let priority_str: String = row.try_get("priority")?;
-            let created_at: DateTime<Utc> = row.try_get("created_at")?;
-            let updated_at: DateTime<Utc> = row.try_get("updated_at")?;
-            let scheduled_at: Option<DateTime<Utc>> = row.try_get("scheduled_at")?;
-            let started_at: Option<DateTime<Utc>> = row.try_get("started_at")?;
-            let completed_at: Option<DateTime<Utc>> = row.try_get("completed_at")?;
-            let attempts: i32 = row.try_get("attempts")?;
-            let max_attempts: i32 = row.try_get("max_attempts")?;
-            let last_error: Option<String> = row.try_get("last_error")?;
-            let worker_id: Option<String> = row.try_get("worker_id")?;
-            let result: Option<serde_json::Value> = row.try_get("result")?;
-            let tags: Vec<String> = row.try_get("tags").unwrap_or_default();
-
-            let state = TaskState::Failed;
-            let priority = match priority_str.as_str() {
-                "low" => TaskPriority::Low,
-                "medium" => TaskPriority::Medium,
-                "high" => TaskPriority::High,
-                "critical" => TaskPriority::Critical,
-                _ => TaskPriority::Medium,
-            };
-
-            tasks.push(Task {
-                id,
-                name,
-                payload,
-                state,
-                priority,
-                created_at,
-                updated_at,
-                scheduled_at,
-                started_at,
-                completed_at,
-                attempts: attempts as u32,
-                max_attempts: max_attempts as u32,
-                last_error,
-                worker_id,
-                result,
-                tags,
-            });
-        }
+        .map_err(AppError::DatabaseError)?;
 
-        Ok(tasks)
+        rows.iter().map(row_to_task).collect()
     }
 
     async fn count_tasks_by_state(&self) -> AppResult<Vec<(String, i64)>> {
@@ -469,7 +889,7 @@ impl Database for PostgresDatabase {
         )
         .fetch_all(&self.pool)
         .await
-        .map_err(|e| AppError::DatabaseError(e))?;
+        .map_err(AppError::DatabaseError)?;
 
         let mut counts = Vec::new();
         for row in rows {
@@ -491,7 +911,7 @@ impl Database for PostgresDatabase {
         )
         .fetch_all(&self.pool)
         .await
-        .map_err(|e| AppError::DatabaseError(e))?;
+        .map_err(AppError::DatabaseError)?;
 
         let mut counts = Vec::new();
         for row in rows {
@@ -503,66 +923,290 @@ impl Database for PostgresDatabase {
         Ok(counts)
     }
 
-    async fn setup(&self) -> AppResult<()> {
-        info!("Setting up PostgreSQL database...");
-        
-        // Create main table
-        sqlx::query(
+    async fn count_tasks_by_group(&self) -> AppResult<Vec<TaskGroupCount>> {
+        let rows = sqlx::query(
             r#"
-            CREATE TABLE IF NOT EXISTS tasks (
-                id TEXT PRIMARY KEY,
-                name TEXT NOT NULL,
-                payload JSONB NOT NULL,
-                state TEXT NOT NULL,
-                priority TEXT NOT NULL,
-                created_at TIMESTAMPTZ NOT NULL,
-                updated_at TIMESTAMPTZ NOT NULL,
-                scheduled_at TIMESTAMPTZ,
-                started_at TIMESTAMPTZ,
-                completed_at TIMESTAMPTZ,
-                attempts INTEGER NOT NULL DEFAULT 0,
-                max_attempts INTEGER NOT NULL DEFAULT 3,
-                last_error TEXT,
-                worker_id TEXT,
-                result JSONB,
-                tags TEXT[] NOT NULL DEFAULT '{}'
+            SELECT state, priority, name, COUNT(*) AS count
+            FROM tasks
+            GROUP BY state, priority, name
+            ORDER BY state, priority, name
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(AppError::DatabaseError)?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(TaskGroupCount {
+                    state: row.try_get("state")?,
+                    priority: row.try_get("priority")?,
+                    name: row.try_get("name")?,
+                    count: row.try_get("count")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn task_timing_stats(&self, since: DateTime<Utc>) -> AppResult<TimingStats> {
+        let wait = self
+            .duration_percentiles(
+                r#"
+                SELECT GREATEST(
+                    EXTRACT(EPOCH FROM started_at - GREATEST(created_at, COALESCE(scheduled_at, created_at))),
+                    0
+                )::float8 AS v
+                FROM tasks
+                WHERE started_at >= $1
+                "#,
+                since,
+            )
+            .await?;
+
+        let run = self
+            .duration_percentiles(
+                r#"
+                SELECT EXTRACT(EPOCH FROM completed_at - started_at)::float8 AS v
+                FROM tasks
+                WHERE state = 'completed' AND completed_at >= $1 AND started_at IS NOT NULL
+                "#,
+                since,
             )
+            .await?;
+
+        Ok(TimingStats { wait, run })
+    }
+
+    async fn count_tasks_by_tag(&self) -> AppResult<Vec<TagStateCount>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT tag, state, COUNT(*) as count
+            FROM tasks, unnest(tags) AS tag
+            GROUP BY tag, state
+            ORDER BY tag, state
             "#
         )
-        .execute(&self.pool)
+        .fetch_all(&self.pool)
         .await
-        .map_err(|e| AppError::DatabaseError(e))?;
+        .map_err(AppError::DatabaseError)?;
 
-        // Create indexes - run each separately to avoid issues if one fails
-        sqlx::query(
-            "CREATE INDEX IF NOT EXISTS idx_tasks_state ON tasks (state)"
+        let mut counts = Vec::new();
+        for row in rows {
+            counts.push(TagStateCount {
+                tag: row.try_get("tag")?,
+                state: row.try_get("state")?,
+                count: row.try_get("count")?,
+            });
+        }
+
+        Ok(counts)
+    }
+
+    async fn watch_changes(&self) -> AppResult<Option<mpsc::Receiver<ChangeEvent>>> {
+        let mut listener = listen_for_changes(&self.pool)
+            .await
+            .map_err(AppError::DatabaseError)?;
+
+        let (sender, receiver) = mpsc::channel(CHANGE_BUFFER_SIZE);
+        let instance_id = self.instance_id.clone();
+        let pool = self.pool.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let notification = match listener.try_recv().await {
+                    Ok(Some(notification)) => notification,
+                    Ok(None) => {
+                        warn!("Lost the task change listener connection, reconnecting");
+                        listener = loop {
+                            match listen_for_changes(&pool).await {
+                                Ok(listener) => break listener,
+                                Err(e) => {
+                                    warn!("Error reconnecting the task change listener: {}", e);
+                                    tokio::time::sleep(Duration::from_secs(1)).await;
+                                }
+                            }
+                        };
+                        // Changes made while disconnected were announced to nobody
+                        if sender.send(ChangeEvent::Missed).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!("Error receiving task changes: {}", e);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                };
+
+                let change: ChangeNotification = match serde_json::from_str(notification.payload()) {
+                    Ok(change) => change,
+                    Err(e) => {
+                        warn!("Ignoring malformed task change notification: {}", e);
+                        continue;
+                    }
+                };
+
+                // This instance already knows about its own changes
+                if change.origin == instance_id {
+                    continue;
+                }
+
+                let change = TaskChange {
+                    id: change.id,
+                    state: change.state.parse().unwrap_or_default(),
+                    scheduled_at: change.scheduled_at,
+                };
+                if sender.send(ChangeEvent::Changed(change)).await.is_err() {
+                    // Nobody is listening anymore
+                    break;
+                }
+            }
+        });
+
+        Ok(Some(receiver))
+    }
+
+    fn migrations(&self) -> &'static [Migration] {
+        POSTGRES_MIGRATIONS
+    }
+
+    async fn applied_migrations(&self) -> AppResult<Vec<AppliedMigration>> {
+        let exists: bool = sqlx::query_scalar(
+            "SELECT to_regclass('schema_migrations') IS NOT NULL"
         )
-        .execute(&self.pool)
+        .fetch_one(&self.pool)
         .await
-        .map_err(|e| AppError::DatabaseError(e))?;
+        .map_err(AppError::DatabaseError)?;
 
-        sqlx::query(
-            "CREATE INDEX IF NOT EXISTS idx_tasks_priority ON tasks (priority)"
+        if !exists {
+            return Ok(Vec::new());
+        }
+
+        let rows = sqlx::query(
+            "SELECT version, description, applied_at FROM schema_migrations ORDER BY version"
         )
-        .execute(&self.pool)
+        .fetch_all(&self.pool)
         .await
-        .map_err(|e| AppError::DatabaseError(e))?;
+        .map_err(AppError::DatabaseError)?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| AppliedMigration {
+                version: row.get("version"),
+                description: row.get("description"),
+                applied_at: row.get("applied_at"),
+            })
+            .collect())
+    }
+
+    async fn apply_migration(&self, migration: &Migration) -> AppResult<bool> {
+        let mut tx = self.pool.begin().await.map_err(AppError::DatabaseError)?;
+
+        // Serialize instances migrating the same database at startup
+        sqlx::query("SELECT pg_advisory_xact_lock($1)")
+            .bind(MIGRATION_LOCK_KEY)
+            .execute(&mut *tx)
+            .await
+            .map_err(AppError::DatabaseError)?;
 
         sqlx::query(
-            "CREATE INDEX IF NOT EXISTS idx_tasks_scheduled_at ON tasks (scheduled_at)"
+            r#"
+            CREATE TABLE IF NOT EXISTS schema_migrations (
+                version BIGINT PRIMARY KEY,
+                description TEXT NOT NULL,
+                applied_at TIMESTAMPTZ NOT NULL
+            )
+            "#
         )
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await
-        .map_err(|e| AppError::DatabaseError(e))?;
+        .map_err(AppError::DatabaseError)?;
+
+        let applied: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM schema_migrations WHERE version = $1)"
+        )
+        .bind(migration.version)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::DatabaseError)?;
+
+        if applied {
+            return Ok(false);
+        }
+
+        // Plain SQL strings may hold several statements
+        tx.execute(migration.sql)
+            .await
+            .map_err(AppError::DatabaseError)?;
 
         sqlx::query(
-            "CREATE INDEX IF NOT EXISTS idx_tasks_created_at ON tasks (created_at)"
+            "INSERT INTO schema_migrations (version, description, applied_at) VALUES ($1, $2, $3)"
         )
-        .execute(&self.pool)
+        .bind(migration.version)
+        .bind(migration.description)
+        .bind(Utc::now())
+        .execute(&mut *tx)
         .await
-        .map_err(|e| AppError::DatabaseError(e))?;
+        .map_err(AppError::DatabaseError)?;
+
+        tx.commit().await.map_err(AppError::DatabaseError)?;
+        Ok(true)
+    }
 
+    async fn setup(&self) -> AppResult<()> {
+        info!("Setting up PostgreSQL database...");
+        migrate::migrate(self, false).await?;
         info!("PostgreSQL database setup completed.");
         Ok(())
     }
-}
\ No newline at end of file
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::conformance::postgres_schema_url;
+
+    #[tokio::test]
+    #[ignore = "needs a PostgreSQL server in TEST_POSTGRES_URL"]
+    async fn test_changes_are_announced_to_other_instances() {
+        let url = postgres_schema_url().await;
+        let listening = PostgresDatabase::new(&url).await.unwrap();
+        listening.setup().await.unwrap();
+        let other = PostgresDatabase::new(&url).await.unwrap();
+        let mut changes = listening.watch_changes().await.unwrap().unwrap();
+
+        // Changes made by the listening instance itself aren't announced back to it
+        let own = Task::new("own".to_string(), serde_json::json!({}));
+        listening.create_task(&own).await.unwrap();
+
+        let at = Utc::now() + chrono::Duration::hours(1);
+        let mut task = Task::new("other".to_string(), serde_json::json!({})).with_scheduled_time(at);
+        other.create_task(&task).await.unwrap();
+        task.mark_cancelled();
+        other.update_task(&task).await.unwrap();
+
+        let Some(ChangeEvent::Changed(inserted)) =
+            tokio::time::timeout(Duration::from_secs(5), changes.recv()).await.unwrap()
+        else {
+            panic!("expected the insert to be announced");
+        };
+        assert_eq!(inserted.id, task.id);
+        assert_eq!(inserted.state, TaskState::Scheduled);
+        assert_eq!(
+            inserted.scheduled_at.map(|at| at.timestamp_micros()),
+            Some(at.timestamp_micros())
+        );
+
+        let Some(ChangeEvent::Changed(cancelled)) =
+            tokio::time::timeout(Duration::from_secs(5), changes.recv()).await.unwrap()
+        else {
+            panic!("expected the cancellation to be announced");
+        };
+        assert_eq!(cancelled.id, task.id);
+        assert_eq!(cancelled.state, TaskState::Cancelled);
+    }
+}
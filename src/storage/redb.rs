@@ -0,0 +1,820 @@
+use crate::error::{AppError, AppResult};
+use crate::models::{
+    BulkAction, LogLevel, QueuePosition, TagStateCount, Task, TaskCursor, TaskFilter,
+    TaskGroupCount, TaskLogEntry, TaskPriority, TaskProgress, TaskState, TimingStats,
+};
+use crate::storage::database::Database;
+use crate::storage::memory::{
+    group_counts, in_queue_order, is_expiring, is_ready, overwrite, priority_key, tag_counts, timing_stats,
+};
+use crate::storage::migrate::{AppliedMigration, Migration};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use redb::{
+    Key, ReadOnlyTable, ReadTransaction, ReadableTable, ReadableTableMetadata, Table,
+    TableDefinition, WriteTransaction,
+};
+use std::borrow::Borrow;
+use std::collections::{BTreeSet, HashSet};
+use std::sync::Arc;
+use tracing::info;
+
+// Index keys pairing an indexed value with a task ID
+type TextKey = (&'static str, &'static str);
+// Index keys pairing a time in microseconds with a task ID
+type TimeKey = (i64, &'static str);
+
+// Tasks as JSON, keyed by ID
+const TASKS: TableDefinition<&str, &[u8]> = TableDefinition::new("tasks");
+
+// Secondary indexes; only the keys carry information
+const BY_CREATED: TableDefinition<TimeKey, ()> = TableDefinition::new("tasks_by_created");
+const BY_STATE: TableDefinition<TextKey, ()> = TableDefinition::new("tasks_by_state");
+const BY_PRIORITY: TableDefinition<TextKey, ()> = TableDefinition::new("tasks_by_priority");
+const BY_TAG: TableDefinition<TextKey, ()> = TableDefinition::new("tasks_by_tag");
+// Scheduled tasks only, by when they are due
+const BY_SCHEDULED: TableDefinition<TimeKey, ()> = TableDefinition::new("tasks_by_scheduled");
+
+// Log lines as JSON, keyed by (task ID, seq)
+const LOGS: TableDefinition<(&str, i64), &[u8]> = TableDefinition::new("task_logs");
+
+// Counters
+const META: TableDefinition<&str, i64> = TableDefinition::new("meta");
+const LOG_SEQ: &str = "log_seq";
+
+const FINISHED_STATES: &[TaskState] = &[
+    TaskState::Completed,
+    TaskState::Failed,
+    TaskState::Cancelled,
+    TaskState::Expired,
+];
+
+/// Database stored in a single redb file, for single-binary deployments.
+///
+/// Tasks are stored as JSON with secondary indexes on creation time, state,
+/// priority, tags and scheduled time. redb allows one writer at a time, but
+/// readers work from snapshots and never wait for it.
+pub struct RedbDatabase {
+    db: Arc<redb::Database>,
+}
+
+fn storage_error(e: impl Into<redb::Error>) -> AppError {
+    AppError::StorageError(Box::new(e.into()))
+}
+
+// Position of a task in the (created_at, id) ordering
+fn created_key(task: &Task) -> (i64, &str) {
+    (task.created_at.timestamp_micros(), task.id.as_str())
+}
+
+// Newest first by (created_at, id), like the SQL listings
+fn sort_newest_first(tasks: &mut [Task]) {
+    tasks.sort_by(|a, b| created_key(b).cmp(&created_key(a)));
+}
+
+// Add or remove an index entry
+fn set_entry<'k, K: Key + 'static>(
+    index: &mut Table<'_, K, ()>,
+    key: impl Borrow<K::SelfType<'k>>,
+    present: bool,
+) -> AppResult<()> {
+    if present {
+        index.insert(key, ()).map_err(storage_error)?;
+    } else {
+        index.remove(key).map_err(storage_error)?;
+    }
+    Ok(())
+}
+
+struct TaskTables<T, K, N> {
+    tasks: T,
+    by_created: N,
+    by_state: K,
+    by_priority: K,
+    by_tag: K,
+    by_scheduled: N,
+}
+
+type ReadTables = TaskTables<
+    ReadOnlyTable<&'static str, &'static [u8]>,
+    ReadOnlyTable<TextKey, ()>,
+    ReadOnlyTable<TimeKey, ()>,
+>;
+
+type WriteTables<'txn> = TaskTables<
+    Table<'txn, &'static str, &'static [u8]>,
+    Table<'txn, TextKey, ()>,
+    Table<'txn, TimeKey, ()>,
+>;
+
+impl ReadTables {
+    fn open(txn: &ReadTransaction) -> AppResult<Self> {
+        Ok(Self {
+            tasks: txn.open_table(TASKS).map_err(storage_error)?,
+            by_created: txn.open_table(BY_CREATED).map_err(storage_error)?,
+            by_state: txn.open_table(BY_STATE).map_err(storage_error)?,
+            by_priority: txn.open_table(BY_PRIORITY).map_err(storage_error)?,
+            by_tag: txn.open_table(BY_TAG).map_err(storage_error)?,
+            by_scheduled: txn.open_table(BY_SCHEDULED).map_err(storage_error)?,
+        })
+    }
+}
+
+impl<T, K, N> TaskTables<T, K, N>
+where
+    T: ReadableTable<&'static str, &'static [u8]>,
+    K: ReadableTable<TextKey, ()>,
+    N: ReadableTable<TimeKey, ()>,
+{
+    fn task(&self, id: &str) -> AppResult<Option<Task>> {
+        match self.tasks.get(id).map_err(storage_error)? {
+            Some(json) => Ok(Some(serde_json::from_slice(json.value())?)),
+            None => Ok(None),
+        }
+    }
+
+    fn load(&self, ids: impl IntoIterator<Item = String>) -> AppResult<Vec<Task>> {
+        let mut tasks = Vec::new();
+        for id in ids {
+            if let Some(task) = self.task(&id)? {
+                tasks.push(task);
+            }
+        }
+        Ok(tasks)
+    }
+
+    fn all(&self) -> AppResult<Vec<Task>> {
+        let mut tasks = Vec::new();
+        for entry in self.tasks.iter().map_err(storage_error)? {
+            let (_, json) = entry.map_err(storage_error)?;
+            tasks.push(serde_json::from_slice(json.value())?);
+        }
+        Ok(tasks)
+    }
+
+    // IDs filed under a value in one of the text indexes
+    fn ids_under(index: &K, value: &str) -> AppResult<Vec<String>> {
+        let mut ids = Vec::new();
+        for entry in index.range((value, "")..).map_err(storage_error)? {
+            let (key, _) = entry.map_err(storage_error)?;
+            let (indexed, id) = key.value();
+            if indexed != value {
+                break;
+            }
+            ids.push(id.to_string());
+        }
+        Ok(ids)
+    }
+
+    // Count the entries of a text index by indexed value
+    fn count_under(index: &K) -> AppResult<Vec<(String, i64)>> {
+        let mut counts: Vec<(String, i64)> = Vec::new();
+        for entry in index.iter().map_err(storage_error)? {
+            let (key, _) = entry.map_err(storage_error)?;
+            let (indexed, _) = key.value();
+            match counts.last_mut() {
+                Some((value, count)) if value == indexed => *count += 1,
+                _ => counts.push((indexed.to_string(), 1)),
+            }
+        }
+        Ok(counts)
+    }
+
+    // Whether an index can narrow the tasks matching the filter
+    fn is_indexed(filter: &TaskFilter) -> bool {
+        !filter.ids.is_empty()
+            || filter.state.is_some()
+            || !filter.tags.is_empty()
+            || filter.priority.is_some()
+    }
+
+    // Tasks in one of `states` (any state if empty) matching the filter, newest
+    // first. The candidates come from the first index the filter can use.
+    fn select(&self, filter: &TaskFilter, states: &[TaskState]) -> AppResult<Vec<Task>> {
+        let candidates = if !filter.ids.is_empty() {
+            let ids: BTreeSet<String> = filter.ids.iter().cloned().collect();
+            self.load(ids)?
+        } else if let Some(state) = &filter.state {
+            self.load(Self::ids_under(&self.by_state, state)?)?
+        } else if !states.is_empty() {
+            let mut ids = Vec::new();
+            for state in states {
+                ids.extend(Self::ids_under(&self.by_state, &state.to_string())?);
+            }
+            self.load(ids)?
+        } else if let Some(tag) = filter.tags.first() {
+            self.load(Self::ids_under(&self.by_tag, tag)?)?
+        } else if let Some(priority) = &filter.priority {
+            self.load(Self::ids_under(&self.by_priority, priority)?)?
+        } else {
+            self.all()?
+        };
+
+        let mut tasks: Vec<Task> = candidates
+            .into_iter()
+            .filter(|task| (states.is_empty() || states.contains(&task.state)) && filter.matches(task))
+            .collect();
+        sort_newest_first(&mut tasks);
+        Ok(tasks)
+    }
+
+    // Walk the creation index newest first from the cursor, for listings no other index narrows
+    fn newest(
+        &self,
+        filter: &TaskFilter,
+        after: Option<&TaskCursor>,
+        limit: usize,
+    ) -> AppResult<Vec<Task>> {
+        let entries = match after {
+            Some(cursor) => self
+                .by_created
+                .range(..(cursor.created_at.timestamp_micros(), cursor.id.as_str())),
+            None => self.by_created.iter(),
+        }
+        .map_err(storage_error)?;
+
+        let mut tasks = Vec::new();
+        for entry in entries.rev() {
+            if tasks.len() >= limit {
+                break;
+            }
+            let (key, _) = entry.map_err(storage_error)?;
+            let (_, id) = key.value();
+            if let Some(task) = self.task(id)? {
+                if filter.matches(&task) {
+                    tasks.push(task);
+                }
+            }
+        }
+        Ok(tasks)
+    }
+
+    // Finished tasks matching the filter but none of the exclusions, least recently changed first
+    fn purgeable(&self, filter: &TaskFilter, exclude: &[TaskFilter], limit: u32) -> AppResult<Vec<Task>> {
+        let mut tasks: Vec<Task> = self
+            .select(filter, FINISHED_STATES)?
+            .into_iter()
+            .filter(|task| !exclude.iter().any(|excluded| excluded.matches(task)))
+            .collect();
+        tasks.sort_by(|a, b| (a.updated_at, &a.id).cmp(&(b.updated_at, &b.id)));
+        tasks.truncate(limit as usize);
+        Ok(tasks)
+    }
+}
+
+impl<'txn> WriteTables<'txn> {
+    fn open(txn: &'txn WriteTransaction) -> AppResult<Self> {
+        Ok(Self {
+            tasks: txn.open_table(TASKS).map_err(storage_error)?,
+            by_created: txn.open_table(BY_CREATED).map_err(storage_error)?,
+            by_state: txn.open_table(BY_STATE).map_err(storage_error)?,
+            by_priority: txn.open_table(BY_PRIORITY).map_err(storage_error)?,
+            by_tag: txn.open_table(BY_TAG).map_err(storage_error)?,
+            by_scheduled: txn.open_table(BY_SCHEDULED).map_err(storage_error)?,
+        })
+    }
+
+    // Store a task, moving its index entries from where `old` had them
+    fn put(&mut self, old: Option<&Task>, task: &Task) -> AppResult<()> {
+        if let Some(old) = old {
+            self.set_index_entries(old, false)?;
+        }
+        let json = serde_json::to_vec(task)?;
+        self.tasks
+            .insert(task.id.as_str(), json.as_slice())
+            .map_err(storage_error)?;
+        self.set_index_entries(task, true)
+    }
+
+    fn remove(&mut self, task: &Task) -> AppResult<()> {
+        self.set_index_entries(task, false)?;
+        self.tasks.remove(task.id.as_str()).map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn set_index_entries(&mut self, task: &Task, present: bool) -> AppResult<()> {
+        let id = task.id.as_str();
+        let state = task.state.to_string();
+        let priority = task.priority.to_string();
+
+        set_entry(&mut self.by_created, created_key(task), present)?;
+        set_entry(&mut self.by_state, (state.as_str(), id), present)?;
+        set_entry(&mut self.by_priority, (priority.as_str(), id), present)?;
+        for tag in task.tags.iter().collect::<HashSet<_>>() {
+            set_entry(&mut self.by_tag, (tag.as_str(), id), present)?;
+        }
+        if let (TaskState::Scheduled, Some(scheduled_at)) = (&task.state, task.scheduled_at) {
+            set_entry(&mut self.by_scheduled, (scheduled_at.timestamp_micros(), id), present)?;
+        }
+        Ok(())
+    }
+
+    // Change every task in one of `states` matching the filter, returning the changed tasks
+    fn update_where(
+        &mut self,
+        filter: &TaskFilter,
+        states: &[TaskState],
+        change: impl Fn(&mut Task),
+    ) -> AppResult<Vec<Task>> {
+        let tasks = self.select(filter, states)?;
+        let mut changed = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            let mut updated = task.clone();
+            change(&mut updated);
+            self.put(Some(&task), &updated)?;
+            changed.push(updated);
+        }
+        Ok(changed)
+    }
+}
+
+// Remove tasks along with their logs
+fn delete_tasks(txn: &WriteTransaction, tables: &mut WriteTables<'_>, tasks: &[Task]) -> AppResult<Vec<String>> {
+    let mut logs = txn.open_table(LOGS).map_err(storage_error)?;
+    for task in tasks {
+        tables.remove(task)?;
+        let id = task.id.as_str();
+        logs.retain_in((id, i64::MIN)..=(id, i64::MAX), |_, _| false)
+            .map_err(storage_error)?;
+    }
+    Ok(tasks.iter().map(|task| task.id.clone()).collect())
+}
+
+impl RedbDatabase {
+    /// Open the database file at `path`, creating it if needed
+    pub fn new(path: &str) -> AppResult<Self> {
+        let db = redb::Database::create(path).map_err(storage_error)?;
+
+        // Read transactions can only open tables that already exist
+        let txn = db.begin_write().map_err(storage_error)?;
+        WriteTables::open(&txn)?;
+        txn.open_table(LOGS).map_err(storage_error)?;
+        txn.open_table(META).map_err(storage_error)?;
+        txn.commit().map_err(storage_error)?;
+
+        Ok(Self { db: Arc::new(db) })
+    }
+
+    // Run a closure against a read snapshot, off the async runtime
+    async fn read<R, F>(&self, f: F) -> AppResult<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&ReadTables) -> AppResult<R> + Send + 'static,
+    {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            let txn = db.begin_read().map_err(storage_error)?;
+            f(&ReadTables::open(&txn)?)
+        })
+        .await
+        .map_err(|e| AppError::InternalServerError(format!("Storage task failed: {}", e)))?
+    }
+
+    // Run a closure in a write transaction, committing only if it succeeds
+    async fn write<R, F>(&self, f: F) -> AppResult<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&WriteTransaction) -> AppResult<R> + Send + 'static,
+    {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            let txn = db.begin_write().map_err(storage_error)?;
+            let result = f(&txn)?;
+            txn.commit().map_err(storage_error)?;
+            Ok(result)
+        })
+        .await
+        .map_err(|e| AppError::InternalServerError(format!("Storage task failed: {}", e)))?
+    }
+}
+
+#[async_trait]
+impl Database for RedbDatabase {
+    async fn create_task(&self, task: &Task) -> AppResult<()> {
+        self.create_tasks(std::slice::from_ref(task)).await
+    }
+
+    async fn create_tasks(&self, tasks: &[Task]) -> AppResult<()> {
+        let tasks = tasks.to_vec();
+        self.write(move |txn| {
+            let mut tables = WriteTables::open(txn)?;
+            for task in &tasks {
+                if tables.task(&task.id)?.is_some() {
+                    return Err(AppError::TaskAlreadyExists(task.id.clone()));
+                }
+                tables.put(None, task)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_task(&self, id: &str) -> AppResult<Task> {
+        let id = id.to_string();
+        self.read(move |tables| tables.task(&id)?.ok_or(AppError::TaskNotFound(id)))
+            .await
+    }
+
+    async fn update_task(&self, task: &Task) -> AppResult<()> {
+        let task = task.clone();
+        self.write(move |txn| {
+            let mut tables = WriteTables::open(txn)?;
+            if let Some(stored) = tables.task(&task.id)? {
+                let mut updated = stored.clone();
+                overwrite(&mut updated, &task);
+                tables.put(Some(&stored), &updated)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn update_task_if_state(&self, task: &Task, expected: &TaskState) -> AppResult<bool> {
+        let task = task.clone();
+        let expected = expected.clone();
+        self.write(move |txn| {
+            let mut tables = WriteTables::open(txn)?;
+            match tables.task(&task.id)? {
+                Some(stored) if stored.state == expected => {
+                    let mut updated = stored.clone();
+                    overwrite(&mut updated, &task);
+                    tables.put(Some(&stored), &updated)?;
+                    Ok(true)
+                }
+                _ => Ok(false),
+            }
+        })
+        .await
+    }
+
+    async fn delete_task(&self, id: &str) -> AppResult<()> {
+        let id = id.to_string();
+        self.write(move |txn| {
+            let mut tables = WriteTables::open(txn)?;
+            let tasks: Vec<Task> = tables.task(&id)?.into_iter().collect();
+            delete_tasks(txn, &mut tables, &tasks)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_tasks(
+        &self,
+        filter: &TaskFilter,
+        after: Option<&TaskCursor>,
+        limit: Option<u32>,
+    ) -> AppResult<Vec<Task>> {
+        let filter = filter.clone();
+        let after = after.cloned();
+        let limit = limit.map_or(usize::MAX, |limit| limit as usize);
+
+        self.read(move |tables| {
+            if !ReadTables::is_indexed(&filter) {
+                return tables.newest(&filter, after.as_ref(), limit);
+            }
+
+            let cursor = after.as_ref().map(|c| (c.created_at.timestamp_micros(), c.id.as_str()));
+            Ok(tables
+                .select(&filter, &[])?
+                .into_iter()
+                .filter(|task| cursor.is_none_or(|cursor| created_key(task) < cursor))
+                .take(limit)
+                .collect())
+        })
+        .await
+    }
+
+    async fn count_tasks(&self, filter: &TaskFilter) -> AppResult<i64> {
+        let filter = filter.clone();
+        self.read(move |tables| {
+            if filter.is_empty() {
+                return Ok(tables.tasks.len().map_err(storage_error)? as i64);
+            }
+            Ok(tables.select(&filter, &[])?.len() as i64)
+        })
+        .await
+    }
+
+    async fn claim_task(&self, id: &str, worker_id: &str) -> AppResult<Option<Task>> {
+        let id = id.to_string();
+        let worker_id = worker_id.to_string();
+        self.write(move |txn| {
+            let mut tables = WriteTables::open(txn)?;
+            let Some(stored) = tables.task(&id)? else {
+                return Ok(None);
+            };
+
+            let now = Utc::now();
+            if !is_ready(&stored, now) {
+                return Ok(None);
+            }
+
+            let mut task = stored.clone();
+            task.state = TaskState::Running;
+            task.worker_id = Some(worker_id);
+            task.started_at = Some(now);
+            task.updated_at = now;
+            task.progress = None;
+            tables.put(Some(&stored), &task)?;
+            Ok(Some(task))
+        })
+        .await
+    }
+
+    async fn cancel_tasks(&self, filter: &TaskFilter) -> AppResult<Vec<Task>> {
+        let filter = filter.clone();
+        let now = Utc::now();
+        self.write(move |txn| {
+            WriteTables::open(txn)?.update_where(&filter, BulkAction::Cancel.eligible_states(), |task| {
+                task.state = TaskState::Cancelled;
+                task.updated_at = now;
+            })
+        })
+        .await
+    }
+
+    async fn expire_tasks(&self, filter: &TaskFilter, now: DateTime<Utc>) -> AppResult<Vec<Task>> {
+        let filter = filter.clone();
+        self.write(move |txn| {
+            let mut tables = WriteTables::open(txn)?;
+            let waiting = [TaskState::Pending, TaskState::Scheduled, TaskState::Failed];
+            let mut expired = Vec::new();
+            for task in tables.select(&filter, &waiting)? {
+                if !is_expiring(&task, now) {
+                    continue;
+                }
+                let mut updated = task.clone();
+                updated.state = TaskState::Expired;
+                updated.updated_at = now;
+                tables.put(Some(&task), &updated)?;
+                expired.push(updated);
+            }
+            Ok(expired)
+        })
+        .await
+    }
+
+    async fn reprioritize_tasks(
+        &self,
+        filter: &TaskFilter,
+        priority: &TaskPriority,
+    ) -> AppResult<Vec<String>> {
+        let filter = filter.clone();
+        let priority = priority.clone();
+        let now = Utc::now();
+        let tasks = self
+            .write(move |txn| {
+                WriteTables::open(txn)?.update_where(
+                    &filter,
+                    BulkAction::Reprioritize.eligible_states(),
+                    |task| {
+                        task.priority = priority.clone();
+                        task.updated_at = now;
+                    },
+                )
+            })
+            .await?;
+        Ok(tasks.into_iter().map(|task| task.id).collect())
+    }
+
+    async fn retry_tasks(&self, filter: &TaskFilter, reset_attempts: bool) -> AppResult<Vec<Task>> {
+        let filter = filter.clone();
+        let now = Utc::now();
+        self.write(move |txn| {
+            WriteTables::open(txn)?.update_where(&filter, BulkAction::Retry.eligible_states(), |task| {
+                task.state = TaskState::Pending;
+                task.worker_id = None;
+                task.started_at = None;
+                task.completed_at = None;
+                task.updated_at = now;
+                if reset_attempts {
+                    task.attempts = 0;
+                }
+            })
+        })
+        .await
+    }
+
+    async fn delete_tasks(&self, filter: &TaskFilter) -> AppResult<Vec<String>> {
+        let filter = filter.clone();
+        self.write(move |txn| {
+            let mut tables = WriteTables::open(txn)?;
+            let tasks = tables.select(&filter, BulkAction::Delete.eligible_states())?;
+            delete_tasks(txn, &mut tables, &tasks)
+        })
+        .await
+    }
+
+    async fn find_purgeable_tasks(
+        &self,
+        filter: &TaskFilter,
+        exclude: &[TaskFilter],
+        limit: u32,
+    ) -> AppResult<Vec<Task>> {
+        let filter = filter.clone();
+        let exclude = exclude.to_vec();
+        self.read(move |tables| tables.purgeable(&filter, &exclude, limit))
+            .await
+    }
+
+    async fn purge_tasks(
+        &self,
+        filter: &TaskFilter,
+        exclude: &[TaskFilter],
+        limit: u32,
+    ) -> AppResult<Vec<String>> {
+        let filter = filter.clone();
+        let exclude = exclude.to_vec();
+        self.write(move |txn| {
+            let mut tables = WriteTables::open(txn)?;
+            let tasks = tables.purgeable(&filter, &exclude, limit)?;
+            delete_tasks(txn, &mut tables, &tasks)
+        })
+        .await
+    }
+
+    async fn update_task_progress(
+        &self,
+        id: &str,
+        progress: &TaskProgress,
+        checkpoint: Option<&serde_json::Value>,
+    ) -> AppResult<bool> {
+        let id = id.to_string();
+        let progress = progress.clone();
+        let checkpoint = checkpoint.cloned();
+        self.write(move |txn| {
+            let mut tables = WriteTables::open(txn)?;
+            let stored = match tables.task(&id)? {
+                Some(stored) if stored.state == TaskState::Running => stored,
+                _ => return Ok(false),
+            };
+
+            let mut task = stored.clone();
+            task.updated_at = progress.updated_at;
+            task.progress = Some(progress);
+            if checkpoint.is_some() {
+                task.checkpoint = checkpoint;
+            }
+            tables.put(Some(&stored), &task)?;
+            Ok(true)
+        })
+        .await
+    }
+
+    async fn append_task_log(
+        &self,
+        task_id: &str,
+        attempt: u32,
+        level: LogLevel,
+        message: &str,
+        logged_at: DateTime<Utc>,
+    ) -> AppResult<()> {
+        let task_id = task_id.to_string();
+        let message = message.to_string();
+        self.write(move |txn| {
+            let mut meta = txn.open_table(META).map_err(storage_error)?;
+            let seq = meta
+                .get(LOG_SEQ)
+                .map_err(storage_error)?
+                .map_or(0, |seq| seq.value())
+                + 1;
+            meta.insert(LOG_SEQ, seq).map_err(storage_error)?;
+
+            let entry = TaskLogEntry {
+                seq,
+                task_id,
+                attempt,
+                level,
+                message,
+                logged_at,
+            };
+            let json = serde_json::to_vec(&entry)?;
+            txn.open_table(LOGS)
+                .map_err(storage_error)?
+                .insert((entry.task_id.as_str(), seq), json.as_slice())
+                .map_err(storage_error)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_task_logs(
+        &self,
+        task_id: &str,
+        after_seq: Option<i64>,
+        limit: u32,
+    ) -> AppResult<Vec<TaskLogEntry>> {
+        let task_id = task_id.to_string();
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            let txn = db.begin_read().map_err(storage_error)?;
+            let logs = txn.open_table(LOGS).map_err(storage_error)?;
+            let start = after_seq.unwrap_or(0).saturating_add(1);
+
+            let mut entries = Vec::new();
+            let range = logs
+                .range((task_id.as_str(), start)..=(task_id.as_str(), i64::MAX))
+                .map_err(storage_error)?;
+            for entry in range.take(limit as usize) {
+                let (_, json) = entry.map_err(storage_error)?;
+                entries.push(serde_json::from_slice(json.value())?);
+            }
+            Ok(entries)
+        })
+        .await
+        .map_err(|e| AppError::InternalServerError(format!("Storage task failed: {}", e)))?
+    }
+
+    async fn get_scheduled_tasks(&self, before: DateTime<Utc>) -> AppResult<Vec<Task>> {
+        self.read(move |tables| {
+            let mut ids = Vec::new();
+            let due = tables
+                .by_scheduled
+                .range(..(before.timestamp_micros().saturating_add(1), ""))
+                .map_err(storage_error)?;
+            for entry in due {
+                let (key, _) = entry.map_err(storage_error)?;
+                ids.push(key.value().1.to_string());
+            }
+
+            let mut tasks: Vec<Task> = tables
+                .load(ids)?
+                .into_iter()
+                .filter(|task| task.scheduled_at.is_some_and(|at| at <= before))
+                .collect();
+            tasks.sort_by_key(|task| (priority_key(task), task.scheduled_at));
+            Ok(tasks)
+        })
+        .await
+    }
+
+    async fn get_ready_tasks(
+        &self,
+        from: Option<QueuePosition>,
+        now: DateTime<Utc>,
+        limit: u32,
+    ) -> AppResult<Vec<Task>> {
+        self.read(move |tables| {
+            let waiting = tables.select(
+                &TaskFilter::default(),
+                &[TaskState::Pending, TaskState::Scheduled],
+            )?;
+            let ready = waiting.into_iter().filter(|task| is_ready(task, now));
+            Ok(in_queue_order(ready, from, limit))
+        })
+        .await
+    }
+
+    async fn get_failed_tasks_for_retry(&self) -> AppResult<Vec<Task>> {
+        self.read(|tables| {
+            let mut tasks: Vec<Task> = tables
+                .select(&TaskFilter::default(), &[TaskState::Failed])?
+                .into_iter()
+                .filter(|task| task.attempts < task.max_attempts)
+                .collect();
+            tasks.sort_by_key(|task| (priority_key(task), task.updated_at));
+            Ok(tasks)
+        })
+        .await
+    }
+
+    async fn count_tasks_by_state(&self) -> AppResult<Vec<(String, i64)>> {
+        self.read(|tables| ReadTables::count_under(&tables.by_state)).await
+    }
+
+    async fn count_tasks_by_priority(&self) -> AppResult<Vec<(String, i64)>> {
+        self.read(|tables| ReadTables::count_under(&tables.by_priority)).await
+    }
+
+    async fn count_tasks_by_group(&self) -> AppResult<Vec<TaskGroupCount>> {
+        self.read(|tables| Ok(group_counts(&tables.all()?))).await
+    }
+
+    // There is no index on start or completion time, so this scans every task
+    async fn task_timing_stats(&self, since: DateTime<Utc>) -> AppResult<TimingStats> {
+        self.read(move |tables| Ok(timing_stats(&tables.all()?, since))).await
+    }
+
+    async fn count_tasks_by_tag(&self) -> AppResult<Vec<TagStateCount>> {
+        self.read(|tables| Ok(tag_counts(&tables.all()?))).await
+    }
+
+    // Tables are created when the file is opened, and new optional task fields
+    // deserialize as absent from older records, so there is no schema to migrate
+    fn migrations(&self) -> &'static [Migration] {
+        &[]
+    }
+
+    async fn applied_migrations(&self) -> AppResult<Vec<AppliedMigration>> {
+        Ok(Vec::new())
+    }
+
+    async fn apply_migration(&self, _migration: &Migration) -> AppResult<bool> {
+        Ok(false)
+    }
+
+    async fn setup(&self) -> AppResult<()> {
+        info!("redb database ready");
+        Ok(())
+    }
+}
@@ -1,12 +1,35 @@
 use crate::error::{AppError, AppResult};
-use crate::models::{Task, TaskPriority, TaskState};
+use crate::models::{
+    DurationPercentiles, LogLevel, QueuePosition, TagStateCount, Task, TaskCursor, TaskFilter,
+    TaskGroupCount, TaskLogEntry, TaskPriority, TaskProgress, TaskState, TimingStats,
+};
 use crate::storage::database::Database;
+use crate::storage::migrate::{self, AppliedMigration, Migration, SQLITE_MIGRATIONS};
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use log::{info, warn};
-use sqlx::{sqlite::SqlitePoolOptions, Row, SqlitePool};
+use tracing::{info, warn};
+use sqlx::sqlite::{Sqlite, SqliteConnection, SqlitePoolOptions, SqliteRow};
+use sqlx::{Executor, QueryBuilder, Row, SqlitePool};
 use std::time::Duration;
 
+// Rows per multi-row statement, kept well below SQLite's bind parameter limit
+const INSERT_CHUNK_SIZE: usize = 500;
+
+// Place of a task's priority in queue order, as TaskPriority::rank gives it. The
+// ready task index is built on this exact expression.
+const PRIORITY_RANK: &str =
+    "CASE priority WHEN 'critical' THEN 0 WHEN 'high' THEN 1 WHEN 'medium' THEN 2 ELSE 3 END";
+
+const TASK_COLUMNS: &str = r#"
+    id, name, payload, state, priority,
+    created_at, updated_at, scheduled_at,
+    started_at, completed_at, attempts,
+    max_attempts, last_error, worker_id,
+    result, tags, trace_id,
+    progress, checkpoint, tenant,
+    expires_at, timeout_seconds, deadline
+"#;
+
 pub struct SqliteDatabase {
     pool: SqlitePool,
 }
@@ -26,12 +49,286 @@ impl SqliteDatabase {
 
         Ok(Self { pool })
     }
+
+    // Nearest-rank percentiles of the durations `v` selected by `samples`, which
+    // takes a single bind parameter. The smallest value ranked at or above p * n
+    // is the value at rank ceil(p * n).
+    async fn duration_percentiles(&self, samples: &str, since: i64) -> AppResult<DurationPercentiles> {
+        let row = sqlx::query(&format!(
+            r#"
+            WITH ranked AS (
+                SELECT v, ROW_NUMBER() OVER (ORDER BY v) AS rn, COUNT(*) OVER () AS n
+                FROM ({}) AS samples
+            )
+            SELECT
+                COUNT(*) AS count,
+                CAST(MIN(CASE WHEN rn >= 0.50 * n THEN v END) AS REAL) AS p50,
+                CAST(MIN(CASE WHEN rn >= 0.95 * n THEN v END) AS REAL) AS p95,
+                CAST(MIN(CASE WHEN rn >= 0.99 * n THEN v END) AS REAL) AS p99
+            FROM ranked
+            "#,
+            samples
+        ))
+        .bind(since)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(AppError::DatabaseError)?;
+
+        Ok(DurationPercentiles {
+            count: row.try_get("count")?,
+            p50: row.try_get("p50")?,
+            p95: row.try_get("p95")?,
+            p99: row.try_get("p99")?,
+        })
+    }
+}
+
+// Timestamps are stored as Unix seconds
+fn from_timestamp(ts: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(ts, 0).unwrap_or_else(Utc::now)
+}
+
+// Convert a row selected with TASK_COLUMNS into a Task
+fn row_to_task(row: &SqliteRow) -> AppResult<Task> {
+    let payload_str: String = row.try_get("payload")?;
+    let state_str: String = row.try_get("state")?;
+    let priority_str: String = row.try_get("priority")?;
+    let created_at: i64 = row.try_get("created_at")?;
+    let updated_at: i64 = row.try_get("updated_at")?;
+    let scheduled_at: Option<i64> = row.try_get("scheduled_at")?;
+    let started_at: Option<i64> = row.try_get("started_at")?;
+    let completed_at: Option<i64> = row.try_get("completed_at")?;
+    let attempts: i32 = row.try_get("attempts")?;
+    let max_attempts: i32 = row.try_get("max_attempts")?;
+    let result_str: Option<String> = row.try_get("result")?;
+    let tags_str: Option<String> = row.try_get("tags")?;
+    let progress_str: Option<String> = row.try_get("progress")?;
+    let checkpoint_str: Option<String> = row.try_get("checkpoint")?;
+    let expires_at: Option<i64> = row.try_get("expires_at")?;
+    let timeout_seconds: Option<i64> = row.try_get("timeout_seconds")?;
+    let deadline: Option<i64> = row.try_get("deadline")?;
+
+    let payload: serde_json::Value = serde_json::from_str(&payload_str)
+        .map_err(AppError::SerializationError)?;
+
+    let result = match result_str {
+        Some(r) => Some(serde_json::from_str(&r).map_err(AppError::SerializationError)?),
+        None => None,
+    };
+
+    let tags: Vec<String> = tags_str
+        .and_then(|t| serde_json::from_str(&t).ok())
+        .unwrap_or_default();
+
+    let progress = progress_str.and_then(|p| serde_json::from_str(&p).ok());
+    let checkpoint = checkpoint_str.and_then(|c| serde_json::from_str(&c).ok());
+
+    Ok(Task {
+        id: row.try_get("id")?,
+        name: row.try_get("name")?,
+        payload,
+        state: state_str.parse().unwrap_or_default(),
+        priority: priority_str.parse().unwrap_or_default(),
+        created_at: from_timestamp(created_at),
+        updated_at: from_timestamp(updated_at),
+        scheduled_at: scheduled_at.map(from_timestamp),
+        started_at: started_at.map(from_timestamp),
+        completed_at: completed_at.map(from_timestamp),
+        attempts: attempts as u32,
+        max_attempts: max_attempts as u32,
+        last_error: row.try_get("last_error")?,
+        worker_id: row.try_get("worker_id")?,
+        result,
+        tags,
+        trace_id: row.try_get("trace_id")?,
+        progress,
+        checkpoint,
+        tenant: row.try_get("tenant")?,
+        expires_at: expires_at.map(from_timestamp),
+        timeout_seconds: timeout_seconds.map(|t| t as u64),
+        deadline: deadline.map(from_timestamp),
+    })
+}
+
+fn progress_to_json(progress: &TaskProgress) -> String {
+    serde_json::to_string(progress).unwrap_or_else(|_| "null".to_string())
+}
+
+// Append a WHERE clause selecting finished tasks that match the filter but none of the exclusions
+fn push_purge_conditions(
+    builder: &mut QueryBuilder<'_, Sqlite>,
+    filter: &TaskFilter,
+    exclude: &[TaskFilter],
+) {
+    builder.push(" WHERE state IN ('completed', 'failed', 'cancelled', 'expired')");
+    push_filter(builder, filter);
+    for excluded in exclude {
+        builder.push(" AND NOT (1 = 1");
+        push_filter(builder, excluded);
+        builder.push(")");
+    }
+}
+
+// Convert a task_logs row into a TaskLogEntry
+fn row_to_log_entry(row: &SqliteRow) -> AppResult<TaskLogEntry> {
+    let attempt: i32 = row.try_get("attempt")?;
+    let level_str: String = row.try_get("level")?;
+    let logged_at: i64 = row.try_get("logged_at")?;
+
+    Ok(TaskLogEntry {
+        seq: row.try_get("seq")?,
+        task_id: row.try_get("task_id")?,
+        attempt: attempt as u32,
+        level: level_str.parse().unwrap_or_default(),
+        message: row.try_get("message")?,
+        logged_at: from_timestamp(logged_at),
+    })
+}
+
+// Append the filter's conditions to a query that already has a WHERE clause
+fn push_filter(builder: &mut QueryBuilder<'_, Sqlite>, filter: &TaskFilter) {
+    if !filter.ids.is_empty() {
+        builder.push(" AND id IN (");
+        let mut separated = builder.separated(", ");
+        for id in &filter.ids {
+            separated.push_bind(id.clone());
+        }
+        builder.push(")");
+    }
+
+    if let Some(state) = &filter.state {
+        builder.push(" AND state = ").push_bind(state.clone());
+    }
+
+    if let Some(priority) = &filter.priority {
+        builder.push(" AND priority = ").push_bind(priority.clone());
+    }
+
+    if let Some(name) = &filter.name {
+        builder.push(" AND name = ").push_bind(name.clone());
+    }
+
+    if let Some(created_after) = filter.created_after {
+        builder.push(" AND created_at >= ").push_bind(created_after.timestamp());
+    }
+
+    if let Some(created_before) = filter.created_before {
+        builder.push(" AND created_at < ").push_bind(created_before.timestamp());
+    }
+
+    if let Some(updated_before) = filter.updated_before {
+        builder.push(" AND updated_at < ").push_bind(updated_before.timestamp());
+    }
+
+    if let Some(deadline_before) = filter.deadline_before {
+        builder.push(" AND deadline <= ").push_bind(deadline_before.timestamp());
+    }
+
+    if let Some(error) = &filter.error_contains {
+        builder
+            .push(" AND instr(last_error, ")
+            .push_bind(error.clone())
+            .push(") > 0");
+    }
+
+    if let Some(tenant) = &filter.tenant {
+        builder.push(" AND tenant = ").push_bind(tenant.clone());
+    }
+
+    for tag in &filter.tags {
+        builder
+            .push(" AND id IN (SELECT task_id FROM task_tags WHERE tag = ")
+            .push_bind(tag.clone())
+            .push(")");
+    }
+}
+
+// Keep the normalized task_tags table in sync with a task's tags
+async fn replace_task_tags(
+    conn: &mut SqliteConnection,
+    task_id: &str,
+    tags: &[String],
+) -> AppResult<()> {
+    sqlx::query("DELETE FROM task_tags WHERE task_id = ?")
+        .bind(task_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(AppError::DatabaseError)?;
+
+    for tag in tags {
+        sqlx::query("INSERT OR IGNORE INTO task_tags (task_id, tag) VALUES (?, ?)")
+            .bind(task_id)
+            .bind(tag)
+            .execute(&mut *conn)
+            .await
+            .map_err(AppError::DatabaseError)?;
+    }
+
+    Ok(())
+}
+
+// Remove the tag index entries and logs of deleted tasks
+async fn delete_task_children(conn: &mut SqliteConnection, ids: &[String]) -> AppResult<()> {
+    for chunk in ids.chunks(INSERT_CHUNK_SIZE) {
+        for table in ["task_tags", "task_logs"] {
+            let mut builder = QueryBuilder::<Sqlite>::new(
+                format!("DELETE FROM {} WHERE task_id IN (", table)
+            );
+            let mut separated = builder.separated(", ");
+            for id in chunk {
+                separated.push_bind(id.clone());
+            }
+            builder.push(")");
+            builder
+                .build()
+                .execute(&mut *conn)
+                .await
+                .map_err(AppError::DatabaseError)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Tables created before migrations existed may lack columns the baseline
+// migration expects; SQLite can't add them conditionally in plain SQL
+async fn add_legacy_columns(conn: &mut SqliteConnection) -> AppResult<()> {
+    let exists: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'tasks'"
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(AppError::DatabaseError)?;
+
+    if exists == 0 {
+        return Ok(());
+    }
+
+    for column in ["trace_id", "progress", "checkpoint"] {
+        let exists: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM pragma_table_info('tasks') WHERE name = ?"
+        )
+        .bind(column)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(AppError::DatabaseError)?;
+
+        if exists == 0 {
+            sqlx::query(&format!("ALTER TABLE tasks ADD COLUMN {} TEXT", column))
+                .execute(&mut *conn)
+                .await
+                .map_err(AppError::DatabaseError)?;
+        }
+    }
+
+    Ok(())
 }
 
 #[async_trait]
 impl Database for SqliteDatabase {
     async fn create_task(&self, task: &Task) -> AppResult<()> {
         let tags = serde_json::to_string(&task.tags).unwrap_or_else(|_| "[]".to_string());
+        let mut tx = self.pool.begin().await.map_err(AppError::DatabaseError)?;
         
         sqlx::query(
             r#"
@@ -40,20 +337,23 @@ impl Database for SqliteDatabase {
                 created_at, updated_at, scheduled_at,
                 started_at, completed_at, attempts,
                 max_attempts, last_error, worker_id,
-                result, tags
+                result, tags, trace_id,
+                progress, checkpoint, tenant,
+                expires_at, timeout_seconds, deadline
             ) VALUES (
                 ?, ?, ?, ?, ?,
                 ?, ?, ?, ?, ?,
                 ?, ?, ?, ?, ?,
-                ?
+                ?, ?, ?, ?, ?,
+                ?, ?, ?
             )
             "#
         )
         .bind(&task.id)
         .bind(&task.name)
-        .bind(&task.payload.to_string())
-        .bind(&task.state.to_string())
-        .bind(&task.priority.to_string())
+        .bind(task.payload.to_string())
+        .bind(task.state.to_string())
+        .bind(task.priority.to_string())
         .bind(task.created_at.timestamp())
         .bind(task.updated_at.timestamp())
         .bind(task.scheduled_at.map(|dt| dt.timestamp()))
@@ -65,112 +365,112 @@ impl Database for SqliteDatabase {
         .bind(&task.worker_id)
         .bind(task.result.as_ref().map(|r| r.to_string()))
         .bind(&tags)
-        .execute(&self.pool)
+        .bind(&task.trace_id)
+        .bind(task.progress.as_ref().map(progress_to_json))
+        .bind(task.checkpoint.as_ref().map(|c| c.to_string()))
+        .bind(&task.tenant)
+        .bind(task.expires_at.map(|t| t.timestamp()))
+        .bind(task.timeout_seconds.map(|t| t as i64))
+        .bind(task.deadline.map(|t| t.timestamp()))
+        .execute(&mut *tx)
         .await
-        .map_err(|e| AppError::DatabaseError(e))?;
+        .map_err(AppError::DatabaseError)?;
+
+        replace_task_tags(&mut tx, &task.id, &task.tags).await?;
+        tx.commit().await.map_err(AppError::DatabaseError)?;
+
+        Ok(())
+    }
+
+    async fn create_tasks(&self, tasks: &[Task]) -> AppResult<()> {
+        let mut tx = self.pool.begin().await.map_err(AppError::DatabaseError)?;
+
+        for chunk in tasks.chunks(INSERT_CHUNK_SIZE) {
+            let mut builder = QueryBuilder::<Sqlite>::new(
+                r#"
+                INSERT INTO tasks (
+                    id, name, payload, state, priority,
+                    created_at, updated_at, scheduled_at,
+                    started_at, completed_at, attempts,
+                    max_attempts, last_error, worker_id,
+                    result, tags, trace_id,
+                    progress, checkpoint, tenant,
+                    expires_at, timeout_seconds, deadline
+                )
+                "#
+            );
+            builder.push_values(chunk, |mut row, task| {
+                row.push_bind(task.id.clone())
+                    .push_bind(task.name.clone())
+                    .push_bind(task.payload.to_string())
+                    .push_bind(task.state.to_string())
+                    .push_bind(task.priority.to_string())
+                    .push_bind(task.created_at.timestamp())
+                    .push_bind(task.updated_at.timestamp())
+                    .push_bind(task.scheduled_at.map(|dt| dt.timestamp()))
+                    .push_bind(task.started_at.map(|dt| dt.timestamp()))
+                    .push_bind(task.completed_at.map(|dt| dt.timestamp()))
+                    .push_bind(task.attempts as i32)
+                    .push_bind(task.max_attempts as i32)
+                    .push_bind(task.last_error.clone())
+                    .push_bind(task.worker_id.clone())
+                    .push_bind(task.result.as_ref().map(|r| r.to_string()))
+                    .push_bind(serde_json::to_string(&task.tags).unwrap_or_else(|_| "[]".to_string()))
+                    .push_bind(task.trace_id.clone())
+                    .push_bind(task.progress.as_ref().map(progress_to_json))
+                    .push_bind(task.checkpoint.as_ref().map(|c| c.to_string()))
+                    .push_bind(task.tenant.clone())
+                    .push_bind(task.expires_at.map(|t| t.timestamp()))
+                    .push_bind(task.timeout_seconds.map(|t| t as i64))
+                    .push_bind(task.deadline.map(|t| t.timestamp()));
+            });
+            builder
+                .build()
+                .execute(&mut *tx)
+                .await
+                .map_err(AppError::DatabaseError)?;
+
+            let tags: Vec<(&str, &str)> = chunk
+                .iter()
+                .flat_map(|task| task.tags.iter().map(move |tag| (task.id.as_str(), tag.as_str())))
+                .collect();
+
+            for tag_chunk in tags.chunks(INSERT_CHUNK_SIZE) {
+                let mut builder = QueryBuilder::<Sqlite>::new(
+                    "INSERT OR IGNORE INTO task_tags (task_id, tag) "
+                );
+                builder.push_values(tag_chunk, |mut row, (task_id, tag)| {
+                    row.push_bind(task_id.to_string()).push_bind(tag.to_string());
+                });
+                builder
+                    .build()
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(AppError::DatabaseError)?;
+            }
+        }
+
+        tx.commit().await.map_err(AppError::DatabaseError)?;
 
         Ok(())
     }
 
     async fn get_task(&self, id: &str) -> AppResult<Task> {
-        let row = sqlx::query(
-            r#"
-            SELECT
-                id, name, payload, state, priority,
-                created_at, updated_at, scheduled_at,
-                started_at, completed_at, attempts,
-                max_attempts, last_error, worker_id,
-                result, tags
-            FROM tasks
-            WHERE id = ?
-            "#
-        )
-        .bind(id)
-        .fetch_one(&self.pool)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => AppError::TaskNotFound(id.to_string()),
-            e => AppError::DatabaseError(e),
-        })?;
-
-        let id: String = row.try_get("id")?;
-        let name: String = row.try_get("name")?;
-        let payload_str: String = row.try_get("payload")?;
-        let state_str: String = row.try_get("state")?;
-        let priority_str: String = row.try_get("priority")?;
-        let created_at: i64 = row.try_get("created_at")?;
-        let updated_at: i64 = row.try_get("updated_at")?;
-        let scheduled_at: Option<i64> = row.try_get("scheduled_at")?;
-        let started_at: Option<i64> = row.try_get("started_at")?;
-        let completed_at: Option<i64> = row.try_get("completed_at")?;
-        let attempts: i32 = row.try_get("attempts")?;
-        let max_attempts: i32 = row.try_get("max_attempts")?;
-        let last_error: Option<String> = row.try_get("last_error")?;
-        let worker_id: Option<String> = row.try_get("worker_id")?;
-        let result_str: Option<String> = row.try_get("result")?;
-        let tags_str: Option<String> = row.try_get("tags")?;
-
-        let payload: serde_json::Value = serde_json::from_str(&payload_str)
-            .map_err(|e| AppError::SerializationError(e))?;
-
-        let result = match result_str {
-            Some(r) => Some(serde_json::from_str(&r).map_err(|e| AppError::SerializationError(e))?),
-            None => None,
-        };
-
-        let tags: Vec<String> = match tags_str {
-            Some(t) => serde_json::from_str(&t).unwrap_or_else(|_| Vec::new()),
-            None => Vec::new(),
-        };
-
-        let state = match state_str.as_str() {
-            "pending" => TaskState::Pending,
-            "scheduled" => TaskState::Scheduled,
-            "running" => TaskState::Running,
-            "completed" => TaskState::Completed,
-            "failed" => TaskState::Failed,
-            "cancelled" => TaskState::Cancelled,
-            _ => TaskState::Pending,
-        };
-
-        let priority = match priority_str.as_str() {
-            "low" => TaskPriority::Low,
-            "medium" => TaskPriority::Medium,
-            "high" => TaskPriority::High,
-            "critical" => TaskPriority::Critical,
-            _ => TaskPriority::Medium,
-        };
-
-        Ok(Task {
-            id,
-            name,
-            payload,
-            state,
-            priority,
-            created_at: DateTime::from_timestamp(created_at, 0)
-                .unwrap_or_else(|| Utc::now()),
-            updated_at: DateTime::from_timestamp(updated_at, 0)
-                .unwrap_or_else(|| Utc::now()),
-            scheduled_at: scheduled_at.map(|ts| 
-                DateTime::from_timestamp(ts, 0).unwrap_or_else(|| Utc::now())
-            ),
-            started_at: started_at.map(|ts| 
-                DateTime::from_timestamp(ts, 0).unwrap_or_else(|| Utc::now())
-            ),
-            completed_at: completed_at.map(|ts| 
-                DateTime::from_timestamp(ts, 0).unwrap_or_else(|| Utc::now())
-            ),
-            attempts: attempts as u32,
-            max_attempts: max_attempts as u32,
-            last_error,
-            worker_id,
-            result,
-            tags,
-        })
+        let row = sqlx::query(&format!("SELECT {} FROM tasks WHERE id = ?", TASK_COLUMNS))
+            .bind(id)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => AppError::TaskNotFound(id.to_string()),
+                e => AppError::DatabaseError(e),
+            })?;
+
+        row_to_task(&row)
     }
     
     async fn update_task(&self, task: &Task) -> AppResult<()> {
         let tags = serde_json::to_string(&task.tags).unwrap_or_else(|_| "[]".to_string());
+        let mut tx = self.pool.begin().await.map_err(AppError::DatabaseError)?;
         
         sqlx::query(
             r#"
@@ -188,14 +488,16 @@ impl Database for SqliteDatabase {
                 last_error = ?,
                 worker_id = ?,
                 result = ?,
-                tags = ?
+                tags = ?,
+                progress = ?,
+                checkpoint = ?
             WHERE id = ?
             "#
         )
         .bind(&task.name)
-        .bind(&task.payload.to_string())
-        .bind(&task.state.to_string())
-        .bind(&task.priority.to_string())
+        .bind(task.payload.to_string())
+        .bind(task.state.to_string())
+        .bind(task.priority.to_string())
         .bind(task.updated_at.timestamp())
         .bind(task.scheduled_at.map(|dt| dt.timestamp()))
         .bind(task.started_at.map(|dt| dt.timestamp()))
@@ -206,320 +508,474 @@ impl Database for SqliteDatabase {
         .bind(&task.worker_id)
         .bind(task.result.as_ref().map(|r| r.to_string()))
         .bind(&tags)
+        .bind(task.progress.as_ref().map(progress_to_json))
+        .bind(task.checkpoint.as_ref().map(|c| c.to_string()))
         .bind(&task.id)
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await
-        .map_err(|e| AppError::DatabaseError(e))?;
+        .map_err(AppError::DatabaseError)?;
+
+        replace_task_tags(&mut tx, &task.id, &task.tags).await?;
+        tx.commit().await.map_err(AppError::DatabaseError)?;
 
         Ok(())
     }
 
+    async fn update_task_if_state(&self, task: &Task, expected: &TaskState) -> AppResult<bool> {
+        let mut tx = self.pool.begin().await.map_err(AppError::DatabaseError)?;
+
+        let result = sqlx::query(
+            r#"
+            UPDATE tasks SET
+                name = ?,
+                payload = ?,
+                state = ?,
+                priority = ?,
+                updated_at = ?,
+                scheduled_at = ?,
+                started_at = ?,
+                completed_at = ?,
+                attempts = ?,
+                max_attempts = ?,
+                last_error = ?,
+                worker_id = ?,
+                result = ?,
+                tags = ?,
+                progress = ?,
+                checkpoint = ?
+            WHERE id = ? AND state = ?
+            "#
+        )
+        .bind(&task.name)
+        .bind(task.payload.to_string())
+        .bind(task.state.to_string())
+        .bind(task.priority.to_string())
+        .bind(task.updated_at.timestamp())
+        .bind(task.scheduled_at.map(|dt| dt.timestamp()))
+        .bind(task.started_at.map(|dt| dt.timestamp()))
+        .bind(task.completed_at.map(|dt| dt.timestamp()))
+        .bind(task.attempts as i32)
+        .bind(task.max_attempts as i32)
+        .bind(&task.last_error)
+        .bind(&task.worker_id)
+        .bind(task.result.as_ref().map(|r| r.to_string()))
+        .bind(serde_json::to_string(&task.tags).unwrap_or_else(|_| "[]".to_string()))
+        .bind(task.progress.as_ref().map(progress_to_json))
+        .bind(task.checkpoint.as_ref().map(|c| c.to_string()))
+        .bind(&task.id)
+        .bind(expected.to_string())
+        .execute(&mut *tx)
+        .await
+        .map_err(AppError::DatabaseError)?;
+
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        replace_task_tags(&mut tx, &task.id, &task.tags).await?;
+        tx.commit().await.map_err(AppError::DatabaseError)?;
+
+        Ok(true)
+    }
+
     async fn delete_task(&self, id: &str) -> AppResult<()> {
+        let mut tx = self.pool.begin().await.map_err(AppError::DatabaseError)?;
+
+        sqlx::query("DELETE FROM task_tags WHERE task_id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await
+            .map_err(AppError::DatabaseError)?;
+
+        sqlx::query("DELETE FROM task_logs WHERE task_id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await
+            .map_err(AppError::DatabaseError)?;
+
         sqlx::query("DELETE FROM tasks WHERE id = ?")
             .bind(id)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await
-            .map_err(|e| AppError::DatabaseError(e))?;
+            .map_err(AppError::DatabaseError)?;
+
+        tx.commit().await.map_err(AppError::DatabaseError)?;
 
         Ok(())
     }
 
     async fn get_tasks(
         &self,
-        state: Option<&str>,
-        priority: Option<&str>,
+        filter: &TaskFilter,
+        after: Option<&TaskCursor>,
         limit: Option<u32>,
-        offset: Option<u32>,
     ) -> AppResult<Vec<Task>> {
-        let mut query = "SELECT * FROM tasks".to_string();
-        let mut conditions = Vec::new();
+        let mut builder = QueryBuilder::<Sqlite>::new(
+            format!("SELECT {} FROM tasks WHERE 1 = 1", TASK_COLUMNS)
+        );
+        push_filter(&mut builder, filter);
 
-        if let Some(state) = state {
-            conditions.push(format!("state = '{}'", state));
+        if let Some(cursor) = after {
+            let created_at = cursor.created_at.timestamp();
+            builder
+                .push(" AND (created_at < ")
+                .push_bind(created_at)
+                .push(" OR (created_at = ")
+                .push_bind(created_at)
+                .push(" AND id < ")
+                .push_bind(cursor.id.clone())
+                .push("))");
         }
 
-        if let Some(priority) = priority {
-            conditions.push(format!("priority = '{}'", priority));
-        }
+        builder.push(" ORDER BY created_at DESC, id DESC");
 
-        if !conditions.is_empty() {
-            query.push_str(" WHERE ");
-            query.push_str(&conditions.join(" AND "));
+        if let Some(limit) = limit {
+            builder.push(" LIMIT ").push_bind(limit as i64);
         }
 
-        query.push_str(" ORDER BY created_at DESC");
+        let rows = builder
+            .build()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(AppError::DatabaseError)?;
+
+        rows.iter().map(row_to_task).collect()
+    }
 
-        if let Some(limit) = limit {
-            query.push_str(&format!(" LIMIT {}", limit));
-        }
+    async fn count_tasks(&self, filter: &TaskFilter) -> AppResult<i64> {
+        let mut builder = QueryBuilder::<Sqlite>::new("SELECT COUNT(*) FROM tasks WHERE 1 = 1");
+        push_filter(&mut builder, filter);
 
-        if let Some(offset) = offset {
-            query.push_str(&format!(" OFFSET {}", offset));
-        }
+        let count: i64 = builder
+            .build_query_scalar()
+            .fetch_one(&self.pool)
+            .await
+            .map_err(AppError::DatabaseError)?;
+
+        Ok(count)
+    }
+
+    async fn claim_task(&self, id: &str, worker_id: &str) -> AppResult<Option<Task>> {
+        let now = Utc::now().timestamp();
+
+        // Read every row, so the update runs to completion and commits before
+        // another connection looks
+        let rows = sqlx::query(&format!(
+            r#"
+            UPDATE tasks SET
+                state = 'running',
+                worker_id = ?,
+                started_at = ?,
+                updated_at = ?,
+                progress = NULL
+            WHERE id = ?
+                AND (state = 'pending'
+                    OR (state = 'scheduled' AND scheduled_at <= ?))
+            RETURNING {}
+            "#,
+            TASK_COLUMNS
+        ))
+        .bind(worker_id)
+        .bind(now)
+        .bind(now)
+        .bind(id)
+        .bind(now)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(AppError::DatabaseError)?;
+
+        rows.first().map(row_to_task).transpose()
+    }
 
-        let rows = sqlx::query(&query)
+    async fn cancel_tasks(&self, filter: &TaskFilter) -> AppResult<Vec<Task>> {
+        let mut builder = QueryBuilder::<Sqlite>::new("UPDATE tasks SET state = 'cancelled', updated_at = ");
+        builder
+            .push_bind(Utc::now().timestamp())
+            .push(" WHERE state IN ('pending', 'scheduled')");
+        push_filter(&mut builder, filter);
+        builder.push(format!(" RETURNING {}", TASK_COLUMNS));
+
+        let rows = builder
+            .build()
             .fetch_all(&self.pool)
             .await
-            .map_err(|e| AppError::DatabaseError(e))?;
+            .map_err(AppError::DatabaseError)?;
 
-        let mut tasks = Vec::new();
-        for row in rows {
-            let id: String = row.try_get("id")?;
-            let name: String = row.try_get("name")?;
-            let payload_str: String = row.try_get("payload")?;
-            let state_str: String = row.try_get("state")?;
-            let priority_str: String = row.try_get("priority")?;
-            let created_at: i64 = row.try_get("created_at")?;
-            let updated_at: i64 = row.try_get("updated_at")?;
-            let scheduled_at: Option<i64> = row.try_get("scheduled_at")?;
-            let started_at: Option<i64> = row.try_get("started_at")?;
-            let completed_at: Option<i64> = row.try_get("completed_at")?;
-            let attempts: i32 = row.try_get("attempts")?;
-            let max_attempts: i32 = row.try_get("max_attempts")?;
-            let last_error: Option<String> = row.try_get("last_error")?;
-            let worker_id: Option<String> = row.try_get("worker_id")?;
-            let result_str: Option<String> = row.try_get("result")?;
-            let tags_str: Option<String> = row.try_get("tags")?;
-
-            let payload: serde_json::Value = serde_json::from_str(&payload_str)
-                .unwrap_or_else(|_| serde_json::Value::Null);
-
-            let result = match result_str {
-                Some(r) => Some(serde_json::from_str(&r).unwrap_or_else(|_| serde_json::Value::Null)),
-                None => None,
-            };
-
-            let tags: Vec<String> = match tags_str {
-                Some(t) => serde_json::from_str(&t).unwrap_or_else(|_| Vec::new()),
-                None => Vec::new(),
-            };
-
-            let state = match state_str.as_str() {
-                "pending" => TaskState::Pending,
-                "scheduled" => TaskState::Scheduled,
-                "running" => TaskState::Running,
-                "completed" => TaskState::Completed,
-                "failed" => TaskState::Failed,
-                "cancelled" => TaskState::Cancelled,
-                _ => TaskState::Pending,
-            };
-
-            let priority = match priority_str.as_str() {
-                "low" => TaskPriority::Low,
-                "medium" => TaskPriority::Medium,
-                "high" => TaskPriority::High,
-                "critical" => TaskPriority::Critical,
-                _ => TaskPriority::Medium,
-            };
-
-            tasks.push(Task {
-                id,
-                name,
-                payload,
-                state,
-                priority,
-                created_at: DateTime::from_timestamp(created_at, 0)
-                    .unwrap_or_else(|| Utc::now()),
-                updated_at: DateTime::from_timestamp(updated_at, 0)
-                    .unwrap_or_else(|| Utc::now()),
-                scheduled_at: scheduled_at.map(|ts| 
-                    DateTime::from_timestamp(ts, 0).unwrap_or_else(|| Utc::now())
-                ),
-                started_at: started_at.map(|ts| 
-                    DateTime::from_timestamp(ts, 0).unwrap_or_else(|| Utc::now())
-                ),
-                completed_at: completed_at.map(|ts| 
-                    DateTime::from_timestamp(ts, 0).unwrap_or_else(|| Utc::now())
-                ),
-                attempts: attempts as u32,
-                max_attempts: max_attempts as u32,
-                last_error,
-                worker_id,
-                result,
-                tags,
-            });
+        rows.iter().map(row_to_task).collect()
+    }
+
+    async fn expire_tasks(&self, filter: &TaskFilter, now: DateTime<Utc>) -> AppResult<Vec<Task>> {
+        let mut builder = QueryBuilder::<Sqlite>::new("UPDATE tasks SET state = 'expired', updated_at = ");
+        builder
+            .push_bind(now.timestamp())
+            .push(
+                " WHERE (state IN ('pending', 'scheduled') \
+                 OR (state = 'failed' AND attempts < max_attempts)) AND expires_at <= ",
+            )
+            .push_bind(now.timestamp());
+        push_filter(&mut builder, filter);
+        builder.push(format!(" RETURNING {}", TASK_COLUMNS));
+
+        let rows = builder
+            .build()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(AppError::DatabaseError)?;
+
+        rows.iter().map(row_to_task).collect()
+    }
+
+    async fn reprioritize_tasks(
+        &self,
+        filter: &TaskFilter,
+        priority: &TaskPriority,
+    ) -> AppResult<Vec<String>> {
+        let mut builder = QueryBuilder::<Sqlite>::new("UPDATE tasks SET priority = ");
+        builder
+            .push_bind(priority.to_string())
+            .push(", updated_at = ")
+            .push_bind(Utc::now().timestamp())
+            .push(" WHERE state IN ('pending', 'scheduled', 'failed')");
+        push_filter(&mut builder, filter);
+        builder.push(" RETURNING id");
+
+        let ids: Vec<String> = builder
+            .build_query_scalar()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(AppError::DatabaseError)?;
+
+        Ok(ids)
+    }
+
+    async fn retry_tasks(&self, filter: &TaskFilter, reset_attempts: bool) -> AppResult<Vec<Task>> {
+        let mut builder = QueryBuilder::<Sqlite>::new(
+            "UPDATE tasks SET state = 'pending', worker_id = NULL, started_at = NULL, completed_at = NULL, updated_at = "
+        );
+        builder.push_bind(Utc::now().timestamp());
+        if reset_attempts {
+            builder.push(", attempts = 0");
         }
+        builder.push(" WHERE state IN ('failed', 'cancelled')");
+        push_filter(&mut builder, filter);
+        builder.push(format!(" RETURNING {}", TASK_COLUMNS));
 
-        Ok(tasks)
+        let rows = builder
+            .build()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(AppError::DatabaseError)?;
+
+        rows.iter().map(row_to_task).collect()
     }
 
-    async fn get_scheduled_tasks(&self, before: DateTime<Utc>) -> AppResult<Vec<Task>> {
-        let before_timestamp = before.timestamp();
-        
+    async fn delete_tasks(&self, filter: &TaskFilter) -> AppResult<Vec<String>> {
+        let mut tx = self.pool.begin().await.map_err(AppError::DatabaseError)?;
+
+        let mut builder = QueryBuilder::<Sqlite>::new("DELETE FROM tasks WHERE state != 'running'");
+        push_filter(&mut builder, filter);
+        builder.push(" RETURNING id");
+
+        let ids: Vec<String> = builder
+            .build_query_scalar()
+            .fetch_all(&mut *tx)
+            .await
+            .map_err(AppError::DatabaseError)?;
+
+        delete_task_children(&mut tx, &ids).await?;
+        tx.commit().await.map_err(AppError::DatabaseError)?;
+
+        Ok(ids)
+    }
+
+    async fn find_purgeable_tasks(
+        &self,
+        filter: &TaskFilter,
+        exclude: &[TaskFilter],
+        limit: u32,
+    ) -> AppResult<Vec<Task>> {
+        let mut builder = QueryBuilder::<Sqlite>::new(format!("SELECT {} FROM tasks", TASK_COLUMNS));
+        push_purge_conditions(&mut builder, filter, exclude);
+        builder.push(" ORDER BY updated_at, id LIMIT ").push_bind(limit as i64);
+
+        let rows = builder
+            .build()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(AppError::DatabaseError)?;
+
+        rows.iter().map(row_to_task).collect()
+    }
+
+    async fn purge_tasks(
+        &self,
+        filter: &TaskFilter,
+        exclude: &[TaskFilter],
+        limit: u32,
+    ) -> AppResult<Vec<String>> {
+        let mut tx = self.pool.begin().await.map_err(AppError::DatabaseError)?;
+
+        let mut builder = QueryBuilder::<Sqlite>::new("DELETE FROM tasks WHERE id IN (SELECT id FROM tasks");
+        push_purge_conditions(&mut builder, filter, exclude);
+        builder.push(" ORDER BY updated_at, id LIMIT ").push_bind(limit as i64).push(") RETURNING id");
+
+        let ids: Vec<String> = builder
+            .build_query_scalar()
+            .fetch_all(&mut *tx)
+            .await
+            .map_err(AppError::DatabaseError)?;
+
+        delete_task_children(&mut tx, &ids).await?;
+        tx.commit().await.map_err(AppError::DatabaseError)?;
+
+        Ok(ids)
+    }
+
+    async fn update_task_progress(
+        &self,
+        id: &str,
+        progress: &TaskProgress,
+        checkpoint: Option<&serde_json::Value>,
+    ) -> AppResult<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE tasks SET
+                progress = ?,
+                checkpoint = COALESCE(?, checkpoint),
+                updated_at = ?
+            WHERE id = ? AND state = 'running'
+            "#
+        )
+        .bind(progress_to_json(progress))
+        .bind(checkpoint.map(|c| c.to_string()))
+        .bind(progress.updated_at.timestamp())
+        .bind(id)
+        .execute(&self.pool)
+        .await
+        .map_err(AppError::DatabaseError)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn append_task_log(
+        &self,
+        task_id: &str,
+        attempt: u32,
+        level: LogLevel,
+        message: &str,
+        logged_at: DateTime<Utc>,
+    ) -> AppResult<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO task_logs (task_id, attempt, level, message, logged_at)
+            VALUES (?, ?, ?, ?, ?)
+            "#
+        )
+        .bind(task_id)
+        .bind(attempt as i32)
+        .bind(level.to_string())
+        .bind(message)
+        .bind(logged_at.timestamp())
+        .execute(&self.pool)
+        .await
+        .map_err(AppError::DatabaseError)?;
+
+        Ok(())
+    }
+
+    async fn get_task_logs(
+        &self,
+        task_id: &str,
+        after_seq: Option<i64>,
+        limit: u32,
+    ) -> AppResult<Vec<TaskLogEntry>> {
         let rows = sqlx::query(
             r#"
-            SELECT
-                id, name, payload, state, priority,
-                created_at, updated_at, scheduled_at,
-                started_at, completed_at, attempts,
-                max_attempts, last_error, worker_id,
-                result, tags
+            SELECT seq, task_id, attempt, level, message, logged_at
+            FROM task_logs
+            WHERE task_id = ? AND seq > ?
+            ORDER BY seq
+            LIMIT ?
+            "#
+        )
+        .bind(task_id)
+        .bind(after_seq.unwrap_or(0))
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(AppError::DatabaseError)?;
+
+        rows.iter().map(row_to_log_entry).collect()
+    }
+
+    async fn get_scheduled_tasks(&self, before: DateTime<Utc>) -> AppResult<Vec<Task>> {
+        let rows = sqlx::query(&format!(
+            r#"
+            SELECT {}
             FROM tasks
             WHERE state = 'scheduled' AND scheduled_at <= ?
             ORDER BY priority DESC, scheduled_at ASC
-            "#
-        )
-        .bind(before_timestamp)
+            "#,
+            TASK_COLUMNS
+        ))
+        .bind(before.timestamp())
         .fetch_all(&self.pool)
         .await
-        .map_err(|e| AppError::DatabaseError(e))?;
+        .map_err(AppError::DatabaseError)?;
 
-        let mut tasks = Vec::new();
-        for row in rows {
-            let id: String = row.try_get("id")?;
-            let name: String = row.try_get("name")?;
-            let payload_str: String = row.try_get("payload")?;
-            let state_str: String = row.try_get("state")?;
-            let priority_str: String = row.try_get("priority")?;
-            let created_at: i64 = row.try_get("created_at")?;
-            let updated_at: i64 = row.try_get("updated_at")?;
-            let scheduled_at: Option<i64> = row.try_get("scheduled_at")?;
-            let started_at: Option<i64> = row.try_get("started_at")?;
-            let completed_at: Option<i64> = row.try_get("completed_at")?;
-            let attempts: i32 = row.try_get("attempts")?;
-            let max_attempts: i32 = row.try_get("max_attempts")?;
-            let last_error: Option<String> = row.try_get("last_error")?;
-            let worker_id: Option<String> = row.try_get("worker_id")?;
-            let result_str: Option<String> = row.try_get("result")?;
-            let tags_str: Option<String> = row.try_get("tags")?;
-
-            let payload: serde_json::Value = serde_json::from_str(&payload_str)
-                .unwrap_or_else(|_| serde_json::Value::Null);
-
-            let result = match result_str {
-                Some(r) => Some(serde_json::from_str(&r).unwrap_or_else(|_| serde_json::Value::Null)),
-                None => None,
-            };
-
-            let tags: Vec<String> = match tags_str {
-                Some(t) => serde_json::from_str(&t).unwrap_or_else(|_| Vec::new()),
-                None => Vec::new(),
-            };
-
-            let state = TaskState::Scheduled;
-            let priority = match priority_str.as_str() {
-                "low" => TaskPriority::Low,
-                "medium" => TaskPriority::Medium,
-                "high" => TaskPriority::High,
-                "critical" => TaskPriority::Critical,
-                _ => TaskPriority::Medium,
-            };
-
-            tasks.push(Task {
-                id,
-                name,
-                payload,
-                state,
-                priority,
-                created_at: DateTime::from_timestamp(created_at, 0)
-                    .unwrap_or_else(|| Utc::now()),
-                updated_at: DateTime::from_timestamp(updated_at, 0)
-                    .unwrap_or_else(|| Utc::now()),
-                scheduled_at: scheduled_at.map(|ts| 
-                    DateTime::from_timestamp(ts, 0).unwrap_or_else(|| Utc::now())
-                ),
-                started_at: started_at.map(|ts| 
-                    DateTime::from_timestamp(ts, 0).unwrap_or_else(|| Utc::now())
-                ),
-                completed_at: completed_at.map(|ts| 
-                    DateTime::from_timestamp(ts, 0).unwrap_or_else(|| Utc::now())
-                ),
-                attempts: attempts as u32,
-                max_attempts: max_attempts as u32,
-                last_error,
-                worker_id,
-                result,
-                tags,
-            });
+        rows.iter().map(row_to_task).collect()
+    }
+
+    async fn get_ready_tasks(
+        &self,
+        from: Option<QueuePosition>,
+        now: DateTime<Utc>,
+        limit: u32,
+    ) -> AppResult<Vec<Task>> {
+        let mut builder = QueryBuilder::<Sqlite>::new(format!(
+            "SELECT {} FROM tasks WHERE state IN ('pending', 'scheduled') \
+             AND (state = 'pending' OR scheduled_at <= ",
+            TASK_COLUMNS
+        ));
+        builder.push_bind(now.timestamp()).push(")");
+
+        if let Some(from) = from {
+            builder
+                .push(format!(" AND ({}, created_at) >= (", PRIORITY_RANK))
+                .push_bind(from.priority_rank as i32)
+                .push(", ")
+                .push_bind(from.created_second)
+                .push(")");
         }
 
-        Ok(tasks)
-    }
+        builder
+            .push(format!(" ORDER BY {}, created_at, id LIMIT ", PRIORITY_RANK))
+            .push_bind(limit as i64);
 
+        let rows = builder
+            .build()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(AppError::DatabaseError)?;
+
+        rows.iter().map(row_to_task).collect()
+    }
+    
     async fn get_failed_tasks_for_retry(&self) -> AppResult<Vec<Task>> {
-        let rows = sqlx::query(
+        let rows = sqlx::query(&format!(
             r#"
-            SELECT
-                id, name, payload, state, priority,
-                created_at, updated_at, scheduled_at,
-                started_at, completed_at, attempts,
-                max_attempts, last_error, worker_id,
-                result, tags
+            SELECT {}
             FROM tasks
             WHERE state = 'failed' AND attempts < max_attempts
             ORDER BY priority DESC, updated_at ASC
-            "#
-        )
+            "#,
+            TASK_COLUMNS
+        ))
         .fetch_all(&self.pool)
         .await
-        .map_err(|e| AppError::DatabaseError(e))?;
-
-        let mut tasks = Vec::new();
-        for row in rows {
-            let id: String = row.try_get("id")?;
-            let name: String = row.try_get("name")?;
-            let payload_str: String = row.try_get("payload")?;
-            let priority_str: String = row.try_get("priority")?;
-            let created_at: i64 = row.try_get("created_at")?;
-            let updated_at: i64 = row.try_get("updated_at")?;
-            let scheduled_at: Option<i64> = row.try_get("scheduled_at")?;
-            let started_at: Option<i64> = row.try_get("started_at")?;
-            let completed_at: Option<i64> = row.try_get("completed_at")?;
-            let attempts: i32 = row.try_get("attempts")?;
-            let max_attempts: i32 = row.try_get("max_attempts")?;
-            let last_error: Option<String> = row.try_get("last_error")?;
-            let worker_id: Option<String> = row.try_get("worker_id")?;
-            let result_str: Option<String> = row.try_get("result")?;
-            let tags_str: Option<String> = row.try_get("tags")?;
-
-            let payload: serde_json::Value = serde_json::from_str(&payload_str)
-                .unwrap_or_else(|_| serde_json::Value::Null);
-
-            let result = match result_str {
-                Some(r) => Some(serde_json::from_str(&r).unwrap_or_else(|_| serde_json::Value::Null)),
-                None => None,
-            };
-
-            let tags: Vec<String> = match tags_str {
-                Some(t) => serde_json::from_str(&t).unwrap_or_else(|_| Vec::new()),
-                None => Vec::new(),
-            };
-
-            tasks.push(Task {
-                id,
-                name,
-                payload,
-                state: TaskState::Failed,
-                priority: match priority_str.as_str() {
-                    "low" => TaskPriority::Low,
-                    "medium" => TaskPriority::Medium,
-                    "high" => TaskPriority::High,
-                    "critical" => TaskPriority::Critical,
-                    _ => TaskPriority::Medium,
-                },
-                created_at: DateTime::from_timestamp(created_at, 0)
-                    .unwrap_or_else(|| Utc::now()),
-                updated_at: DateTime::from_timestamp(updated_at, 0)
-                    .unwrap_or_else(|| Utc::now()),
-                scheduled_at: scheduled_at.map(|ts| 
-                    DateTime::from_timestamp(ts, 0).unwrap_or_else(|| Utc::now())
-                ),
-                started_at: started_at.map(|ts| 
-                    DateTime::from_timestamp(ts, 0).unwrap_or_else(|| Utc::now())
-                ),
-                completed_at: completed_at.map(|ts| 
-                    DateTime::from_timestamp(ts, 0).unwrap_or_else(|| Utc::now())
-                ),
-                attempts: attempts as u32,
-                max_attempts: max_attempts as u32,
-                last_error,
-                worker_id,
-                result,
-                tags,
-            });
-        }
+        .map_err(AppError::DatabaseError)?;
 
-        Ok(tasks)
+        rows.iter().map(row_to_task).collect()
     }
 
     async fn count_tasks_by_state(&self) -> AppResult<Vec<(String, i64)>> {
@@ -532,7 +988,7 @@ impl Database for SqliteDatabase {
         )
         .fetch_all(&self.pool)
         .await
-        .map_err(|e| AppError::DatabaseError(e))?;
+        .map_err(AppError::DatabaseError)?;
 
         let mut counts = Vec::new();
         for row in rows {
@@ -554,7 +1010,7 @@ impl Database for SqliteDatabase {
         )
         .fetch_all(&self.pool)
         .await
-        .map_err(|e| AppError::DatabaseError(e))?;
+        .map_err(AppError::DatabaseError)?;
 
         let mut counts = Vec::new();
         for row in rows {
@@ -566,66 +1022,177 @@ impl Database for SqliteDatabase {
         Ok(counts)
     }
 
-    async fn setup(&self) -> AppResult<()> {
-        info!("Setting up SQLite database...");
-        
-        // Create main table
-        sqlx::query(
+    async fn count_tasks_by_group(&self) -> AppResult<Vec<TaskGroupCount>> {
+        let rows = sqlx::query(
             r#"
-            CREATE TABLE IF NOT EXISTS tasks (
-                id TEXT PRIMARY KEY,
-                name TEXT NOT NULL,
-                payload TEXT NOT NULL,
-                state TEXT NOT NULL,
-                priority TEXT NOT NULL,
-                created_at INTEGER NOT NULL,
-                updated_at INTEGER NOT NULL,
-                scheduled_at INTEGER,
-                started_at INTEGER,
-                completed_at INTEGER,
-                attempts INTEGER NOT NULL DEFAULT 0,
-                max_attempts INTEGER NOT NULL DEFAULT 3,
-                last_error TEXT,
-                worker_id TEXT,
-                result TEXT,
-                tags TEXT
+            SELECT state, priority, name, COUNT(*) AS count
+            FROM tasks
+            GROUP BY state, priority, name
+            ORDER BY state, priority, name
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(AppError::DatabaseError)?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(TaskGroupCount {
+                    state: row.try_get("state")?,
+                    priority: row.try_get("priority")?,
+                    name: row.try_get("name")?,
+                    count: row.try_get("count")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn task_timing_stats(&self, since: DateTime<Utc>) -> AppResult<TimingStats> {
+        let since = since.timestamp();
+
+        let wait = self
+            .duration_percentiles(
+                r#"
+                SELECT MAX(started_at - MAX(created_at, COALESCE(scheduled_at, created_at)), 0) AS v
+                FROM tasks
+                WHERE started_at >= ?
+                "#,
+                since,
             )
+            .await?;
+
+        let run = self
+            .duration_percentiles(
+                r#"
+                SELECT completed_at - started_at AS v
+                FROM tasks
+                WHERE state = 'completed' AND completed_at >= ? AND started_at IS NOT NULL
+                "#,
+                since,
+            )
+            .await?;
+
+        Ok(TimingStats { wait, run })
+    }
+
+    async fn count_tasks_by_tag(&self) -> AppResult<Vec<TagStateCount>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT task_tags.tag AS tag, tasks.state AS state, COUNT(*) as count
+            FROM task_tags
+            JOIN tasks ON tasks.id = task_tags.task_id
+            GROUP BY task_tags.tag, tasks.state
+            ORDER BY task_tags.tag, tasks.state
             "#
         )
-        .execute(&self.pool)
+        .fetch_all(&self.pool)
         .await
-        .map_err(|e| AppError::DatabaseError(e))?;
+        .map_err(AppError::DatabaseError)?;
 
-        // Create indexes - run each separately to avoid issues if one fails
-        sqlx::query(
-            "CREATE INDEX IF NOT EXISTS idx_tasks_state ON tasks (state)"
+        let mut counts = Vec::new();
+        for row in rows {
+            counts.push(TagStateCount {
+                tag: row.try_get("tag")?,
+                state: row.try_get("state")?,
+                count: row.try_get("count")?,
+            });
+        }
+
+        Ok(counts)
+    }
+
+    fn migrations(&self) -> &'static [Migration] {
+        SQLITE_MIGRATIONS
+    }
+
+    async fn applied_migrations(&self) -> AppResult<Vec<AppliedMigration>> {
+        let exists: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'schema_migrations'"
         )
-        .execute(&self.pool)
+        .fetch_one(&self.pool)
         .await
-        .map_err(|e| AppError::DatabaseError(e))?;
+        .map_err(AppError::DatabaseError)?;
 
-        sqlx::query(
-            "CREATE INDEX IF NOT EXISTS idx_tasks_priority ON tasks (priority)"
+        if exists == 0 {
+            return Ok(Vec::new());
+        }
+
+        let rows = sqlx::query(
+            "SELECT version, description, applied_at FROM schema_migrations ORDER BY version"
         )
-        .execute(&self.pool)
+        .fetch_all(&self.pool)
         .await
-        .map_err(|e| AppError::DatabaseError(e))?;
+        .map_err(AppError::DatabaseError)?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| AppliedMigration {
+                version: row.get("version"),
+                description: row.get("description"),
+                applied_at: from_timestamp(row.get("applied_at")),
+            })
+            .collect())
+    }
+
+    async fn apply_migration(&self, migration: &Migration) -> AppResult<bool> {
+        let mut tx = self.pool.begin().await.map_err(AppError::DatabaseError)?;
 
         sqlx::query(
-            "CREATE INDEX IF NOT EXISTS idx_tasks_scheduled_at ON tasks (scheduled_at)"
+            r#"
+            CREATE TABLE IF NOT EXISTS schema_migrations (
+                version INTEGER PRIMARY KEY,
+                description TEXT NOT NULL,
+                applied_at INTEGER NOT NULL
+            )
+            "#
         )
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await
-        .map_err(|e| AppError::DatabaseError(e))?;
+        .map_err(AppError::DatabaseError)?;
+
+        let applied: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM schema_migrations WHERE version = ?"
+        )
+        .bind(migration.version)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::DatabaseError)?;
+
+        if applied > 0 {
+            return Ok(false);
+        }
+
+        if migration.version == 1 {
+            add_legacy_columns(&mut tx).await?;
+        }
+
+        // Plain SQL strings may hold several statements
+        tx.execute(migration.sql)
+            .await
+            .map_err(AppError::DatabaseError)?;
 
         sqlx::query(
-            "CREATE INDEX IF NOT EXISTS idx_tasks_created_at ON tasks (created_at)"
+            "INSERT INTO schema_migrations (version, description, applied_at) VALUES (?, ?, ?)"
         )
-        .execute(&self.pool)
+        .bind(migration.version)
+        .bind(migration.description)
+        .bind(Utc::now().timestamp())
+        .execute(&mut *tx)
         .await
-        .map_err(|e| AppError::DatabaseError(e))?;
+        .map_err(AppError::DatabaseError)?;
+
+        tx.commit().await.map_err(AppError::DatabaseError)?;
+        Ok(true)
+    }
 
+    async fn setup(&self) -> AppResult<()> {
+        info!("Setting up SQLite database...");
+        migrate::migrate(self, false).await?;
         info!("SQLite database setup completed.");
         Ok(())
     }
-}
\ No newline at end of file
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
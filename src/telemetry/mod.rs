@@ -0,0 +1,54 @@
+use crate::config::LoggingConfig;
+use crate::error::{AppError, AppResult};
+use tracing_subscriber::EnvFilter;
+
+/// Install the global tracing subscriber described by the logging config
+pub fn init(config: &LoggingConfig) -> AppResult<()> {
+    // RUST_LOG takes precedence so filters can be tweaked without a config change
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(&config.level))
+        .map_err(|e| AppError::ConfigError(format!("Invalid log level: {}", e)))?;
+
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    let result = match config.format.as_str() {
+        "json" => builder.json().with_current_span(true).with_span_list(true).try_init(),
+        "text" => builder.try_init(),
+        other => {
+            return Err(AppError::ConfigError(format!("Unknown log format: {}", other)));
+        }
+    };
+
+    result.map_err(|e| AppError::ConfigError(format!("Failed to initialize logging: {}", e)))
+}
+
+/// Extract the trace ID from a W3C `traceparent` header value
+pub fn trace_id_from_traceparent(header: &str) -> Option<String> {
+    let mut parts = header.trim().split('-');
+    let _version = parts.next()?;
+    let trace_id = parts.next()?;
+
+    let valid = trace_id.len() == 32
+        && trace_id.bytes().all(|b| b.is_ascii_hexdigit())
+        && trace_id.bytes().any(|b| b != b'0');
+
+    valid.then(|| trace_id.to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_id_from_traceparent() {
+        assert_eq!(
+            trace_id_from_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+            Some("4bf92f3577b34da6a3ce929d0e0e4736".to_string())
+        );
+        assert_eq!(
+            trace_id_from_traceparent("00-00000000000000000000000000000000-00f067aa0ba902b7-01"),
+            None
+        );
+        assert_eq!(trace_id_from_traceparent("garbage"), None);
+    }
+}